@@ -0,0 +1,547 @@
+/*!
+# bawawa
+
+run all the commands of a definition file (see `bawawa::Definitions`)
+or of a `Procfile` (see `bawawa::Procfile`), Procfile style: every line
+printed by a command is prefixed with its name, Ctrl-C terminates all
+the commands and the first command to fail stops the others. The
+commands still running 10 seconds later, or after a second Ctrl-C, are
+killed.
+
+```text
+bawawa <FILE>
+```
+
+the exit code is the one of the first command that failed, 0 if all
+of them succeeded. A command starts once the commands it `depends_on`
+succeeded, it is not started if one of them failed, and it is killed
+after its `timeout`. A command with a `restart` policy is run by a
+`bawawa::Supervisor`, it only fails once the supervisor gives up or does
+not restart it; no command can depend on it.
+
+a file without the `.toml`, `.yaml` or `.yml` extension is read as a
+`Procfile`, with the variables of the `.env` file of the same directory
+if there is one.
+*/
+
+use bawawa::{
+    Command, Control as _, Definitions, Error, Process, Procfile, RestartPolicy, Supervisor,
+    SupervisorEvent,
+};
+use futures::{try_ready, Async, Future, Poll, Stream};
+use std::{
+    io::{self, IsTerminal as _},
+    path::Path,
+    process::{ExitCode, ExitStatus},
+    time::{Duration, Instant},
+};
+use tokio_codec::{FramedRead, LinesCodec};
+use tokio_timer::Delay;
+
+/// how long the output is still read once a process has exited
+const LINGER: Duration = Duration::from_millis(100);
+
+/// how long the commands have to exit once terminated, before they are
+/// killed
+const GRACE: Duration = Duration::from_secs(10);
+
+/// the colors of the prefixes, in turn
+const COLORS: [&str; 6] = ["36", "33", "32", "35", "34", "31"];
+
+type Lines = Box<dyn Stream<Item = String, Error = io::Error> + Send>;
+
+/// a command to run, with its restart policy if any
+struct Entry {
+    name: String,
+    command: Command,
+    timeout: Option<Duration>,
+    restart: Option<RestartPolicy>,
+    /// the indices of the entries to run before, which come before it
+    depends_on: Vec<usize>,
+}
+
+/// a command of the group
+struct Member {
+    prefix: String,
+    state: State,
+    outputs: Vec<Lines>,
+    status: Option<ExitStatus>,
+    /// when the command is killed, once started
+    deadline: Option<Delay>,
+    /// how long the pipes are still read once the process has exited
+    linger: Option<Delay>,
+    /// what happened to the process, printed once its output is closed
+    notes: Vec<String>,
+}
+
+enum State {
+    /// waiting for the commands it depends on
+    Pending(Entry),
+    Process(Process),
+    Supervisor {
+        supervisor: Supervisor,
+        /// the exit status of the last incarnation
+        status: Option<ExitStatus>,
+    },
+    /// never started: a command it depends on failed, or the group
+    /// stopped before
+    Skipped,
+}
+
+/// all the commands, resolves with the first failing status
+struct Group {
+    members: Vec<Member>,
+    ctrl_c: Box<dyn Stream<Item = (), Error = io::Error> + Send>,
+    stopping: bool,
+    /// when the commands still running are killed, once stopping
+    grace: Option<Delay>,
+    failure: Option<ExitStatus>,
+}
+
+fn main() -> ExitCode {
+    let mut arguments = std::env::args().skip(1);
+    let path = match (arguments.next(), arguments.next()) {
+        (Some(path), None) if path != "--help" && path != "-h" => path,
+        _ => {
+            eprintln!("usage: bawawa <FILE>");
+            eprintln!();
+            eprintln!("run all the commands defined in the TOML or YAML file, or the Procfile");
+            return ExitCode::from(2);
+        }
+    };
+
+    match run(&path) {
+        Ok(None) => ExitCode::SUCCESS,
+        Ok(Some(status)) => ExitCode::from(exit_code(status)),
+        Err(error) => {
+            let mut message = format!("bawawa: {}", error);
+            let mut source = std::error::Error::source(&error);
+            while let Some(cause) = source {
+                message.push_str(&format!("\n  caused by: {}", cause));
+                source = cause.source();
+            }
+            eprintln!("{}", message);
+            ExitCode::FAILURE
+        }
+    }
+}
+
+fn run(path: &str) -> Result<Option<ExitStatus>, Error> {
+    let entries = load(Path::new(path))?;
+    let width = entries
+        .iter()
+        .map(|entry| entry.name.len())
+        .max()
+        .unwrap_or(0);
+    let colored = io::stdout().is_terminal();
+
+    let members = entries
+        .into_iter()
+        .enumerate()
+        .map(|(index, entry)| Member {
+            prefix: if colored {
+                let color = COLORS[index % COLORS.len()];
+                format!("\x1b[{}m{:width$} |\x1b[0m", color, entry.name)
+            } else {
+                format!("{:width$} |", entry.name)
+            },
+            state: State::Pending(entry),
+            outputs: Vec::new(),
+            status: None,
+            deadline: None,
+            linger: None,
+            notes: Vec::new(),
+        })
+        .collect();
+
+    let mut runtime = tokio::runtime::Runtime::new()?;
+    runtime.block_on(futures::future::lazy(move || Group {
+        members,
+        ctrl_c: Box::new(tokio_signal::ctrl_c().flatten_stream()),
+        stopping: false,
+        grace: None,
+        failure: None,
+    }))
+}
+
+/// the commands of the definition file or of the Procfile
+fn load(path: &Path) -> Result<Vec<Entry>, Error> {
+    let is_procfile = !matches!(
+        path.extension().and_then(|extension| extension.to_str()),
+        Some("toml" | "yaml" | "yml")
+    );
+    if !is_procfile {
+        let definitions = Definitions::load(path)?;
+        let commands = definitions.commands();
+        let mut entries = Vec::with_capacity(commands.len());
+        for definition in commands {
+            let mut depends_on = Vec::new();
+            // the dependencies are defined before, as checked when loading
+            for (index, dependency) in commands.iter().enumerate() {
+                if !definition
+                    .depends_on()
+                    .iter()
+                    .any(|name| name == dependency.name())
+                {
+                    continue;
+                }
+                if dependency.restart().is_some() {
+                    return Err(Error::Load {
+                        path: path.to_owned(),
+                        line: None,
+                        column: None,
+                        reason: format!(
+                            "command '{}' cannot depend on '{}' which is restarted",
+                            definition.name(),
+                            dependency.name()
+                        ),
+                        source: None,
+                    });
+                }
+                depends_on.push(index);
+            }
+            entries.push(Entry {
+                name: definition.name().to_owned(),
+                command: definition.command().clone(),
+                timeout: definition.timeout(),
+                restart: definition.restart(),
+                depends_on,
+            });
+        }
+        return Ok(entries);
+    }
+
+    let mut procfile = Procfile::load(path)?;
+    let env = path.with_file_name(".env");
+    if env.is_file() {
+        procfile.env_file(env)?;
+    }
+    Ok(procfile
+        .processes()
+        .map(|(name, command)| Entry {
+            name: name.to_owned(),
+            command: command.clone(),
+            timeout: None,
+            restart: None,
+            depends_on: Vec::new(),
+        })
+        .collect())
+}
+
+/// read the standard outputs of the process
+fn take_outputs(process: &mut Process, outputs: &mut Vec<Lines>) {
+    if let Some(stdout) = process.stdout().take() {
+        outputs.push(lines(stdout));
+    }
+    if let Some(stderr) = process.stderr().take() {
+        outputs.push(lines(stderr));
+    }
+}
+
+/// print the lines read so far, forget the outputs once closed
+fn print_lines(prefix: &str, outputs: &mut Vec<Lines>, stdout: &mut impl io::Write) {
+    let mut index = 0;
+    while index < outputs.len() {
+        match outputs[index].poll() {
+            Ok(Async::Ready(Some(line))) => {
+                let _ = writeln!(stdout, "{} {}", prefix, line);
+            }
+            Ok(Async::Ready(None)) => drop(outputs.remove(index)),
+            Ok(Async::NotReady) => index += 1,
+            Err(error) => {
+                let _ = writeln!(stdout, "{} cannot read: {}", prefix, error);
+                drop(outputs.remove(index));
+            }
+        }
+    }
+}
+
+/// print what happened to the process, in order
+fn print_notes(prefix: &str, notes: &mut Vec<String>, stdout: &mut impl io::Write) {
+    for note in notes.drain(..) {
+        let _ = writeln!(stdout, "{} {}", prefix, note);
+    }
+}
+
+fn lines<R>(pipe: R) -> Lines
+where
+    R: tokio_io::AsyncRead + Send + 'static,
+{
+    Box::new(FramedRead::new(pipe, LinesCodec::new()))
+}
+
+/// the exit code to report for the given status
+fn exit_code(status: ExitStatus) -> u8 {
+    #[cfg(unix)]
+    {
+        use std::os::unix::process::ExitStatusExt as _;
+        if let Some(signal) = status.signal() {
+            return (128 + signal) as u8;
+        }
+    }
+    status.code().map_or(1, |code| code as u8)
+}
+
+impl Member {
+    /// whether the command succeeded, once it won't run anymore
+    fn succeeded(&self) -> Option<bool> {
+        match (&self.state, self.status) {
+            (State::Skipped, _) => Some(false),
+            (_, status) => status.map(|status| status.success()),
+        }
+    }
+
+    /// whether the command runs, or may still run
+    fn is_running(&self) -> bool {
+        self.status.is_none() && !matches!(self.state, State::Skipped)
+    }
+
+    /// start the pending command
+    fn start(&mut self) -> Result<(), Error> {
+        let entry = match std::mem::replace(&mut self.state, State::Skipped) {
+            State::Pending(entry) => entry,
+            state => {
+                self.state = state;
+                return Ok(());
+            }
+        };
+        self.state = match entry.restart {
+            // the outputs are taken once the incarnation started
+            Some(policy) => State::Supervisor {
+                supervisor: Supervisor::new(entry.command, policy)?,
+                status: None,
+            },
+            None => {
+                let mut process = Process::spawn(entry.command)?;
+                take_outputs(&mut process, &mut self.outputs);
+                State::Process(process)
+            }
+        };
+        self.deadline = entry
+            .timeout
+            .map(|timeout| Delay::new(Instant::now() + timeout));
+        Ok(())
+    }
+
+    /// do not start the pending command
+    fn skip(&mut self) {
+        self.state = State::Skipped;
+        self.notes.push("not started".to_owned());
+    }
+
+    /// terminate the process, and stop restarting it
+    fn terminate(&mut self) -> Result<(), Error> {
+        match &mut self.state {
+            State::Process(process) => process.terminate(),
+            State::Supervisor { supervisor, .. } => supervisor.terminate(),
+            State::Pending(_) | State::Skipped => Ok(()),
+        }
+    }
+
+    /// kill the process, and stop restarting it
+    fn kill(&mut self) -> Result<(), Error> {
+        match &mut self.state {
+            State::Process(process) => process.kill(),
+            State::Supervisor { supervisor, .. } => supervisor.kill(),
+            State::Pending(_) | State::Skipped => Ok(()),
+        }
+    }
+
+    /// poll the exit of the command, once it won't be restarted
+    ///
+    /// what happened to the process is noted, to be printed after its
+    /// output.
+    fn poll_exit(&mut self, stdout: &mut impl io::Write) -> Poll<ExitStatus, Error> {
+        let Member {
+            prefix,
+            state,
+            outputs,
+            notes,
+            ..
+        } = self;
+        let (supervisor, status) = match state {
+            State::Process(process) => {
+                let status = try_ready!(process.poll());
+                notes.push(format!("exited: {}", status));
+                return Ok(Async::Ready(status));
+            }
+            State::Supervisor { supervisor, status } => (supervisor, status),
+            State::Pending(_) | State::Skipped => return Ok(Async::NotReady),
+        };
+        loop {
+            match try_ready!(supervisor.poll()) {
+                Some(SupervisorEvent::Started { .. }) => {
+                    print_notes(prefix, notes, stdout);
+                    if let Some(process) = supervisor.process_mut() {
+                        take_outputs(process, outputs);
+                    }
+                }
+                Some(SupervisorEvent::Exited { status: exited, .. }) => {
+                    *status = Some(exited);
+                    notes.push(format!("exited: {}", exited));
+                }
+                Some(SupervisorEvent::SpawnFailed { kind }) => {
+                    notes.push(format!("cannot restart: {:?}", kind));
+                }
+                Some(SupervisorEvent::Restarting { delay, .. }) => {
+                    notes.push(format!("restarting in {:?}", delay));
+                }
+                Some(SupervisorEvent::GaveUp { restarts }) => {
+                    notes.push(format!("gave up after {} restarts", restarts));
+                }
+                Some(SupervisorEvent::Unhealthy { failures, .. }) => {
+                    let _ = writeln!(stdout, "{} unhealthy after {} checks", prefix, failures);
+                }
+                // every incarnation exits before the supervision ends
+                None => {
+                    return status.map(Async::Ready).ok_or_else(|| {
+                        Error::Io(io::Error::other(
+                            "the supervision ended before the process exited",
+                        ))
+                    })
+                }
+            }
+        }
+    }
+}
+
+impl Group {
+    /// start the pending members once the commands they depend on
+    /// succeeded, skip them if one of them did not or if stopping
+    fn start(&mut self) -> Result<(), Error> {
+        for index in 0..self.members.len() {
+            let depends_on = match &self.members[index].state {
+                State::Pending(entry) => &entry.depends_on,
+                _ => continue,
+            };
+            let succeeded = |dependency: &usize| self.members[*dependency].succeeded();
+            if self.stopping
+                || depends_on
+                    .iter()
+                    .any(|dependency| succeeded(dependency) == Some(false))
+            {
+                self.members[index].skip();
+            } else if depends_on
+                .iter()
+                .all(|dependency| succeeded(dependency) == Some(true))
+            {
+                self.members[index].start()?;
+            }
+        }
+        Ok(())
+    }
+
+    /// terminate all the running members, once
+    fn stop(&mut self) {
+        if self.stopping {
+            return;
+        }
+        self.stopping = true;
+        self.grace = Some(Delay::new(Instant::now() + GRACE));
+        for member in self.members.iter_mut() {
+            if member.status.is_none() {
+                if let Err(error) = member.terminate() {
+                    eprintln!("{} cannot terminate: {}", member.prefix, error);
+                }
+            }
+        }
+    }
+
+    /// kill all the members still running
+    fn kill(&mut self) {
+        self.grace = None;
+        for member in self.members.iter_mut() {
+            if member.status.is_none() {
+                if let Err(error) = member.kill() {
+                    eprintln!("{} cannot kill: {}", member.prefix, error);
+                }
+            }
+        }
+    }
+}
+
+impl Future for Group {
+    type Item = Option<ExitStatus>;
+    type Error = Error;
+
+    fn poll(&mut self) -> Poll<Self::Item, Self::Error> {
+        while let Async::Ready(Some(())) = self.ctrl_c.poll()? {
+            if self.stopping {
+                self.kill();
+            } else {
+                self.stop();
+            }
+        }
+        if let Some(grace) = &mut self.grace {
+            if grace.poll().map_err(Error::Timer)?.is_ready() {
+                self.kill();
+            }
+        }
+        self.start()?;
+
+        let mut running = false;
+        let mut exited = false;
+        let mut failed = false;
+        let stdout = io::stdout();
+        let mut stdout = stdout.lock();
+        for member in self.members.iter_mut() {
+            if let Some(deadline) = &mut member.deadline {
+                if deadline.poll().map_err(Error::Timer)?.is_ready() {
+                    member.deadline = None;
+                    member.notes.push("timed out".to_owned());
+                    if let Err(error) = member.kill() {
+                        member.notes.push(format!("cannot kill: {}", error));
+                    }
+                }
+            }
+
+            let status = match member.status {
+                Some(_) => None,
+                None => match member.poll_exit(&mut stdout)? {
+                    Async::Ready(status) => Some(status),
+                    Async::NotReady => None,
+                },
+            };
+            member.status = member.status.or(status);
+            if let Some(status) = status {
+                exited = true;
+                member.deadline = None;
+                member.linger = Some(Delay::new(Instant::now() + LINGER));
+                if !status.success() && self.failure.is_none() {
+                    self.failure = Some(status);
+                    failed = true;
+                }
+            }
+
+            print_lines(&member.prefix, &mut member.outputs, &mut stdout);
+
+            if let Some(linger) = &mut member.linger {
+                // something left running in the background may keep the
+                // pipes open
+                if !member.outputs.is_empty() && linger.poll().map_err(Error::Timer)?.is_ready() {
+                    member.outputs.clear();
+                }
+            }
+
+            if member.outputs.is_empty() {
+                print_notes(&member.prefix, &mut member.notes, &mut stdout);
+            }
+            running = running || member.is_running() || !member.outputs.is_empty();
+        }
+        drop(stdout);
+
+        if failed {
+            // the exit of the others is polled already
+            self.stop();
+        }
+        if exited {
+            // the members depending on those which exited may start now
+            futures::task::current().notify();
+            Ok(Async::NotReady)
+        } else if running {
+            Ok(Async::NotReady)
+        } else {
+            Ok(Async::Ready(self.failure))
+        }
+    }
+}
@@ -1,11 +1,11 @@
 use crate::{
-    Command, Control, Error, ErrorKind, ResultExt, StandardError, StandardInput, StandardOutput,
+    ChildStderr, ChildStdin, ChildStdout, Command, Control, Error, StandardError, StandardInput,
+    StandardOutput,
 };
 use futures::prelude::*;
 use std::{marker::PhantomData, mem::ManuallyDrop};
 use tokio_codec::{Decoder, FramedRead};
 use tokio_io::AsyncRead;
-use tokio_process::{ChildStderr, ChildStdin, ChildStdout};
 
 /// capture the standard output or standard error output from
 /// a running process
@@ -156,6 +156,11 @@ where
     fn kill(&mut self) -> Result<(), Error> {
         unsafe { (*self.command).kill() }
     }
+
+    #[inline]
+    fn terminate(&mut self) -> Result<(), Error> {
+        unsafe { (*self.command).terminate() }
+    }
 }
 
 impl<'a, C, D, Item> StandardOutput<'a> for Capture<'a, C, D, ChildStderr, Item>
@@ -223,13 +228,15 @@ impl<'a, C, D, E, R, Item> Stream for Capture<'a, C, D, R, Item>
 where
     R: AsyncRead,
     D: Decoder<Item = Item, Error = E>,
-    E: std::error::Error + Send + From<std::io::Error> + 'static,
+    E: std::error::Error + Send + Sync + From<std::io::Error> + 'static,
 {
     type Item = Item;
     type Error = Error;
     #[inline]
     fn poll(&mut self) -> Poll<Option<Self::Item>, Self::Error> {
-        self.framed_read.poll().chain_err(|| ErrorKind::Capture)
+        self.framed_read
+            .poll()
+            .map_err(|error| Error::Capture(Box::new(error)))
     }
 }
 
@@ -1,16 +1,62 @@
-use crate::{Process, Program, Result};
-use std::{fmt, path::PathBuf};
+#[cfg(any(feature = "log", feature = "tracing"))]
+use crate::ForwardOutput;
+#[cfg(target_os = "linux")]
+use crate::Sandbox;
+#[cfg(all(
+    target_os = "linux",
+    any(target_arch = "x86_64", target_arch = "aarch64")
+))]
+use crate::SyscallFilter;
+#[cfg(unix)]
+use crate::{credentials::Credentials, pre_exec::PreExecHook};
+use crate::{spawner::SpawnerHandle, OsSpawner, Process, Program, Result, Spawner, TailOptions};
+use std::{
+    collections::{BTreeMap, BTreeSet},
+    fmt,
+    path::PathBuf,
+};
+
+/// what is displayed instead of the secret arguments and environment variables
+const REDACTED: &str = "***";
 
 /// just like standard `Command` but keeps the components
 /// in a human readable format so we can actually display
 /// it when needed. or keep trace of it.
 ///
 /// a Command is not active unless it has been started
-#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+///
+/// the arguments and environment variables set with [`secret_argument`]
+/// and [`secret_environment_variable`] are displayed as `***`.
+///
+/// [`secret_argument`]: #method.secret_argument
+/// [`secret_environment_variable`]: #method.secret_environment_variable
+#[derive(Clone, PartialEq, Eq, Hash)]
 pub struct Command {
     current_working_directory: Option<PathBuf>,
     program: Program,
     arguments: Vec<String>,
+    /// the indices of the secret arguments
+    secret_arguments: BTreeSet<usize>,
+    environment: BTreeMap<String, String>,
+    /// the keys of the secret environment variables
+    secret_environment: BTreeSet<String>,
+    /// the arguments start with `-c` and the script, see [`Command::shell`]
+    shell: bool,
+    #[cfg(unix)]
+    credentials: Credentials,
+    #[cfg(unix)]
+    pre_exec: Vec<PreExecHook>,
+    #[cfg(target_os = "linux")]
+    sandbox: Option<Sandbox>,
+    #[cfg(all(
+        target_os = "linux",
+        any(target_arch = "x86_64", target_arch = "aarch64")
+    ))]
+    syscall_filter: Option<SyscallFilter>,
+    output_tail: Option<TailOptions>,
+    #[cfg(any(feature = "log", feature = "tracing"))]
+    forward_output: Option<ForwardOutput>,
+    spawner: Option<SpawnerHandle>,
 }
 
 impl Command {
@@ -20,9 +66,71 @@ impl Command {
             current_working_directory: None,
             program,
             arguments: Vec::new(),
+            secret_arguments: BTreeSet::new(),
+            environment: BTreeMap::new(),
+            secret_environment: BTreeSet::new(),
+            shell: false,
+            #[cfg(unix)]
+            credentials: Credentials::default(),
+            #[cfg(unix)]
+            pre_exec: Vec::new(),
+            #[cfg(target_os = "linux")]
+            sandbox: None,
+            #[cfg(all(
+                target_os = "linux",
+                any(target_arch = "x86_64", target_arch = "aarch64")
+            ))]
+            syscall_filter: None,
+            output_tail: None,
+            #[cfg(any(feature = "log", feature = "tracing"))]
+            forward_output: None,
+            spawner: None,
         }
     }
 
+    /// create a command running `script` with `/bin/sh -c`
+    ///
+    /// use the [`shell!`] macro to safely build the script with
+    /// parameters. The command is displayed as the script.
+    ///
+    /// ```
+    /// # use bawawa::{shell, Command, Error};
+    /// # use futures::Future as _;
+    /// let file = "Cargo.toml";
+    /// let command = Command::shell(shell!("grep -Fc {} {} || true", "[package]", file))?;
+    /// # if cfg!(unix) {
+    /// let output = command.spawn()?.output().wait()?;
+    /// assert_eq!(output.stdout, b"1\n");
+    /// # }
+    /// assert_eq!(command.to_string(), "grep -Fc '[package]' Cargo.toml || true");
+    /// # Ok::<(), Error>(())
+    /// ```
+    ///
+    /// # Error
+    ///
+    /// fails if `/bin/sh` is not a valid [`Program`]
+    ///
+    /// [`shell!`]: ./macro.shell.html
+    /// [`Program`]: ./struct.Program.html
+    pub fn shell<S: AsRef<str>>(script: S) -> Result<Self> {
+        Ok(Self::shell_with(Program::new("/bin/sh")?, script))
+    }
+
+    /// create a command running `script` with `shell -c`, see [`shell`]
+    ///
+    /// [`shell`]: #method.shell
+    pub fn shell_with<S: AsRef<str>>(shell: Program, script: S) -> Self {
+        let mut command = Command::new(shell);
+        command.arguments(["-c", script.as_ref()]).shell = true;
+        command
+    }
+
+    /// the program to execute
+    #[inline]
+    pub fn program(&self) -> &Program {
+        &self.program
+    }
+
     /// set the working directory: the directory in which the command
     /// will be executed.
     #[inline]
@@ -31,6 +139,167 @@ impl Command {
         self
     }
 
+    /// run the command as the user with the given id
+    ///
+    /// the user, the group and the supplementary groups are switched to
+    /// in the child process, before the program is executed, which
+    /// usually requires privileges. Like `std::process::Command::uid`,
+    /// the supplementary groups of root are dropped unless [`groups`] is
+    /// set.
+    ///
+    /// [`groups`]: #method.groups
+    #[cfg(unix)]
+    #[inline]
+    pub fn uid(&mut self, uid: u32) -> &mut Self {
+        self.credentials.uid(uid);
+        self
+    }
+
+    /// run the command with the group of the given id
+    #[cfg(unix)]
+    #[inline]
+    pub fn gid(&mut self, gid: u32) -> &mut Self {
+        self.credentials.gid(gid);
+        self
+    }
+
+    /// run the command with the given supplementary groups only
+    #[cfg(unix)]
+    pub fn groups<I: IntoIterator<Item = u32>>(&mut self, groups: I) -> &mut Self {
+        self.credentials.groups(groups.into_iter().collect());
+        self
+    }
+
+    /// run the command as the named user, with its primary group and its
+    /// supplementary groups, as found in the passwd and group databases
+    ///
+    /// the command is displayed with `USER=<name>`.
+    ///
+    /// # Error
+    ///
+    /// fails with [`Error::UnknownUser`] if there is no such user and with
+    /// [`Error::Io`] if the databases cannot be read.
+    ///
+    /// [`Error::UnknownUser`]: ./enum.Error.html#variant.UnknownUser
+    /// [`Error::Io`]: ./enum.Error.html#variant.Io
+    #[cfg(unix)]
+    pub fn user(&mut self, name: &str) -> Result<&mut Self> {
+        self.credentials.user(name)?;
+        Ok(self)
+    }
+
+    /// run the command with the named group, as found in the group
+    /// database
+    ///
+    /// # Error
+    ///
+    /// fails with [`Error::UnknownGroup`] if there is no such group and
+    /// with [`Error::Io`] if the database cannot be read.
+    ///
+    /// [`Error::UnknownGroup`]: ./enum.Error.html#variant.UnknownGroup
+    /// [`Error::Io`]: ./enum.Error.html#variant.Io
+    #[cfg(unix)]
+    pub fn group(&mut self, name: &str) -> Result<&mut Self> {
+        self.credentials.group(name)?;
+        Ok(self)
+    }
+
+    #[cfg(unix)]
+    #[inline]
+    pub(crate) fn credentials(&self) -> &Credentials {
+        &self.credentials
+    }
+
+    /// run `hook` in the child process, right before the program is
+    /// executed
+    ///
+    /// the hooks run in the order they are added, after the switch of
+    /// the user (see [`uid`]) and the [`Sandbox`] but before the
+    /// [`SyscallFilter`] is installed. They are only run by the
+    /// [`OsSpawner`].
+    ///
+    /// # Error
+    ///
+    /// a hook failing fails the spawn with [`Error::PreExec`], only the
+    /// OS error code of the returned error is kept.
+    ///
+    /// # Safety
+    ///
+    /// the hook runs in the forked child of a possibly multi-threaded
+    /// process: just like with [`std::os::unix::process::CommandExt::pre_exec`],
+    /// it may only call async-signal-safe functions and must not
+    /// allocate.
+    ///
+    /// ```
+    /// # use bawawa::{Command, Program};
+    /// let mut command = Command::new(Program::new("true")?);
+    /// unsafe {
+    ///     command.pre_exec(|| {
+    ///         libc::umask(0o077);
+    ///         Ok(())
+    ///     })
+    /// };
+    /// # Ok::<(), bawawa::Error>(())
+    /// ```
+    ///
+    /// [`uid`]: #method.uid
+    /// [`Sandbox`]: ./struct.Sandbox.html
+    /// [`SyscallFilter`]: ./struct.SyscallFilter.html
+    /// [`OsSpawner`]: ./struct.OsSpawner.html
+    /// [`Error::PreExec`]: ./enum.Error.html#variant.PreExec
+    #[cfg(unix)]
+    pub unsafe fn pre_exec<F>(&mut self, hook: F) -> &mut Self
+    where
+        F: Fn() -> std::io::Result<()> + Send + Sync + 'static,
+    {
+        self.pre_exec.push(PreExecHook::new(hook));
+        self
+    }
+
+    #[cfg(unix)]
+    #[inline]
+    pub(crate) fn pre_exec_hooks(&self) -> &[PreExecHook] {
+        &self.pre_exec
+    }
+
+    /// isolate the command in new namespaces, see [`Sandbox`]
+    ///
+    /// [`Sandbox`]: ./struct.Sandbox.html
+    #[cfg(target_os = "linux")]
+    #[inline]
+    pub fn sandbox(&mut self, sandbox: Sandbox) -> &mut Self {
+        self.sandbox = Some(sandbox);
+        self
+    }
+
+    #[cfg(target_os = "linux")]
+    #[inline]
+    pub(crate) fn sandbox_options(&self) -> Option<&Sandbox> {
+        self.sandbox.as_ref()
+    }
+
+    /// filter the system calls of the program, see [`SyscallFilter`]
+    ///
+    /// [`SyscallFilter`]: ./struct.SyscallFilter.html
+    #[cfg(all(
+        target_os = "linux",
+        any(target_arch = "x86_64", target_arch = "aarch64")
+    ))]
+    #[inline]
+    pub fn syscall_filter(&mut self, filter: SyscallFilter) -> &mut Self {
+        self.syscall_filter = Some(filter);
+        self
+    }
+
+    #[cfg(all(
+        target_os = "linux",
+        any(target_arch = "x86_64", target_arch = "aarch64")
+    ))]
+    #[inline]
+    pub(crate) fn syscall_filter_options(&self) -> Option<&SyscallFilter> {
+        self.syscall_filter.as_ref()
+    }
+
     /// set argument to the command
     pub fn argument<S>(&mut self, argument: S) -> &mut Self
     where
@@ -54,6 +323,134 @@ impl Command {
         self
     }
 
+    /// set an argument that is passed unchanged to the program but
+    /// displayed as `***` (passwords, tokens...)
+    pub fn secret_argument<S>(&mut self, argument: S) -> &mut Self
+    where
+        S: AsRef<str>,
+    {
+        self.secret_arguments.insert(self.arguments.len());
+        self.argument(argument)
+    }
+
+    /// set an environment variable of the command, on top of the ones
+    /// inherited from the current process
+    pub fn environment_variable<K, V>(&mut self, key: K, value: V) -> &mut Self
+    where
+        K: AsRef<str>,
+        V: AsRef<str>,
+    {
+        self.secret_environment.remove(key.as_ref());
+        self.environment
+            .insert(key.as_ref().to_owned(), value.as_ref().to_owned());
+        self
+    }
+
+    /// set an environment variable whose value is displayed as `***`
+    pub fn secret_environment_variable<K, V>(&mut self, key: K, value: V) -> &mut Self
+    where
+        K: AsRef<str>,
+        V: AsRef<str>,
+    {
+        self.environment_variable(key.as_ref(), value);
+        self.secret_environment.insert(key.as_ref().to_owned());
+        self
+    }
+
+    /// set environment variables of the command
+    pub fn environment_variables<I, K, V>(&mut self, variables: I) -> &mut Self
+    where
+        I: IntoIterator<Item = (K, V)>,
+        K: AsRef<str>,
+        V: AsRef<str>,
+    {
+        for (key, value) in variables {
+            self.environment_variable(key, value);
+        }
+        self
+    }
+
+    /// the working directory set with [`current_working_directory`]
+    ///
+    /// [`current_working_directory`]: #method.current_working_directory
+    #[inline]
+    pub fn working_directory(&self) -> Option<&PathBuf> {
+        self.current_working_directory.as_ref()
+    }
+
+    /// the arguments passed to the program
+    #[inline]
+    pub fn argument_list(&self) -> &[String] {
+        &self.arguments
+    }
+
+    /// the environment variables set on top of the inherited ones
+    #[inline]
+    pub fn environment(&self) -> &BTreeMap<String, String> {
+        &self.environment
+    }
+
+    /// keep the last lines printed by the process in an [`OutputTail`]
+    ///
+    /// the standard error output (and optionally the standard output) is
+    /// then continuously drained while the [`Process`] is polled, and the
+    /// errors returned when it fails will display these lines.
+    ///
+    /// Once drained the output cannot be captured anymore.
+    ///
+    /// [`OutputTail`]: ./struct.OutputTail.html
+    /// [`Process`]: ./struct.Process.html
+    #[inline]
+    pub fn output_tail(&mut self, options: TailOptions) -> &mut Self {
+        self.output_tail = Some(options);
+        self
+    }
+
+    #[inline]
+    pub(crate) fn output_tail_options(&self) -> Option<TailOptions> {
+        self.output_tail
+    }
+
+    /// forward the lines printed by the process to `log` or `tracing`
+    ///
+    /// the lines are emitted while the [`Process`] is polled, with the
+    /// program file name as target (or as `program` field for `tracing`)
+    /// and the PID and the command as fields.
+    ///
+    /// Once forwarded the output cannot be captured anymore.
+    ///
+    /// [`Process`]: ./struct.Process.html
+    #[cfg(any(feature = "log", feature = "tracing"))]
+    #[inline]
+    pub fn forward_output(&mut self, options: ForwardOutput) -> &mut Self {
+        self.forward_output = Some(options);
+        self
+    }
+
+    #[cfg(any(feature = "log", feature = "tracing"))]
+    #[inline]
+    pub(crate) fn forward_output_options(&self) -> Option<ForwardOutput> {
+        self.forward_output
+    }
+
+    /// set the [`Spawner`] starting the process, the [`OsSpawner`] is
+    /// used by default
+    ///
+    /// [`Spawner`]: ./trait.Spawner.html
+    /// [`OsSpawner`]: ./struct.OsSpawner.html
+    #[inline]
+    pub fn spawner<S: Spawner + 'static>(&mut self, spawner: S) -> &mut Self {
+        self.spawner = Some(SpawnerHandle::new(spawner));
+        self
+    }
+
+    pub(crate) fn spawner_ref(&self) -> &dyn Spawner {
+        match &self.spawner {
+            Some(spawner) => spawner.spawner(),
+            None => &OsSpawner,
+        }
+    }
+
     /// spawn the command into the given process
     ///
     /// # Error
@@ -65,6 +462,31 @@ impl Command {
         Process::spawn(self.clone())
     }
 
+    /// the arguments, with the secret ones redacted
+    pub(crate) fn displayed_arguments(&self) -> impl Iterator<Item = &str> {
+        self.arguments
+            .iter()
+            .enumerate()
+            .map(move |(index, argument)| {
+                if self.secret_arguments.contains(&index) {
+                    REDACTED
+                } else {
+                    argument.as_str()
+                }
+            })
+    }
+
+    /// the environment variables, with the secret values redacted
+    pub(crate) fn displayed_environment(&self) -> impl Iterator<Item = (&str, &str)> {
+        self.environment.iter().map(move |(key, value)| {
+            if self.secret_environment.contains(key) {
+                (key.as_str(), REDACTED)
+            } else {
+                (key.as_str(), value.as_str())
+            }
+        })
+    }
+
     pub(super) fn process_command(&self) -> std::process::Command {
         let mut cmd = std::process::Command::new(&self.program);
 
@@ -75,7 +497,8 @@ impl Command {
         cmd.stdin(std::process::Stdio::piped())
             .stdout(std::process::Stdio::piped())
             .stderr(std::process::Stdio::piped())
-            .args(self.arguments.iter());
+            .args(self.arguments.iter())
+            .envs(self.environment.iter());
 
         cmd
     }
@@ -86,10 +509,90 @@ impl fmt::Display for Command {
         if let Some(cwd) = &self.current_working_directory {
             write!(f, "CWD={} ", cwd.display())?;
         }
-        self.program.fmt(f)?;
-        for argument in self.arguments.iter() {
+        #[cfg(unix)]
+        self.credentials.display(f)?;
+        for (key, value) in self.displayed_environment() {
+            write!(f, "{}={} ", key, value)?;
+        }
+        let mut arguments = self.displayed_arguments();
+        if self.shell {
+            // `-c` and the script are displayed as the script only
+            arguments.next();
+            if let Some(script) = arguments.next() {
+                f.write_str(script)?;
+            }
+        } else {
+            self.program.fmt(f)?;
+        }
+        for argument in arguments {
             write!(f, " {}", argument)?;
         }
         Ok(())
     }
 }
+
+impl fmt::Debug for Command {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let mut debug = f.debug_struct("Command");
+        debug
+            .field("current_working_directory", &self.current_working_directory)
+            .field("program", &self.program)
+            .field("arguments", &self.displayed_arguments().collect::<Vec<_>>())
+            .field(
+                "environment",
+                &self.displayed_environment().collect::<BTreeMap<_, _>>(),
+            )
+            .field("shell", &self.shell);
+        #[cfg(unix)]
+        debug.field("credentials", &self.credentials);
+        #[cfg(unix)]
+        debug.field("pre_exec", &self.pre_exec);
+        #[cfg(target_os = "linux")]
+        debug.field("sandbox", &self.sandbox);
+        #[cfg(all(
+            target_os = "linux",
+            any(target_arch = "x86_64", target_arch = "aarch64")
+        ))]
+        debug.field("syscall_filter", &self.syscall_filter);
+        debug.field("output_tail", &self.output_tail);
+        #[cfg(any(feature = "log", feature = "tracing"))]
+        debug.field("forward_output", &self.forward_output);
+        debug.field("spawner", &self.spawner).finish()
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::{DryRunSpawner, FakeSpawner, Process};
+
+    #[test]
+    fn secrets_are_redacted() -> Result<()> {
+        let spawner = FakeSpawner::new();
+        let mut command = Command::new(Program::with_spawner("curl", &DryRunSpawner::new())?);
+        command
+            .secret_environment_variable("TOKEN", "t0k3n")
+            .environment_variable("LANG", "C")
+            .argument("--user")
+            .secret_argument("admin:hunter2")
+            .argument("https://example.com")
+            .spawner(spawner);
+
+        let display = "LANG=C TOKEN=*** curl --user *** https://example.com";
+        assert_eq!(command.to_string(), display);
+        let debug = format!("{:?}", command);
+        assert!(!debug.contains("hunter2") && !debug.contains("t0k3n"));
+        assert!(debug.contains("***"));
+
+        let error = Process::spawn(command.clone())
+            .err()
+            .expect("nothing is expected");
+        assert!(!error.to_string().contains("hunter2"));
+        assert!(!format!("{:?}", error).contains("hunter2"));
+
+        // still passed unchanged to the program
+        let process_command = format!("{:?}", command.process_command());
+        assert!(process_command.contains("hunter2") && process_command.contains("t0k3n"));
+        Ok(())
+    }
+}
@@ -0,0 +1,710 @@
+use crate::{Command, Error, Program, Restart, RestartPolicy, Result, TailOptions, TaskGraph};
+#[cfg(any(feature = "log", feature = "tracing"))]
+use crate::{ForwardOutput, Level};
+use serde::{de::IgnoredAny, Deserialize};
+use std::{
+    collections::BTreeMap,
+    path::{Path, PathBuf},
+    time::Duration,
+};
+
+/// commands defined in a TOML or YAML file, loaded with [`Definitions::load`]
+///
+/// every command has a unique name, the durations are in seconds:
+///
+/// ```toml
+/// [[commands]]
+/// name = "build"
+/// program = "cargo"
+/// args = ["build", "--release"]
+/// cwd = "."
+/// env = { RUST_LOG = "debug" }
+/// timeout = 600
+/// stdio = { tail = 20, tail_stdout = true }
+///
+/// [[commands]]
+/// name = "test"
+/// program = "cargo"
+/// args = ["test"]
+/// depends_on = ["build"]
+///
+/// [[commands]]
+/// name = "server"
+/// program = "target/release/server"
+/// restart = { policy = "on-failure", max_restarts = 5, window = 60, initial_delay = 0.1, max_delay = 30 }
+/// ```
+///
+/// the commands run to completion in a [`TaskGraph`] (see
+/// [`Definitions::task_graph`]), where every command runs once.
+///
+/// `restart` is the [`RestartPolicy`] of a [`Supervisor`] running the
+/// command: `policy` is one of `"always"`, `"on-failure"` or `"never"`,
+/// the supervisor gives up after `max_restarts` restarts within `window`
+/// (60 seconds by default) and waits between `initial_delay` and
+/// `max_delay` before restarting.
+///
+/// with the `log` or `tracing` features the output can be forwarded to the
+/// logs too, with `forward_stdout` and `forward_stderr` in `stdio` (one of
+/// `"off"`, `"error"`, `"warn"`, `"info"`, `"debug"` or `"trace"`).
+///
+/// [`Definitions::load`]: ./struct.Definitions.html#method.load
+/// [`TaskGraph`]: ./struct.TaskGraph.html
+/// [`Definitions::task_graph`]: ./struct.Definitions.html#method.task_graph
+/// [`RestartPolicy`]: ./struct.RestartPolicy.html
+/// [`Supervisor`]: ./struct.Supervisor.html
+#[derive(Debug, Clone)]
+pub struct Definitions {
+    commands: Vec<Definition>,
+}
+
+/// a command of the [`Definitions`]
+///
+/// [`Definitions`]: ./struct.Definitions.html
+#[derive(Debug, Clone)]
+pub struct Definition {
+    name: String,
+    command: Command,
+    timeout: Option<Duration>,
+    restart: Option<RestartPolicy>,
+    depends_on: Vec<String>,
+}
+
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+struct RawDefinitions {
+    #[serde(default)]
+    commands: Vec<RawDefinition>,
+}
+
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+struct RawDefinition {
+    name: String,
+    program: String,
+    #[serde(default)]
+    args: Vec<String>,
+    cwd: Option<PathBuf>,
+    #[serde(default)]
+    env: BTreeMap<String, String>,
+    #[serde(default)]
+    stdio: RawStdio,
+    timeout: Option<f64>,
+    restart: Option<RawRestart>,
+    #[serde(default)]
+    depends_on: Vec<String>,
+}
+
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+struct RawRestart {
+    policy: RawRestartPolicy,
+    max_restarts: Option<usize>,
+    window: Option<f64>,
+    initial_delay: Option<f64>,
+    max_delay: Option<f64>,
+}
+
+#[derive(Deserialize, Clone, Copy)]
+#[serde(rename_all = "kebab-case")]
+enum RawRestartPolicy {
+    Always,
+    OnFailure,
+    Never,
+}
+
+#[derive(Deserialize, Default)]
+#[serde(deny_unknown_fields)]
+struct RawStdio {
+    tail: Option<usize>,
+    #[serde(default)]
+    tail_stdout: bool,
+    #[cfg(any(feature = "log", feature = "tracing"))]
+    forward_stdout: Option<RawLevel>,
+    #[cfg(any(feature = "log", feature = "tracing"))]
+    forward_stderr: Option<RawLevel>,
+}
+
+#[cfg(any(feature = "log", feature = "tracing"))]
+#[derive(Deserialize, Clone, Copy)]
+#[serde(rename_all = "lowercase")]
+enum RawLevel {
+    Off,
+    Error,
+    Warn,
+    Info,
+    Debug,
+    Trace,
+}
+
+/// where the values of the commands are, only known for TOML as
+/// serde_yaml does not tell where the values are
+#[derive(Deserialize, Default)]
+struct RawPositions {
+    #[serde(default)]
+    commands: Vec<RawDefinitionPositions>,
+}
+
+#[derive(Deserialize, Default)]
+struct RawDefinitionPositions {
+    name: Option<toml::Spanned<IgnoredAny>>,
+    program: Option<toml::Spanned<IgnoredAny>>,
+    timeout: Option<toml::Spanned<IgnoredAny>>,
+    restart: Option<toml::Spanned<IgnoredAny>>,
+}
+
+/// the file being loaded, to report the errors with their location
+struct Source<'a> {
+    path: &'a Path,
+    content: &'a str,
+    positions: Vec<RawDefinitionPositions>,
+}
+
+type Position = (Option<usize>, Option<usize>);
+
+impl Definitions {
+    /// load the definitions, in TOML or YAML depending on the extension
+    /// of the file (`.toml`, `.yaml` or `.yml`)
+    ///
+    /// # Error
+    ///
+    /// fails with [`Error::Load`] (and the line and column of the invalid
+    /// value when known) if the file cannot be read or parsed, if two
+    /// commands have the same name, if a command depends on a command not
+    /// defined before it, if a timeout or a delay of a restart policy is
+    /// not a valid duration, if the window of a restart policy is set
+    /// without its `max_restarts` or if a program is not valid (see
+    /// [`Program::new`]).
+    ///
+    /// [`Error::Load`]: ./enum.Error.html#variant.Load
+    /// [`Program::new`]: ./struct.Program.html#method.new
+    pub fn load<P: AsRef<Path>>(path: P) -> Result<Self> {
+        let path = path.as_ref();
+        let content = std::fs::read_to_string(path).map_err(|error| Error::Load {
+            path: path.to_owned(),
+            line: None,
+            column: None,
+            reason: "cannot read the file".to_owned(),
+            source: Some(Box::new(error)),
+        })?;
+        let mut source = Source {
+            path,
+            content: &content,
+            positions: Vec::new(),
+        };
+
+        let raw: RawDefinitions = match path.extension().and_then(|extension| extension.to_str()) {
+            Some("toml") => {
+                let raw = toml::from_str(&content).map_err(|error| {
+                    let (line, column) = match error.span() {
+                        Some(span) => source.position(span.start),
+                        None => (None, None),
+                    };
+                    source.error(line, column, error.message().to_owned())
+                })?;
+                source.positions = toml::from_str::<RawPositions>(&content)
+                    .map(|positions| positions.commands)
+                    .unwrap_or_default();
+                raw
+            }
+            Some("yaml") | Some("yml") => serde_yaml::from_str(&content).map_err(|error| {
+                let location = error.location();
+                source.error(
+                    location.as_ref().map(|location| location.line()),
+                    location.as_ref().map(|location| location.column()),
+                    error.to_string(),
+                )
+            })?,
+            _ => {
+                return Err(source.error(
+                    None,
+                    None,
+                    "unknown format, expected a `.toml`, `.yaml` or `.yml` file".to_owned(),
+                ))
+            }
+        };
+
+        let mut commands: Vec<Definition> = Vec::with_capacity(raw.commands.len());
+        for (index, raw) in raw.commands.into_iter().enumerate() {
+            let (line, column) = source.position_of(index, |command| &command.name);
+            if commands.iter().any(|command| command.name == raw.name) {
+                return Err(source.error(
+                    line,
+                    column,
+                    format!("command '{}' is defined twice", raw.name),
+                ));
+            }
+            if let Some(dependency) = raw
+                .depends_on
+                .iter()
+                .find(|dependency| commands.iter().all(|command| command.name != **dependency))
+            {
+                return Err(source.error(
+                    line,
+                    column,
+                    format!(
+                        "command '{}' depends on '{}' which is not defined before it",
+                        raw.name, dependency
+                    ),
+                ));
+            }
+
+            let timeout = match raw.timeout {
+                Some(timeout) => Some(source.duration(
+                    index,
+                    |command| &command.timeout,
+                    timeout,
+                    &format!("timeout of command '{}'", raw.name),
+                )?),
+                None => None,
+            };
+            let restart = match &raw.restart {
+                Some(restart) => Some(source.restart_policy(index, &raw.name, restart)?),
+                None => None,
+            };
+
+            let program = Program::new(&raw.program).map_err(|error| {
+                let (line, column) = source.position_of(index, |command| &command.program);
+                Error::Load {
+                    path: source.path.to_owned(),
+                    line,
+                    column,
+                    reason: format!("invalid program of command '{}'", raw.name),
+                    source: Some(Box::new(error)),
+                }
+            })?;
+            commands.push(Definition::new(program, timeout, restart, raw));
+        }
+
+        Ok(Definitions { commands })
+    }
+
+    /// the commands, in the order of the file
+    pub fn commands(&self) -> &[Definition] {
+        &self.commands
+    }
+
+    /// the command with the given name
+    pub fn get(&self, name: &str) -> Option<&Definition> {
+        self.commands.iter().find(|command| command.name == name)
+    }
+
+    /// a [`TaskGraph`] running the commands, after the commands they
+    /// depend on and within their timeout
+    ///
+    /// [`TaskGraph`]: ./struct.TaskGraph.html
+    pub fn task_graph(&self) -> TaskGraph {
+        let mut graph = TaskGraph::new();
+        let mut ids = BTreeMap::new();
+        for command in self.commands.iter() {
+            // the dependencies have been checked when loading
+            let dependencies: Vec<_> = command
+                .depends_on
+                .iter()
+                .map(|dependency| ids[dependency.as_str()])
+                .collect();
+            let id = graph.task(
+                command.name.as_str(),
+                command.command.clone(),
+                &dependencies,
+            );
+            if let Some(timeout) = command.timeout {
+                graph.timeout(id, timeout);
+            }
+            ids.insert(command.name.as_str(), id);
+        }
+        graph
+    }
+}
+
+impl Definition {
+    fn new(
+        program: Program,
+        timeout: Option<Duration>,
+        restart: Option<RestartPolicy>,
+        raw: RawDefinition,
+    ) -> Self {
+        let mut command = Command::new(program);
+        command.arguments(raw.args).environment_variables(raw.env);
+        if let Some(cwd) = raw.cwd {
+            command.current_working_directory(cwd);
+        }
+        if let Some(tail) = raw.stdio.tail {
+            command.output_tail(TailOptions::new(tail).include_stdout(raw.stdio.tail_stdout));
+        }
+        #[cfg(any(feature = "log", feature = "tracing"))]
+        {
+            let RawStdio {
+                forward_stdout,
+                forward_stderr,
+                ..
+            } = raw.stdio;
+            if forward_stdout.is_some() || forward_stderr.is_some() {
+                let mut forward = ForwardOutput::new();
+                if let Some(level) = forward_stdout {
+                    forward = forward.stdout(level.into());
+                }
+                if let Some(level) = forward_stderr {
+                    forward = forward.stderr(level.into());
+                }
+                command.forward_output(forward);
+            }
+        }
+
+        Definition {
+            name: raw.name,
+            command,
+            timeout,
+            restart,
+            depends_on: raw.depends_on,
+        }
+    }
+
+    /// the unique name of the command
+    pub fn name(&self) -> &str {
+        &self.name
+    }
+
+    pub fn command(&self) -> &Command {
+        &self.command
+    }
+
+    /// how long the command may run, it is killed afterward in the
+    /// [`Definitions::task_graph`]
+    ///
+    /// [`Definitions::task_graph`]: ./struct.Definitions.html#method.task_graph
+    pub fn timeout(&self) -> Option<Duration> {
+        self.timeout
+    }
+
+    /// how the command is restarted by a [`Supervisor`], the
+    /// [`Definitions::task_graph`] runs it once whatever its policy
+    ///
+    /// [`Supervisor`]: ./struct.Supervisor.html
+    /// [`Definitions::task_graph`]: ./struct.Definitions.html#method.task_graph
+    pub fn restart(&self) -> Option<RestartPolicy> {
+        self.restart
+    }
+
+    /// the names of the commands to run before this one
+    pub fn depends_on(&self) -> &[String] {
+        &self.depends_on
+    }
+}
+
+impl From<RawRestartPolicy> for Restart {
+    fn from(policy: RawRestartPolicy) -> Self {
+        match policy {
+            RawRestartPolicy::Always => Restart::Always,
+            RawRestartPolicy::OnFailure => Restart::OnFailure,
+            RawRestartPolicy::Never => Restart::Never,
+        }
+    }
+}
+
+#[cfg(any(feature = "log", feature = "tracing"))]
+impl From<RawLevel> for Option<Level> {
+    fn from(level: RawLevel) -> Self {
+        match level {
+            RawLevel::Off => None,
+            RawLevel::Error => Some(Level::Error),
+            RawLevel::Warn => Some(Level::Warn),
+            RawLevel::Info => Some(Level::Info),
+            RawLevel::Debug => Some(Level::Debug),
+            RawLevel::Trace => Some(Level::Trace),
+        }
+    }
+}
+
+impl<'a> Source<'a> {
+    fn error(&self, line: Option<usize>, column: Option<usize>, reason: String) -> Error {
+        Error::Load {
+            path: self.path.to_owned(),
+            line,
+            column,
+            reason,
+            source: None,
+        }
+    }
+
+    /// the duration of `seconds`, `what` is reported at the position of
+    /// the value if it is not a valid duration
+    fn duration<F>(&self, index: usize, value: F, seconds: f64, what: &str) -> Result<Duration>
+    where
+        F: Fn(&RawDefinitionPositions) -> &Option<toml::Spanned<IgnoredAny>>,
+    {
+        Duration::try_from_secs_f64(seconds).map_err(|error| {
+            let (line, column) = self.position_of(index, value);
+            self.error(line, column, format!("invalid {}: {}", what, error))
+        })
+    }
+
+    /// the restart policy of the `index`th command, named `name`
+    fn restart_policy(&self, index: usize, name: &str, raw: &RawRestart) -> Result<RestartPolicy> {
+        let duration = |seconds: Option<f64>, default: Duration, what: &str| match seconds {
+            Some(seconds) => self.duration(
+                index,
+                |command| &command.restart,
+                seconds,
+                &format!("{} of the restart policy of command '{}'", what, name),
+            ),
+            None => Ok(default),
+        };
+
+        let mut policy = RestartPolicy::new(raw.policy.into()).backoff(
+            duration(
+                raw.initial_delay,
+                RestartPolicy::DEFAULT_INITIAL_DELAY,
+                "initial delay",
+            )?,
+            duration(
+                raw.max_delay,
+                RestartPolicy::DEFAULT_MAX_DELAY,
+                "maximum delay",
+            )?,
+        );
+        match (raw.max_restarts, raw.window) {
+            (Some(max_restarts), window) => {
+                let window = duration(window, RestartPolicy::DEFAULT_WINDOW, "window")?;
+                policy = policy.max_restarts(max_restarts, window);
+            }
+            (None, Some(_)) => {
+                let (line, column) = self.position_of(index, |command| &command.restart);
+                return Err(self.error(
+                    line,
+                    column,
+                    format!(
+                        "the window of the restart policy of command '{}' requires max_restarts",
+                        name
+                    ),
+                ));
+            }
+            (None, None) => {}
+        }
+        Ok(policy)
+    }
+
+    /// the line and column (starting at 1) of the byte `offset`
+    fn position(&self, offset: usize) -> Position {
+        let before = &self.content[..offset.min(self.content.len())];
+        let line = before.matches('\n').count() + 1;
+        let column = before.len() - before.rfind('\n').map_or(0, |newline| newline + 1) + 1;
+        (Some(line), Some(column))
+    }
+
+    /// the position of a value of the `index`th command, when known
+    fn position_of<F>(&self, index: usize, value: F) -> Position
+    where
+        F: Fn(&RawDefinitionPositions) -> &Option<toml::Spanned<IgnoredAny>>,
+    {
+        match self
+            .positions
+            .get(index)
+            .and_then(|command| value(command).as_ref())
+        {
+            Some(spanned) => self.position(spanned.span().start),
+            None => (None, None),
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::{temp::TempPath, TaskStatus};
+
+    fn load_error(name: &str, content: &str) -> Error {
+        let path = TempPath::file(name, content);
+        Definitions::load(&path).expect_err("the file should not load")
+    }
+
+    #[test]
+    fn load_toml() -> Result<()> {
+        let path = TempPath::file(
+            "load.toml",
+            r#"
+[[commands]]
+name = "version"
+program = "rustc"
+args = ["--version"]
+env = { LANG = "C" }
+timeout = 1.5
+stdio = { tail = 5, tail_stdout = true }
+
+[[commands]]
+name = "help"
+program = "rustc"
+args = ["--help"]
+depends_on = ["version"]
+"#,
+        );
+        let definitions = Definitions::load(&path)?;
+
+        let version = definitions.get("version").expect("the version command");
+        assert_eq!(version.command().to_string(), "LANG=C rustc --version");
+        assert_eq!(version.timeout(), Some(Duration::from_millis(1500)));
+        assert_eq!(
+            version.command().output_tail_options(),
+            Some(TailOptions::new(5).include_stdout(true))
+        );
+        let help = definitions.get("help").expect("the help command");
+        assert_eq!(help.depends_on(), ["version"]);
+
+        let mut runtime = tokio::runtime::Runtime::new().unwrap();
+        let report = runtime.block_on(definitions.task_graph().run())?;
+        assert!(report
+            .tasks()
+            .iter()
+            .all(|task| task.status == TaskStatus::Succeeded));
+        Ok(())
+    }
+
+    #[test]
+    fn load_yaml() -> Result<()> {
+        let path = TempPath::file(
+            "load.yaml",
+            "commands:\n  - name: version\n    program: rustc\n    args: [--version]\n    cwd: /\n",
+        );
+        let definitions = Definitions::load(&path)?;
+
+        assert_eq!(
+            definitions.commands()[0].command().to_string(),
+            "CWD=/ rustc --version"
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn errors_with_location() {
+        let error = load_error(
+            "invalid.toml",
+            "[[commands]]\nname = \"a\"\nprogram = \"rustc\"\n\n[[commands]]\nname = \"b\"\nprogram = \"the-impossible-program\"\n",
+        );
+        match &error {
+            Error::Load {
+                line,
+                column,
+                source,
+                ..
+            } => {
+                assert_eq!((*line, *column), (Some(7), Some(11)));
+                assert!(source.is_some());
+            }
+            error => panic!("unexpected error: {}", error),
+        }
+
+        let error = load_error(
+            "unknown.yaml",
+            "commands:\n  - name: a\n    program: rustc\n    arguments: [--version]\n",
+        );
+        match &error {
+            Error::Load { line, .. } => assert_eq!(*line, Some(4)),
+            error => panic!("unexpected error: {}", error),
+        }
+
+        let error = load_error(
+            "dependency.toml",
+            "[[commands]]\nname = \"a\"\nprogram = \"rustc\"\ndepends_on = [\"b\"]\n",
+        );
+        assert!(error
+            .to_string()
+            .contains(":2:8: command 'a' depends on 'b'"));
+
+        // serde_yaml does not tell where the values are
+        let error = load_error(
+            "dependency.yaml",
+            "commands:\n  - name: a\n    program: rustc\n    depends_on: [b]\n",
+        );
+        match &error {
+            Error::Load { line, column, .. } => assert_eq!((*line, *column), (None, None)),
+            error => panic!("unexpected error: {}", error),
+        }
+    }
+
+    #[test]
+    fn invalid_timeout() {
+        for timeout in ["-1", "1e30", "nan"] {
+            let error = load_error(
+                "timeout.toml",
+                &format!(
+                    "[[commands]]\nname = \"a\"\nprogram = \"rustc\"\ntimeout = {}\n",
+                    timeout
+                ),
+            );
+            match &error {
+                Error::Load {
+                    line,
+                    column,
+                    reason,
+                    ..
+                } => {
+                    assert_eq!((*line, *column), (Some(4), Some(11)));
+                    assert!(
+                        reason.starts_with("invalid timeout of command 'a'"),
+                        "{}",
+                        reason
+                    );
+                }
+                error => panic!("unexpected error: {}", error),
+            }
+        }
+    }
+
+    #[test]
+    fn load_restart() -> Result<()> {
+        let path = TempPath::file(
+            "restart.toml",
+            r#"
+[[commands]]
+name = "server"
+program = "rustc"
+restart = { policy = "on-failure", max_restarts = 5, window = 30, initial_delay = 0.5 }
+
+[[commands]]
+name = "version"
+program = "rustc"
+"#,
+        );
+        let definitions = Definitions::load(&path)?;
+
+        assert_eq!(
+            definitions.commands()[0].restart(),
+            Some(
+                RestartPolicy::on_failure()
+                    .max_restarts(5, Duration::from_secs(30))
+                    .backoff(Duration::from_millis(500), RestartPolicy::DEFAULT_MAX_DELAY)
+            )
+        );
+        assert_eq!(definitions.commands()[1].restart(), None);
+        Ok(())
+    }
+
+    #[test]
+    fn invalid_restart() {
+        let error = load_error(
+            "restart.toml",
+            "[[commands]]\nname = \"a\"\nprogram = \"rustc\"\nrestart = { policy = \"always\", window = 10 }\n",
+        );
+        match &error {
+            Error::Load {
+                line,
+                column,
+                reason,
+                ..
+            } => {
+                assert_eq!((*line, *column), (Some(4), Some(11)));
+                assert!(reason.contains("requires max_restarts"), "{}", reason);
+            }
+            error => panic!("unexpected error: {}", error),
+        }
+
+        let error = load_error(
+            "restart.yaml",
+            "commands:\n  - name: a\n    program: rustc\n    restart: { policy: sometimes }\n",
+        );
+        assert!(
+            error.to_string().contains("unknown variant `sometimes`"),
+            "{}",
+            error
+        );
+    }
+}
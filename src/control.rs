@@ -1,6 +1,7 @@
-use crate::{Capture, Command, Result, SendStdin};
+use crate::{Capture, ChildStderr, ChildStdin, ChildStdout, Command, Result, SendStdin};
+#[cfg(target_os = "linux")]
+use crate::{Error, ProcessStats, SampleStats};
 use tokio_codec::{Decoder, Encoder, FramedRead, FramedWrite};
-use tokio_process::{ChildStderr, ChildStdin, ChildStdout};
 
 /// [`Process`] control trait, access Program ID, the command line or kill the
 /// running process
@@ -17,6 +18,38 @@ pub trait Control: Sized {
     ///
     /// this is equivalent to `SIGKILL` on unix platform
     fn kill(&mut self) -> Result<()>;
+
+    /// ask the process to finish, giving it a chance to clean up
+    ///
+    /// this is equivalent to `SIGTERM` on unix platform, the process is
+    /// killed on the other platforms.
+    fn terminate(&mut self) -> Result<()> {
+        self.kill()
+    }
+
+    /// read the current [`ProcessStats`] of the running process
+    ///
+    /// returns `None` if the process has already exited.
+    ///
+    /// [`ProcessStats`]: ./struct.ProcessStats.html
+    #[cfg(target_os = "linux")]
+    fn stats(&self) -> Result<Option<ProcessStats>> {
+        ProcessStats::read(self.id()).map_err(|source| Error::Stats {
+            command: Box::new(self.command().clone()),
+            id: self.id(),
+            source,
+        })
+    }
+
+    /// sample the [`ProcessStats`] of the running process every `period`
+    ///
+    /// the stream requires a `tokio` runtime with a timer.
+    ///
+    /// [`ProcessStats`]: ./struct.ProcessStats.html
+    #[cfg(target_os = "linux")]
+    fn sample_stats(&self, period: std::time::Duration) -> SampleStats {
+        SampleStats::new(self, period)
+    }
 }
 
 /// Access the standard input of a running [`Process`]
@@ -0,0 +1,304 @@
+use crate::{
+    pre_exec::{Reporter, Step},
+    Command, Error, Result,
+};
+use std::{ffi::CString, fmt, io, os::unix::process::CommandExt as _};
+
+/// the user and groups a command runs as, switched to before `exec`
+#[derive(Debug, Clone, Default, PartialEq, Eq, Hash)]
+pub(crate) struct Credentials {
+    uid: Option<Id>,
+    gid: Option<Id>,
+    groups: Option<Vec<u32>>,
+}
+
+/// a user or group id, with the name it was resolved from
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+struct Id {
+    id: u32,
+    name: Option<String>,
+}
+
+impl Credentials {
+    pub(crate) fn uid(&mut self, uid: u32) {
+        self.uid = Some(Id {
+            id: uid,
+            name: None,
+        });
+    }
+
+    pub(crate) fn gid(&mut self, gid: u32) {
+        self.gid = Some(Id {
+            id: gid,
+            name: None,
+        });
+    }
+
+    pub(crate) fn groups(&mut self, groups: Vec<u32>) {
+        self.groups = Some(groups);
+    }
+
+    /// resolve the user in the passwd database, its primary group and
+    /// its supplementary groups are used too
+    pub(crate) fn user(&mut self, name: &str) -> Result<()> {
+        let unknown = || Error::UnknownUser {
+            name: name.to_owned(),
+        };
+        let c_name = CString::new(name).map_err(|_| unknown())?;
+
+        let (uid, gid) = lookup(|passwd: &mut libc::passwd, buffer, result| unsafe {
+            libc::getpwnam_r(
+                c_name.as_ptr(),
+                passwd,
+                buffer.as_mut_ptr(),
+                buffer.len(),
+                result,
+            )
+        })?
+        .map(|passwd| (passwd.pw_uid, passwd.pw_gid))
+        .ok_or_else(unknown)?;
+
+        let mut groups: Vec<libc::gid_t> = vec![0; 16];
+        loop {
+            let mut count = groups.len() as libc::c_int;
+            let found = unsafe {
+                libc::getgrouplist(
+                    c_name.as_ptr(),
+                    gid as _,
+                    groups.as_mut_ptr() as *mut _,
+                    &mut count,
+                )
+            };
+            if found >= 0 {
+                groups.truncate(count as usize);
+                break;
+            }
+            groups.resize((count as usize).max(groups.len() * 2), 0);
+        }
+
+        self.uid = Some(Id {
+            id: uid,
+            name: Some(name.to_owned()),
+        });
+        self.gid = Some(Id {
+            id: gid,
+            name: None,
+        });
+        self.groups = Some(groups);
+        Ok(())
+    }
+
+    /// resolve the group in the group database
+    pub(crate) fn group(&mut self, name: &str) -> Result<()> {
+        let unknown = || Error::UnknownGroup {
+            name: name.to_owned(),
+        };
+        let c_name = CString::new(name).map_err(|_| unknown())?;
+
+        let gid = lookup(|group: &mut libc::group, buffer, result| unsafe {
+            libc::getgrnam_r(
+                c_name.as_ptr(),
+                group,
+                buffer.as_mut_ptr(),
+                buffer.len(),
+                result,
+            )
+        })?
+        .map(|group| group.gr_gid)
+        .ok_or_else(unknown)?;
+
+        self.gid = Some(Id {
+            id: gid,
+            name: Some(name.to_owned()),
+        });
+        Ok(())
+    }
+
+    /// the `USER=` and `GROUP=` displayed with the command
+    pub(crate) fn display(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        if let Some(uid) = &self.uid {
+            write!(f, "USER={} ", uid)?;
+        }
+        if let Some(gid) = &self.gid {
+            if gid.name.is_some() || self.uid.as_ref().is_none_or(|uid| uid.name.is_none()) {
+                write!(f, "GROUP={} ", gid)?;
+            }
+        }
+        Ok(())
+    }
+
+    pub(crate) fn is_set(&self) -> bool {
+        self.uid.is_some() || self.gid.is_some() || self.groups.is_some()
+    }
+
+    /// switch the groups and the user in the child, before `exec`
+    pub(crate) fn apply(&self, command: &mut std::process::Command, reporter: Reporter) {
+        if !self.is_set() {
+            return;
+        }
+
+        let uid = self.uid.as_ref().map(|uid| uid.id as libc::uid_t);
+        let gid = self.gid.as_ref().map(|gid| gid.id as libc::gid_t);
+        let groups: Option<Vec<libc::gid_t>> = self
+            .groups
+            .as_ref()
+            .map(|groups| groups.iter().map(|group| *group as libc::gid_t).collect());
+        // like `std::process::Command::uid`, root drops its supplementary
+        // groups when switching user without explicit groups
+        let drop_groups = uid.is_some() && groups.is_none();
+
+        // only async-signal-safe functions are called in the child
+        unsafe {
+            command.pre_exec(move || {
+                if let Some(groups) = &groups {
+                    if libc::setgroups(groups.len() as _, groups.as_ptr()) != 0 {
+                        return Err(reporter.fail(Step::Groups));
+                    }
+                } else if drop_groups && libc::getuid() == 0 {
+                    // fails in a user namespace where `setgroups` is
+                    // denied, there are no groups to drop then
+                    let _ = libc::setgroups(0, std::ptr::null());
+                }
+                if let Some(gid) = gid {
+                    if libc::setgid(gid) != 0 {
+                        return Err(reporter.fail(Step::Gid));
+                    }
+                }
+                if let Some(uid) = uid {
+                    if libc::setuid(uid) != 0 {
+                        return Err(reporter.fail(Step::Uid));
+                    }
+                }
+                Ok(())
+            });
+        }
+    }
+
+    /// the error of the `step` of the switch that failed
+    pub(crate) fn error(&self, command: &Command, step: Step, source: io::Error) -> Error {
+        let id = |id: &Option<Id>| id.as_ref().map_or(0, |id| id.id);
+        match step {
+            Step::Groups => Error::CannotSetGroups {
+                command: Box::new(command.clone()),
+                groups: self.groups.clone().unwrap_or_default(),
+                source,
+            },
+            Step::Gid => Error::CannotSetGid {
+                command: Box::new(command.clone()),
+                gid: id(&self.gid),
+                source,
+            },
+            Step::Uid => Error::CannotSetUid {
+                command: Box::new(command.clone()),
+                uid: id(&self.uid),
+                source,
+            },
+            _ => unreachable!("not a step of the switch"),
+        }
+    }
+}
+
+/// run a reentrant lookup of the passwd or group database, growing the
+/// buffer as needed, `None` if there is no such entry
+fn lookup<T, F>(mut get: F) -> io::Result<Option<T>>
+where
+    F: FnMut(&mut T, &mut Vec<libc::c_char>, &mut *mut T) -> libc::c_int,
+{
+    let mut entry: T = unsafe { std::mem::zeroed() };
+    let mut buffer: Vec<libc::c_char> = vec![0; 1024];
+    loop {
+        let mut result = std::ptr::null_mut();
+        match get(&mut entry, &mut buffer, &mut result) {
+            0 if result.is_null() => return Ok(None),
+            0 => return Ok(Some(entry)),
+            libc::ERANGE => {
+                let size = buffer.len() * 2;
+                buffer.resize(size, 0);
+            }
+            error => return Err(io::Error::from_raw_os_error(error)),
+        }
+    }
+}
+
+impl fmt::Display for Id {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match &self.name {
+            Some(name) => f.write_str(name),
+            None => self.id.fmt(f),
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use crate::{Command, Error, Program, Result};
+    use futures::Future as _;
+
+    #[test]
+    #[ignore = "switching to another user requires root"]
+    fn switch_user_and_groups() -> Result<()> {
+        let mut command = Command::shell("id -u; id -g; id -G")?;
+        command.uid(65534).gid(65534).groups(Vec::new());
+        assert_eq!(
+            command.to_string(),
+            "USER=65534 GROUP=65534 id -u; id -g; id -G"
+        );
+
+        let output = command.spawn()?.output().wait()?;
+        assert_eq!(
+            String::from_utf8_lossy(&output.stdout),
+            "65534\n65534\n65534\n"
+        );
+
+        // the supplementary groups of root are dropped with the user only
+        let mut command = Command::shell("id -G")?;
+        command.uid(65534);
+        let output = command.spawn()?.output().wait()?;
+        assert_eq!(
+            String::from_utf8_lossy(&output.stdout),
+            format!("{}\n", unsafe { libc::getgid() })
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn distinct_switch_errors() -> Result<()> {
+        let mut command = Command::new(Program::new("true")?);
+        command.uid(u32::MAX);
+        match command.spawn().err().expect("-1 is not a valid user") {
+            Error::CannotSetUid { uid, .. } => assert_eq!(uid, u32::MAX),
+            error => panic!("unexpected error: {}", error),
+        }
+
+        command.gid(u32::MAX);
+        match command.spawn().err().expect("-1 is not a valid group") {
+            error @ Error::CannotSetGid { .. } => assert_eq!(
+                error.to_string(),
+                "cannot switch to group 4294967295 for command 'USER=4294967295 GROUP=4294967295 true'"
+            ),
+            error => panic!("unexpected error: {}", error),
+        }
+        Ok(())
+    }
+
+    #[test]
+    fn resolve_names() -> Result<()> {
+        let mut command = Command::new(Program::new("true")?);
+        command.user("root")?;
+        assert_eq!(command.to_string(), "USER=root true");
+        assert_eq!(
+            command.credentials().uid.as_ref().map(|uid| uid.id),
+            Some(0)
+        );
+
+        match command.user("bawawa-unknown-user").err() {
+            Some(Error::UnknownUser { name }) => assert_eq!(name, "bawawa-unknown-user"),
+            _ => panic!("the user is unknown"),
+        }
+        match command.group("bawawa-unknown-group").err() {
+            Some(Error::UnknownGroup { .. }) => {}
+            _ => panic!("the group is unknown"),
+        }
+        Ok(())
+    }
+}
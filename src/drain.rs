@@ -0,0 +1,136 @@
+use crate::OutputPipe;
+use futures::{try_ready, Async, Poll};
+use std::{io, mem};
+use tokio_io::AsyncRead;
+
+/// the output pipes of a process
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) enum Pipe {
+    Stdout,
+    Stderr,
+}
+
+/// split what is read from a non blocking pipe into lines
+///
+/// unlike `FramedRead` this does not hold the reader, it can then be
+/// used to drain the pipes still owned by the `Process`.
+#[derive(Debug, Default)]
+pub(crate) struct LineDrain {
+    partial: Vec<u8>,
+    finished: bool,
+}
+
+impl Pipe {
+    #[cfg(any(feature = "log", feature = "tracing"))]
+    pub(crate) fn name(self) -> &'static str {
+        match self {
+            Pipe::Stdout => "stdout",
+            Pipe::Stderr => "stderr",
+        }
+    }
+}
+
+impl LineDrain {
+    /// size of the buffer used to read from the pipe
+    const BUFFER_SIZE: usize = 4 * 1024;
+
+    /// longest partial line kept before it is flushed as a line anyway
+    const MAX_LINE_SIZE: usize = 64 * 1024;
+
+    pub(crate) fn new() -> Self {
+        LineDrain::default()
+    }
+
+    /// read everything available in `reader`, calling `on_line` for every
+    /// complete line.
+    ///
+    /// returns `Async::Ready` once the end of the stream has been reached,
+    /// any remaining partial line is flushed then.
+    pub(crate) fn poll_drain<R, F>(&mut self, reader: &mut R, mut on_line: F) -> Poll<(), io::Error>
+    where
+        R: AsyncRead,
+        F: FnMut(String),
+    {
+        if self.finished {
+            return Ok(Async::Ready(()));
+        }
+
+        let mut buffer = [0; Self::BUFFER_SIZE];
+        loop {
+            let read = try_ready!(reader.poll_read(&mut buffer));
+            if self.consume(&buffer[..read], &mut on_line) {
+                return Ok(Async::Ready(()));
+            }
+        }
+    }
+
+    /// read what is left in the pipe once the process has exited
+    ///
+    /// the pipe is read without waiting for it to be readable (see
+    /// `OutputPipe::read_remaining`) so the last lines printed before
+    /// exiting are not lost. Any partial line is flushed then.
+    pub(crate) fn drain_remaining<R, F>(&mut self, reader: &mut R, mut on_line: F) -> io::Result<()>
+    where
+        R: OutputPipe,
+        F: FnMut(String),
+    {
+        if self.finished {
+            return Ok(());
+        }
+
+        let mut buffer = [0u8; Self::BUFFER_SIZE];
+        loop {
+            let read = match reader.read_remaining(&mut buffer) {
+                Ok(read) => read,
+                Err(error) => match error.kind() {
+                    io::ErrorKind::Interrupted => continue,
+                    io::ErrorKind::WouldBlock => break,
+                    _ => return Err(error),
+                },
+            };
+            if self.consume(&buffer[..read], &mut on_line) {
+                return Ok(());
+            }
+        }
+
+        if !self.partial.is_empty() {
+            on_line(self.take_line());
+        }
+        Ok(())
+    }
+
+    /// split the bytes read into lines, an empty read is the end of the
+    /// stream: returns `true` then.
+    fn consume<F>(&mut self, bytes: &[u8], on_line: &mut F) -> bool
+    where
+        F: FnMut(String),
+    {
+        if bytes.is_empty() {
+            self.finished = true;
+            if !self.partial.is_empty() {
+                on_line(self.take_line());
+            }
+            return true;
+        }
+
+        for byte in bytes {
+            if *byte == b'\n' {
+                on_line(self.take_line());
+            } else {
+                self.partial.push(*byte);
+                if self.partial.len() >= Self::MAX_LINE_SIZE {
+                    on_line(self.take_line());
+                }
+            }
+        }
+        false
+    }
+
+    fn take_line(&mut self) -> String {
+        let mut line = mem::take(&mut self.partial);
+        if line.last() == Some(&b'\r') {
+            line.pop();
+        }
+        String::from_utf8_lossy(&line).into_owned()
+    }
+}
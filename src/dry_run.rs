@@ -0,0 +1,135 @@
+use crate::{Command, FakeProcess, Program, Spawned, Spawner};
+use std::{
+    io,
+    sync::{Arc, Mutex},
+};
+
+/// a [`Spawner`] that does not execute anything, only prints and records
+/// the commands that would have run
+///
+/// every spawned [`Process`] prints the default output (nothing unless
+/// set with [`process`]) and exits successfully right away. The commands
+/// are printed to the standard error output (unless [`quiet`]) and
+/// recorded with the `Display` of the [`Command`], the secrets are then
+/// redacted.
+///
+/// ```
+/// # use bawawa::{Command, DryRunSpawner, Process, Program, Error};
+/// # use futures::Future as _;
+/// let dry_run = DryRunSpawner::new();
+///
+/// // prints `dry run: kubectl apply -f deployment.yaml`
+/// let mut command = Command::new(Program::with_spawner("kubectl", &dry_run)?);
+/// command
+///     .arguments(&["apply", "-f", "deployment.yaml"])
+///     .spawner(dry_run.clone());
+///
+/// assert!(Process::spawn(command)?.wait()?.success());
+/// assert_eq!(dry_run.commands(), vec!["kubectl apply -f deployment.yaml"]);
+/// # Ok::<(), Error>(())
+/// ```
+///
+/// [`Spawner`]: ./trait.Spawner.html
+/// [`Process`]: ./struct.Process.html
+/// [`process`]: #method.process
+/// [`quiet`]: #method.quiet
+/// [`Command`]: ./struct.Command.html
+#[derive(Clone, Default)]
+pub struct DryRunSpawner {
+    process: FakeProcess,
+    commands: Arc<Mutex<Vec<String>>>,
+    quiet: bool,
+}
+
+impl DryRunSpawner {
+    /// create a spawner whose processes print nothing and succeed
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// set the stand-in run by every command instead of the actual program
+    ///
+    /// [`FakeProcess::expect_stdin`] is ignored as no input is expected
+    /// from a dry run.
+    ///
+    /// [`FakeProcess::expect_stdin`]: ./struct.FakeProcess.html#method.expect_stdin
+    pub fn process(mut self, process: FakeProcess) -> Self {
+        self.process = process.ignore_stdin();
+        self
+    }
+
+    /// only record the commands, without printing them
+    pub fn quiet(mut self, quiet: bool) -> Self {
+        self.quiet = quiet;
+        self
+    }
+
+    /// the command lines that would have run so far, in order
+    pub fn commands(&self) -> Vec<String> {
+        self.lock().clone()
+    }
+
+    fn lock(&self) -> std::sync::MutexGuard<'_, Vec<String>> {
+        self.commands
+            .lock()
+            .unwrap_or_else(|poisoned| poisoned.into_inner())
+    }
+}
+
+impl Spawner for DryRunSpawner {
+    fn spawn(&self, command: &Command) -> io::Result<Spawned> {
+        let line = command.to_string();
+        if !self.quiet {
+            eprintln!("dry run: {}", line);
+        }
+        self.lock().push(line);
+        Ok(self.process.clone().start())
+    }
+
+    /// every program is assumed to exist
+    fn check_program(&self, _: &Program) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::{Process, Result};
+    use futures::Future as _;
+
+    #[test]
+    fn default_output() -> Result<()> {
+        let dry_run = DryRunSpawner::new().process(FakeProcess::new().stdout("ok\n"));
+        let mut command = Command::new(Program::with_spawner("rm", &dry_run)?);
+        command
+            .current_working_directory("/".into())
+            .arguments(["-rf", "target"])
+            .spawner(dry_run.clone());
+
+        let output = Process::spawn(command.clone())?.output().wait()?;
+        assert!(output.status.success());
+        assert_eq!(output.stdout, b"ok\n");
+        Process::spawn(command)?.wait()?;
+
+        assert_eq!(
+            dry_run.commands(),
+            vec!["CWD=/ rm -rf target", "CWD=/ rm -rf target"]
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn redact_secrets() -> Result<()> {
+        let dry_run = DryRunSpawner::new().quiet(true);
+        let mut command = Command::new(Program::with_spawner("curl", &dry_run)?);
+        command
+            .arguments(["--user"])
+            .secret_argument("admin:hunter2")
+            .spawner(dry_run.clone());
+
+        Process::spawn(command)?.wait()?;
+        assert_eq!(dry_run.commands(), vec!["curl --user ***"]);
+        Ok(())
+    }
+}
@@ -0,0 +1,554 @@
+use crate::{Command, OutputTail, Program};
+use std::{fmt, io, path::PathBuf, process::ExitStatus, time::Duration};
+
+/// the errors of the crate, keeping the [`Command`] (and the PID or the
+/// last lines of its output when known) so they can be reported in a
+/// meaningful way
+///
+/// the underlying error, if any, is available with
+/// [`std::error::Error::source`]. New variants may be added without
+/// a major release.
+///
+/// [`Command`]: ./struct.Command.html
+#[derive(Debug)]
+#[non_exhaustive]
+pub enum Error {
+    /// input/output error outside of any process, displayed as is
+    Io(io::Error),
+
+    /// the program cannot be found or executed, see [`Program::new`]
+    ///
+    /// [`Program::new`]: ./struct.Program.html#method.new
+    InvalidProgramName { program: Program, source: io::Error },
+
+    /// the command cannot be started, see [`Command::spawn`]
+    ///
+    /// [`Command::spawn`]: ./struct.Command.html#method.spawn
+    CannotSpawnCommand {
+        command: Box<Command>,
+        source: io::Error,
+    },
+
+    /// the supplementary groups cannot be set before running the command,
+    /// see [`Command::groups`]
+    ///
+    /// [`Command::groups`]: ./struct.Command.html#method.groups
+    CannotSetGroups {
+        command: Box<Command>,
+        groups: Vec<u32>,
+        source: io::Error,
+    },
+
+    /// the group cannot be switched to before running the command, see
+    /// [`Command::gid`]
+    ///
+    /// [`Command::gid`]: ./struct.Command.html#method.gid
+    CannotSetGid {
+        command: Box<Command>,
+        gid: u32,
+        source: io::Error,
+    },
+
+    /// the user cannot be switched to before running the command, see
+    /// [`Command::uid`]
+    ///
+    /// [`Command::uid`]: ./struct.Command.html#method.uid
+    CannotSetUid {
+        command: Box<Command>,
+        uid: u32,
+        source: io::Error,
+    },
+
+    /// the command cannot be isolated in its [`Sandbox`]
+    ///
+    /// [`Sandbox`]: ./struct.Sandbox.html
+    CannotSandbox {
+        command: Box<Command>,
+        source: io::Error,
+    },
+
+    /// the [`SyscallFilter`] of the command cannot be installed
+    ///
+    /// [`SyscallFilter`]: ./struct.SyscallFilter.html
+    CannotFilterSyscalls {
+        command: Box<Command>,
+        source: io::Error,
+    },
+
+    /// a hook set with [`Command::pre_exec`] failed in the child
+    ///
+    /// [`Command::pre_exec`]: ./struct.Command.html#method.pre_exec
+    PreExec {
+        command: Box<Command>,
+        source: io::Error,
+    },
+
+    /// the system call does not exist on this architecture, see
+    /// [`SyscallFilter`]
+    ///
+    /// [`SyscallFilter`]: ./struct.SyscallFilter.html
+    UnknownSyscall { name: String },
+
+    /// the error number of [`Denied::Errno`] is not within `1..=4095`
+    ///
+    /// [`Denied::Errno`]: ./enum.Denied.html#variant.Errno
+    InvalidErrno { errno: i32 },
+
+    /// the user is not in the passwd database, see [`Command::user`]
+    ///
+    /// [`Command::user`]: ./struct.Command.html#method.user
+    UnknownUser { name: String },
+
+    /// the group is not in the group database, see [`Command::group`]
+    ///
+    /// [`Command::group`]: ./struct.Command.html#method.group
+    UnknownGroup { name: String },
+
+    /// the process cannot be signalled, see [`Control::kill`]
+    ///
+    /// [`Control::kill`]: ./trait.Control.html#method.kill
+    CannotKillProcess {
+        command: Box<Command>,
+        id: u32,
+        source: io::Error,
+    },
+
+    /// error while waiting for the process to finish
+    Poll {
+        command: Box<Command>,
+        id: u32,
+        tail: OutputTail,
+        source: io::Error,
+    },
+
+    /// the process did not exit successfully
+    ExitStatus {
+        command: Box<Command>,
+        id: u32,
+        status: ExitStatus,
+        tail: OutputTail,
+    },
+
+    /// the process was not ready after `timeout`, see [`Process::ready_when`]
+    ///
+    /// [`Process::ready_when`]: ./struct.Process.html#method.ready_when
+    NotReady {
+        command: Box<Command>,
+        id: u32,
+        timeout: Duration,
+        tail: OutputTail,
+    },
+
+    /// the process exited before it was ready, see
+    /// [`Process::ready_when`]
+    ///
+    /// [`Process::ready_when`]: ./struct.Process.html#method.ready_when
+    ExitedBeforeReady {
+        command: Box<Command>,
+        id: u32,
+        status: ExitStatus,
+        tail: OutputTail,
+    },
+
+    /// cannot read the [`ProcessStats`]
+    ///
+    /// [`ProcessStats`]: ./struct.ProcessStats.html
+    Stats {
+        command: Box<Command>,
+        id: u32,
+        source: io::Error,
+    },
+
+    /// the timer of a timeout, an interval or a delay failed, they need a
+    /// `tokio` runtime with a timer
+    Timer(tokio_timer::Error),
+
+    /// cannot read the output of the process, see [`Process::output`]
+    ///
+    /// [`Process::output`]: ./struct.Process.html#method.output
+    Output {
+        command: Box<Command>,
+        id: u32,
+        source: io::Error,
+    },
+
+    /// the [`Job`] has been cancelled
+    ///
+    /// [`Job`]: ./struct.Job.html
+    Cancelled { command: Box<Command> },
+
+    /// an attempt of a [`Retry`] did not exit successfully, caused by the
+    /// failure of the previous attempt if any
+    ///
+    /// [`Retry`]: ./struct.Retry.html
+    Attempt {
+        command: Box<Command>,
+        attempt: usize,
+        status: ExitStatus,
+        tail: OutputTail,
+        previous: Option<Box<Error>>,
+    },
+
+    /// the attempt of a [`Retry`] cannot be spawned, caused by the
+    /// failure of the previous attempt
+    ///
+    /// [`Retry`]: ./struct.Retry.html
+    CannotSpawnAttempt {
+        attempt: usize,
+        error: Box<Error>,
+        previous: Option<Box<Error>>,
+    },
+
+    /// none of the `attempts` of a [`Retry`] succeeded, caused by the
+    /// failure of the last attempt
+    ///
+    /// [`Retry`]: ./struct.Retry.html
+    Retry {
+        command: Box<Command>,
+        attempts: usize,
+        last: Box<Error>,
+    },
+
+    /// error of the decoder of a [`Capture`]
+    ///
+    /// [`Capture`]: ./struct.Capture.html
+    Capture(Box<dyn std::error::Error + Send + Sync>),
+
+    /// error of the encoder of a [`SendStdin`]
+    ///
+    /// [`SendStdin`]: ./struct.SendStdin.html
+    SendStdin(Box<dyn std::error::Error + Send + Sync>),
+
+    /// the [`CommandTemplate`] cannot be parsed
+    ///
+    /// [`CommandTemplate`]: ./struct.CommandTemplate.html
+    InvalidTemplate { template: String, reason: String },
+
+    /// no value was given for the placeholder `name` of the template
+    MissingVariable { template: String, name: String },
+
+    /// the value given for `name` has no placeholder in the template
+    UnknownVariable { template: String, name: String },
+
+    /// the file cannot be loaded, the line and column (starting at 1)
+    /// point to the invalid definition when known
+    Load {
+        path: PathBuf,
+        line: Option<usize>,
+        column: Option<usize>,
+        reason: String,
+        source: Option<Box<dyn std::error::Error + Send + Sync>>,
+    },
+}
+
+/// result with the [`Error`] of the crate
+///
+/// [`Error`]: ./enum.Error.html
+pub type Result<T> = std::result::Result<T, Error>;
+
+impl Error {
+    /// the command the error is about, if any
+    pub fn command(&self) -> Option<&Command> {
+        match self {
+            Error::CannotSpawnCommand { command, .. }
+            | Error::CannotSetGroups { command, .. }
+            | Error::CannotSetGid { command, .. }
+            | Error::CannotSetUid { command, .. }
+            | Error::CannotSandbox { command, .. }
+            | Error::CannotFilterSyscalls { command, .. }
+            | Error::PreExec { command, .. }
+            | Error::CannotKillProcess { command, .. }
+            | Error::Poll { command, .. }
+            | Error::ExitStatus { command, .. }
+            | Error::NotReady { command, .. }
+            | Error::ExitedBeforeReady { command, .. }
+            | Error::Stats { command, .. }
+            | Error::Output { command, .. }
+            | Error::Cancelled { command }
+            | Error::Attempt { command, .. }
+            | Error::Retry { command, .. } => Some(command),
+            Error::CannotSpawnAttempt { error, .. } => error.command(),
+            _ => None,
+        }
+    }
+
+    /// the Process ID of the process the error is about, if any
+    pub fn id(&self) -> Option<u32> {
+        match self {
+            Error::CannotKillProcess { id, .. }
+            | Error::Poll { id, .. }
+            | Error::ExitStatus { id, .. }
+            | Error::NotReady { id, .. }
+            | Error::ExitedBeforeReady { id, .. }
+            | Error::Stats { id, .. }
+            | Error::Output { id, .. } => Some(*id),
+            _ => None,
+        }
+    }
+
+    /// the error of the [`Spawner`] starting the command
+    ///
+    /// [`Spawner`]: ./trait.Spawner.html
+    pub(crate) fn cannot_spawn(command: &Command, source: io::Error) -> Self {
+        #[cfg(unix)]
+        let source = match crate::pre_exec::spawn_error(command, source) {
+            Ok(error) => return error,
+            Err(source) => source,
+        };
+        Error::CannotSpawnCommand {
+            command: Box::new(command.clone()),
+            source,
+        }
+    }
+
+    /// the last lines printed by the process, if any
+    pub fn output_tail(&self) -> Option<&OutputTail> {
+        match self {
+            Error::Poll { tail, .. }
+            | Error::ExitStatus { tail, .. }
+            | Error::NotReady { tail, .. }
+            | Error::ExitedBeforeReady { tail, .. }
+            | Error::Attempt { tail, .. } => Some(tail),
+            _ => None,
+        }
+    }
+}
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Error::Io(error) => error.fmt(f),
+            Error::InvalidProgramName { program, .. } => {
+                write!(f, "invalid program name: '{}'", program)
+            }
+            Error::CannotSpawnCommand { command, .. } => {
+                write!(f, "cannot spawn command: '{}'", command)
+            }
+            Error::CannotSetGroups {
+                command, groups, ..
+            } => write!(
+                f,
+                "cannot set the supplementary groups {:?} of command '{}'",
+                groups, command
+            ),
+            Error::CannotSetGid { command, gid, .. } => {
+                write!(
+                    f,
+                    "cannot switch to group {} for command '{}'",
+                    gid, command
+                )
+            }
+            Error::CannotSetUid { command, uid, .. } => {
+                write!(f, "cannot switch to user {} for command '{}'", uid, command)
+            }
+            Error::CannotSandbox { command, .. } => {
+                write!(f, "cannot sandbox command '{}'", command)
+            }
+            Error::CannotFilterSyscalls { command, .. } => {
+                write!(f, "cannot filter the system calls of command '{}'", command)
+            }
+            Error::PreExec { command, .. } => {
+                write!(f, "pre-exec hook failed for command '{}'", command)
+            }
+            Error::UnknownSyscall { name } => write!(f, "unknown system call '{}'", name),
+            Error::InvalidErrno { errno } => write!(f, "invalid error number {}", errno),
+            Error::UnknownUser { name } => write!(f, "unknown user '{}'", name),
+            Error::UnknownGroup { name } => write!(f, "unknown group '{}'", name),
+            Error::CannotKillProcess { command, id, .. } => {
+                write!(f, "cannot kill process '{}' ({})", id, command)
+            }
+            Error::Poll { command, tail, .. } => write!(
+                f,
+                "Error while waiting for command to finish: {}{}",
+                command,
+                tail.report()
+            ),
+            Error::ExitStatus {
+                command,
+                status,
+                tail,
+                ..
+            } => write!(
+                f,
+                "command '{}' did not exit successfully: {}{}",
+                command,
+                status,
+                tail.report()
+            ),
+            Error::NotReady {
+                command,
+                timeout,
+                tail,
+                ..
+            } => write!(
+                f,
+                "command '{}' not ready after {:?}{}",
+                command,
+                timeout,
+                tail.report()
+            ),
+            Error::ExitedBeforeReady {
+                command,
+                status,
+                tail,
+                ..
+            } => write!(
+                f,
+                "command '{}' exited before being ready: {}{}",
+                command,
+                status,
+                tail.report()
+            ),
+            Error::Stats { command, id, .. } => {
+                write!(f, "cannot read the stats of process '{}' ({})", id, command)
+            }
+            Error::Timer(_) => write!(f, "error in the timer"),
+            Error::Output { command, .. } => {
+                write!(f, "cannot read the output of '{}'", command)
+            }
+            Error::Cancelled { command } => write!(f, "command '{}' has been cancelled", command),
+            Error::Attempt {
+                command,
+                attempt,
+                status,
+                tail,
+                ..
+            } => write!(
+                f,
+                "attempt {} of '{}' did not exit successfully: {}{}",
+                attempt,
+                command,
+                status,
+                tail.report()
+            ),
+            Error::CannotSpawnAttempt { attempt, error, .. } => {
+                // the source of the error is the previous attempt, not
+                // the reason it cannot be spawned
+                write!(f, "attempt {}: {}", attempt, error)?;
+                match std::error::Error::source(error.as_ref()) {
+                    Some(reason) => write!(f, ": {}", reason),
+                    None => Ok(()),
+                }
+            }
+            Error::Retry {
+                command, attempts, ..
+            } => write!(
+                f,
+                "command '{}' did not succeed after {} attempt(s)",
+                command, attempts
+            ),
+            Error::Capture(_) => write!(f, "error in `capture`"),
+            Error::SendStdin(_) => write!(f, "error in `send_stdin`"),
+            Error::InvalidTemplate { template, reason } => {
+                write!(f, "invalid command template '{}': {}", template, reason)
+            }
+            Error::MissingVariable { template, name } => write!(
+                f,
+                "missing variable '{}' in command template '{}'",
+                name, template
+            ),
+            Error::UnknownVariable { template, name } => write!(
+                f,
+                "unknown variable '{}' for command template '{}'",
+                name, template
+            ),
+            Error::Load {
+                path,
+                line,
+                column,
+                reason,
+                ..
+            } => {
+                write!(f, "{}", path.display())?;
+                if let Some(line) = line {
+                    write!(f, ":{}", line)?;
+                    if let Some(column) = column {
+                        write!(f, ":{}", column)?;
+                    }
+                }
+                write!(f, ": {}", reason)
+            }
+        }
+    }
+}
+
+impl std::error::Error for Error {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            Error::Io(error) => Some(error),
+            Error::InvalidProgramName { source, .. }
+            | Error::CannotSpawnCommand { source, .. }
+            | Error::CannotSetGroups { source, .. }
+            | Error::CannotSetGid { source, .. }
+            | Error::CannotSetUid { source, .. }
+            | Error::CannotSandbox { source, .. }
+            | Error::CannotFilterSyscalls { source, .. }
+            | Error::PreExec { source, .. }
+            | Error::CannotKillProcess { source, .. }
+            | Error::Poll { source, .. }
+            | Error::Stats { source, .. }
+            | Error::Output { source, .. } => Some(source),
+            Error::Timer(source) => Some(source),
+            Error::Attempt { previous, .. } | Error::CannotSpawnAttempt { previous, .. } => {
+                previous
+                    .as_deref()
+                    .map(|previous| previous as &(dyn std::error::Error + 'static))
+            }
+            Error::Retry { last, .. } => Some(last.as_ref()),
+            Error::Capture(source) | Error::SendStdin(source) => Some(source.as_ref()),
+            Error::Load { source, .. } => source
+                .as_deref()
+                .map(|source| source as &(dyn std::error::Error + 'static)),
+            Error::ExitStatus { .. }
+            | Error::NotReady { .. }
+            | Error::ExitedBeforeReady { .. }
+            | Error::Cancelled { .. }
+            | Error::InvalidTemplate { .. }
+            | Error::MissingVariable { .. }
+            | Error::UnknownVariable { .. }
+            | Error::UnknownSyscall { .. }
+            | Error::InvalidErrno { .. }
+            | Error::UnknownUser { .. }
+            | Error::UnknownGroup { .. } => None,
+        }
+    }
+}
+
+impl From<io::Error> for Error {
+    fn from(error: io::Error) -> Self {
+        Error::Io(error)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::{FakeSpawner, Process};
+
+    #[test]
+    fn structured_error_with_source() {
+        fn assert_std_error<E: std::error::Error + Send + Sync + 'static>(_: &E) {}
+
+        let spawner = FakeSpawner::new();
+        let mut command = Command::new(Program::new_unchecked("missing".to_owned()));
+        command.spawner(spawner);
+
+        let error = Process::spawn(command.clone())
+            .err()
+            .expect("nothing is expected");
+        assert_std_error(&error);
+        assert_eq!(error.command(), Some(&command));
+        match &error {
+            Error::CannotSpawnCommand { source, .. } => {
+                assert_eq!(source.kind(), io::ErrorKind::NotFound)
+            }
+            error => panic!("unexpected error: {}", error),
+        }
+        let source = std::error::Error::source(&error).expect("the io error");
+        assert_eq!(source.to_string(), "unexpected command 'missing'");
+
+        let error = Error::from(io::Error::other("disk full"));
+        let source = std::error::Error::source(&error).expect("the io error");
+        assert_eq!(source.to_string(), "disk full");
+    }
+}
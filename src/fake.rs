@@ -0,0 +1,468 @@
+use crate::{
+    Child, ChildStderr, ChildStdin, ChildStdout, Command, OutputPipe, Program, Spawned, Spawner,
+};
+use futures::{
+    task::{self, Task},
+    try_ready, Async, Future as _, Poll,
+};
+use std::{
+    collections::VecDeque,
+    io::{self, Read, Write},
+    process::ExitStatus,
+    sync::{
+        atomic::{AtomicU32, Ordering},
+        Arc, Mutex,
+    },
+    time::{Duration, Instant},
+};
+use tokio_io::{AsyncRead, AsyncWrite};
+use tokio_timer::Delay;
+
+/// an in-memory [`Spawner`] to test code using [`Process`]es without
+/// running actual programs
+///
+/// the spawner is given the [`FakeProcess`]es it is expected to spawn, in
+/// order. Spawning a command that was not expected fails.
+///
+/// ```
+/// # use bawawa::{Command, FakeProcess, FakeSpawner, Process, Program, Error};
+/// # use futures::Future as _;
+/// let spawner = FakeSpawner::new();
+/// spawner.expect_program("git", FakeProcess::new().stdout("v1.2.3\n"));
+///
+/// let mut command = Command::new(Program::with_spawner("git", &spawner)?);
+/// command.arguments(&["describe", "--tags"]).spawner(spawner.clone());
+///
+/// let output = Process::spawn(command)?.output().wait()?;
+/// assert_eq!(output.stdout, b"v1.2.3\n");
+/// assert_eq!(spawner.spawned()[0].to_string(), "git describe --tags");
+/// # Ok::<(), Error>(())
+/// ```
+///
+/// [`Spawner`]: ./trait.Spawner.html
+/// [`Process`]: ./struct.Process.html
+/// [`FakeProcess`]: ./struct.FakeProcess.html
+#[derive(Clone, Default)]
+pub struct FakeSpawner {
+    state: Arc<Mutex<FakeState>>,
+}
+
+#[derive(Default)]
+struct FakeState {
+    expected: VecDeque<(Option<String>, FakeProcess)>,
+    spawned: Vec<Command>,
+}
+
+/// the scripted behavior of a process spawned by the [`FakeSpawner`]
+///
+/// by default the process prints nothing and exits successfully right
+/// away.
+///
+/// [`FakeSpawner`]: ./struct.FakeSpawner.html
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct FakeProcess {
+    stdout: Vec<u8>,
+    stderr: Vec<u8>,
+    exit_code: i32,
+    delay: Duration,
+    stdin: Option<Vec<u8>>,
+}
+
+struct FakeChild {
+    id: u32,
+    status: ExitStatus,
+    exit: Instant,
+    delay: Option<Delay>,
+    stdin: Option<(Vec<u8>, Arc<Mutex<FakeStdinState>>)>,
+    killed: bool,
+}
+
+/// what has been sent to a [`FakeStdin`]
+#[derive(Default)]
+pub(crate) struct FakeStdinState {
+    pub(crate) written: Vec<u8>,
+    pub(crate) closed: bool,
+    /// the task waiting for more input
+    pub(crate) task: Option<Task>,
+}
+
+/// a standard input keeping what has been sent to it
+pub(crate) struct FakeStdin(pub(crate) Arc<Mutex<FakeStdinState>>);
+
+struct FakeOutput(io::Cursor<Vec<u8>>);
+
+/// the fake Process IDs count down from here so they do not collide
+/// with the IDs of actual processes
+static NEXT_ID: AtomicU32 = AtomicU32::new(u32::MAX);
+
+impl FakeSpawner {
+    /// create a spawner not expecting any command yet
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// the next command spawned, whatever its program, runs `process`
+    pub fn expect(&self, process: FakeProcess) -> &Self {
+        self.lock().expected.push_back((None, process));
+        self
+    }
+
+    /// the next command spawned with the given program runs `process`
+    pub fn expect_program<P: Into<String>>(&self, program: P, process: FakeProcess) -> &Self {
+        self.lock()
+            .expected
+            .push_back((Some(program.into()), process));
+        self
+    }
+
+    /// the commands spawned so far
+    pub fn spawned(&self) -> Vec<Command> {
+        self.lock().spawned.clone()
+    }
+
+    /// the number of expected processes not spawned yet
+    pub fn remaining(&self) -> usize {
+        self.lock().expected.len()
+    }
+
+    fn lock(&self) -> std::sync::MutexGuard<'_, FakeState> {
+        self.state
+            .lock()
+            .unwrap_or_else(|poisoned| poisoned.into_inner())
+    }
+}
+
+impl Spawner for FakeSpawner {
+    fn spawn(&self, command: &Command) -> io::Result<Spawned> {
+        let mut state = self.lock();
+        let program: &str = command.program().as_ref();
+        let index = state
+            .expected
+            .iter()
+            .position(|(expected, _)| expected.as_deref().is_none_or(|name| name == program))
+            .ok_or_else(|| {
+                io::Error::new(
+                    io::ErrorKind::NotFound,
+                    format!("unexpected command '{}'", command),
+                )
+            })?;
+        let (_, process) = state.expected.remove(index).expect("expected process");
+        state.spawned.push(command.clone());
+        Ok(process.start())
+    }
+
+    /// the program exists if a process is expected for it
+    fn check_program(&self, program: &Program) -> io::Result<()> {
+        let program: &str = program.as_ref();
+        let expected = self
+            .lock()
+            .expected
+            .iter()
+            .any(|(expected, _)| expected.as_deref().is_none_or(|name| name == program));
+        if expected {
+            Ok(())
+        } else {
+            Err(io::Error::new(
+                io::ErrorKind::NotFound,
+                format!("program '{}' is not expected", program),
+            ))
+        }
+    }
+}
+
+impl FakeProcess {
+    pub fn new() -> Self {
+        FakeProcess {
+            stdout: Vec::new(),
+            stderr: Vec::new(),
+            exit_code: 0,
+            delay: Duration::from_secs(0),
+            stdin: None,
+        }
+    }
+
+    /// set what the process prints on its standard output
+    pub fn stdout<B: Into<Vec<u8>>>(mut self, stdout: B) -> Self {
+        self.stdout = stdout.into();
+        self
+    }
+
+    /// set what the process prints on its standard error output
+    pub fn stderr<B: Into<Vec<u8>>>(mut self, stderr: B) -> Self {
+        self.stderr = stderr.into();
+        self
+    }
+
+    /// set the exit code of the process
+    pub fn exit_code(mut self, exit_code: i32) -> Self {
+        self.exit_code = exit_code;
+        self
+    }
+
+    /// exit only after `delay`, this requires a `tokio` runtime with a timer
+    pub fn delay(mut self, delay: Duration) -> Self {
+        self.delay = delay;
+        self
+    }
+
+    /// expect the process to be sent exactly `stdin` on its standard input
+    ///
+    /// the process then exits only once its standard input is closed, and
+    /// fails to be waited for if it received something else.
+    pub fn expect_stdin<B: Into<Vec<u8>>>(mut self, stdin: B) -> Self {
+        self.stdin = Some(stdin.into());
+        self
+    }
+
+    /// do not expect anything on the standard input
+    pub(crate) fn ignore_stdin(mut self) -> Self {
+        self.stdin = None;
+        self
+    }
+
+    /// start the scripted process
+    pub(crate) fn start(self) -> Spawned {
+        let stdin = Arc::new(Mutex::new(FakeStdinState::default()));
+        Spawned {
+            child: Box::new(FakeChild {
+                id: fake_id(),
+                status: exit_status(self.exit_code),
+                exit: Instant::now() + self.delay,
+                delay: None,
+                stdin: self.stdin.map(|expected| (expected, stdin.clone())),
+                killed: false,
+            }),
+            stdin: Some(ChildStdin::new(FakeStdin(stdin))),
+            stdout: Some(ChildStdout::new(FakeOutput(io::Cursor::new(self.stdout)))),
+            stderr: Some(ChildStderr::new(FakeOutput(io::Cursor::new(self.stderr)))),
+        }
+    }
+}
+
+impl Default for FakeProcess {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Child for FakeChild {
+    #[inline]
+    fn id(&self) -> u32 {
+        self.id
+    }
+
+    fn poll_exit(&mut self) -> Poll<ExitStatus, io::Error> {
+        if self.killed {
+            return Ok(Async::Ready(killed_status()));
+        }
+
+        if Instant::now() < self.exit {
+            let exit = self.exit;
+            let delay = self.delay.get_or_insert_with(|| Delay::new(exit));
+            try_ready!(delay.poll().map_err(io::Error::other));
+        }
+
+        if let Some((expected, stdin)) = &self.stdin {
+            let mut stdin = stdin.lock().unwrap();
+            if !stdin.closed {
+                stdin.task = Some(task::current());
+                return Ok(Async::NotReady);
+            }
+            if stdin.written != *expected {
+                return Err(io::Error::new(
+                    io::ErrorKind::InvalidData,
+                    format!(
+                        "unexpected standard input: expected {:?}, received {:?}",
+                        String::from_utf8_lossy(expected),
+                        String::from_utf8_lossy(&stdin.written),
+                    ),
+                ));
+            }
+        }
+
+        Ok(Async::Ready(self.status))
+    }
+
+    fn kill(&mut self) -> io::Result<()> {
+        self.killed = true;
+        Ok(())
+    }
+}
+
+impl FakeStdin {
+    fn close(&mut self) {
+        let mut state = self
+            .0
+            .lock()
+            .unwrap_or_else(|poisoned| poisoned.into_inner());
+        state.closed = true;
+        if let Some(task) = state.task.take() {
+            task.notify();
+        }
+    }
+}
+
+impl Write for FakeStdin {
+    fn write(&mut self, buffer: &[u8]) -> io::Result<usize> {
+        let mut state = self.0.lock().unwrap();
+        state.written.extend_from_slice(buffer);
+        if let Some(task) = state.task.take() {
+            task.notify();
+        }
+        Ok(buffer.len())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+impl AsyncWrite for FakeStdin {
+    fn shutdown(&mut self) -> Poll<(), io::Error> {
+        self.close();
+        Ok(Async::Ready(()))
+    }
+}
+
+impl Drop for FakeStdin {
+    fn drop(&mut self) {
+        self.close();
+    }
+}
+
+impl Read for FakeOutput {
+    fn read(&mut self, buffer: &mut [u8]) -> io::Result<usize> {
+        self.0.read(buffer)
+    }
+}
+
+impl AsyncRead for FakeOutput {}
+
+impl OutputPipe for FakeOutput {}
+
+/// a Process ID for a process that is not actually running
+pub(crate) fn fake_id() -> u32 {
+    NEXT_ID.fetch_sub(1, Ordering::Relaxed)
+}
+
+#[cfg(unix)]
+pub(crate) fn exit_status(code: i32) -> ExitStatus {
+    use std::os::unix::process::ExitStatusExt as _;
+    ExitStatus::from_raw((code & 0xff) << 8)
+}
+
+#[cfg(unix)]
+pub(crate) fn signal_status(signal: i32) -> ExitStatus {
+    use std::os::unix::process::ExitStatusExt as _;
+    ExitStatus::from_raw(signal & 0x7f)
+}
+
+#[cfg(unix)]
+fn killed_status() -> ExitStatus {
+    signal_status(libc::SIGKILL)
+}
+
+#[cfg(windows)]
+pub(crate) fn exit_status(code: i32) -> ExitStatus {
+    use std::os::windows::process::ExitStatusExt as _;
+    ExitStatus::from_raw(code as u32)
+}
+
+#[cfg(windows)]
+fn killed_status() -> ExitStatus {
+    exit_status(1)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::{Control as _, Error, Process, Result};
+
+    fn command(spawner: &FakeSpawner, program: &str) -> Result<Command> {
+        let mut command = Command::new(Program::with_spawner(program, spawner)?);
+        command.spawner(spawner.clone());
+        Ok(command)
+    }
+
+    #[test]
+    fn scripted_output_and_status() -> Result<()> {
+        let spawner = FakeSpawner::new();
+        spawner.expect_program(
+            "compiler",
+            FakeProcess::new()
+                .stdout("compiling\n")
+                .stderr("error: oops\n")
+                .exit_code(2),
+        );
+        let mut command = command(&spawner, "compiler")?;
+        command.argument("main.rs");
+
+        let output = Process::spawn(command)?.output().wait()?;
+
+        assert_eq!(output.status.code(), Some(2));
+        assert_eq!(output.stdout, b"compiling\n");
+        assert_eq!(output.stderr, b"error: oops\n");
+        assert_eq!(spawner.spawned()[0].to_string(), "compiler main.rs");
+        assert_eq!(spawner.remaining(), 0);
+
+        Ok(())
+    }
+
+    #[test]
+    fn unexpected_command() -> Result<()> {
+        let spawner = FakeSpawner::new();
+        spawner.expect_program("expected", FakeProcess::new());
+
+        assert!(Program::with_spawner("unexpected", &spawner).is_err());
+
+        let command = command(&spawner, "expected")?;
+        Process::spawn(command.clone())?.wait()?;
+        match Process::spawn(command)
+            .err()
+            .expect("nothing is expected anymore")
+        {
+            Error::CannotSpawnCommand { .. } => Ok(()),
+            error => panic!("unexpected error: {}", error),
+        }
+    }
+
+    #[test]
+    fn expected_stdin() -> Result<()> {
+        let spawner = FakeSpawner::new();
+        spawner
+            .expect(FakeProcess::new().expect_stdin("yes\n"))
+            .expect(FakeProcess::new().expect_stdin("yes\n"));
+
+        let mut process = Process::spawn(command(&spawner, "prompt")?)?;
+        process.stdin().as_mut().unwrap().write_all(b"yes\n")?;
+        process.stdin().take();
+        assert!(process.wait()?.success());
+
+        let mut process = Process::spawn(command(&spawner, "prompt")?)?;
+        process.stdin().as_mut().unwrap().write_all(b"no\n")?;
+        process.stdin().take();
+        assert!(process.wait().is_err());
+
+        Ok(())
+    }
+
+    #[test]
+    fn delay_and_kill() -> Result<()> {
+        let spawner = FakeSpawner::new();
+        spawner
+            .expect(FakeProcess::new().delay(Duration::from_millis(100)))
+            .expect(FakeProcess::new().delay(Duration::from_secs(60)));
+        let mut runtime = tokio::runtime::Runtime::new().unwrap();
+
+        let start = Instant::now();
+        let process = Process::spawn(command(&spawner, "slow")?)?;
+        assert!(runtime.block_on(process)?.success());
+        assert!(start.elapsed() >= Duration::from_millis(100));
+
+        let mut process = Process::spawn(command(&spawner, "slow")?)?;
+        process.kill()?;
+        assert!(!runtime.block_on(process)?.success());
+
+        Ok(())
+    }
+}
@@ -0,0 +1,274 @@
+use crate::{drain::Pipe, Command, Program};
+use std::path::Path;
+
+/// verbosity level of the forwarded lines
+///
+/// maps to the levels of the `log` and `tracing` crates.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub enum Level {
+    Error,
+    Warn,
+    Info,
+    Debug,
+    Trace,
+}
+
+/// settings to forward the output of a [`Process`] to the logs
+///
+/// By default the standard output is forwarded with the level [`Level::Info`]
+/// and the standard error output with the level [`Level::Warn`].
+///
+/// If both the `log` and the `tracing` features are enabled, the lines are
+/// forwarded to `tracing` only (use the `log` feature of `tracing` to forward
+/// them to `log` too).
+///
+/// [`Process`]: ./struct.Process.html
+/// [`Level::Info`]: ./enum.Level.html#variant.Info
+/// [`Level::Warn`]: ./enum.Level.html#variant.Warn
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct ForwardOutput {
+    stdout: Option<Level>,
+    stderr: Option<Level>,
+}
+
+/// emit the lines of a running process
+pub(crate) struct Forwarder {
+    options: ForwardOutput,
+    target: String,
+    pid: u32,
+    command: String,
+}
+
+impl ForwardOutput {
+    pub fn new() -> Self {
+        ForwardOutput {
+            stdout: Some(Level::Info),
+            stderr: Some(Level::Warn),
+        }
+    }
+
+    /// set the level of the standard output lines, `None` to not forward them
+    pub fn stdout(mut self, level: Option<Level>) -> Self {
+        self.stdout = level;
+        self
+    }
+
+    /// set the level of the standard error lines, `None` to not forward them
+    pub fn stderr(mut self, level: Option<Level>) -> Self {
+        self.stderr = level;
+        self
+    }
+
+    #[inline]
+    pub(crate) fn forwards(&self, pipe: Pipe) -> bool {
+        self.level(pipe).is_some()
+    }
+
+    #[inline]
+    fn level(&self, pipe: Pipe) -> Option<Level> {
+        match pipe {
+            Pipe::Stdout => self.stdout,
+            Pipe::Stderr => self.stderr,
+        }
+    }
+}
+
+impl Default for ForwardOutput {
+    fn default() -> Self {
+        ForwardOutput::new()
+    }
+}
+
+impl Forwarder {
+    pub(crate) fn new(options: ForwardOutput, command: &Command, pid: u32) -> Self {
+        Forwarder {
+            options,
+            target: target(command.program()),
+            pid,
+            command: command.to_string(),
+        }
+    }
+
+    pub(crate) fn forward(&self, pipe: Pipe, line: &str) {
+        if let Some(level) = self.options.level(pipe) {
+            self.emit(level, pipe, line)
+        }
+    }
+
+    #[cfg(feature = "tracing")]
+    fn emit(&self, level: Level, pipe: Pipe, line: &str) {
+        // `tracing` requires the target and the level to be constants,
+        // the program name is then given as a field
+        macro_rules! emit {
+            ($level:expr) => {
+                tracing::event!(
+                    target: "bawawa",
+                    $level,
+                    program = %self.target,
+                    pid = self.pid,
+                    command = %self.command,
+                    stream = pipe.name(),
+                    "{}",
+                    line
+                )
+            };
+        }
+
+        match level {
+            Level::Error => emit!(tracing::Level::ERROR),
+            Level::Warn => emit!(tracing::Level::WARN),
+            Level::Info => emit!(tracing::Level::INFO),
+            Level::Debug => emit!(tracing::Level::DEBUG),
+            Level::Trace => emit!(tracing::Level::TRACE),
+        }
+    }
+
+    #[cfg(not(feature = "tracing"))]
+    fn emit(&self, level: Level, pipe: Pipe, line: &str) {
+        let level = match level {
+            Level::Error => log::Level::Error,
+            Level::Warn => log::Level::Warn,
+            Level::Info => log::Level::Info,
+            Level::Debug => log::Level::Debug,
+            Level::Trace => log::Level::Trace,
+        };
+
+        log::log!(
+            target: &self.target,
+            level,
+            pid = self.pid,
+            command:% = self.command,
+            stream = pipe.name();
+            "{}",
+            line
+        );
+    }
+}
+
+/// the target of the forwarded lines: the file name of the program
+fn target(program: &Program) -> String {
+    let program: &str = program.as_ref();
+    Path::new(program)
+        .file_name()
+        .and_then(|name| name.to_str())
+        .unwrap_or(program)
+        .to_owned()
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::{Process, Result};
+    use futures::Future as _;
+    use std::sync::Mutex;
+
+    /// a `rustc` failing with an error on its standard error output
+    fn failing_rustc() -> Result<Command> {
+        let mut cmd = Command::new(Program::new("rustc")?);
+        cmd.arguments(["file-that-does-not-exist"])
+            .forward_output(ForwardOutput::new().stderr(Some(Level::Error)));
+        Ok(cmd)
+    }
+
+    #[cfg(not(feature = "tracing"))]
+    struct Capture(Mutex<Vec<(log::Level, String, String)>>);
+
+    #[cfg(not(feature = "tracing"))]
+    impl log::Log for Capture {
+        fn enabled(&self, _: &log::Metadata) -> bool {
+            true
+        }
+
+        fn log(&self, record: &log::Record) {
+            self.0.lock().unwrap().push((
+                record.level(),
+                record.target().to_owned(),
+                record.args().to_string(),
+            ));
+        }
+
+        fn flush(&self) {}
+    }
+
+    #[cfg(not(feature = "tracing"))]
+    static LOGGER: Capture = Capture(Mutex::new(Vec::new()));
+
+    #[cfg(not(feature = "tracing"))]
+    #[test]
+    fn forward_stderr_to_log() -> Result<()> {
+        log::set_logger(&LOGGER).unwrap();
+        log::set_max_level(log::LevelFilter::Trace);
+
+        Process::spawn(failing_rustc()?)?.wait()?;
+
+        let records = LOGGER.0.lock().unwrap();
+        assert!(records.iter().any(|(level, target, line)| {
+            *level == log::Level::Error
+                && target == "rustc"
+                && line.contains("file-that-does-not-exist")
+        }));
+
+        Ok(())
+    }
+
+    /// collect the level and the fields of the events
+    #[cfg(feature = "tracing")]
+    #[derive(Clone, Default)]
+    struct Events(std::sync::Arc<Mutex<Vec<(tracing::Level, String)>>>);
+
+    #[cfg(feature = "tracing")]
+    impl<S: tracing::Subscriber> tracing_subscriber::Layer<S> for Events {
+        fn on_event(
+            &self,
+            event: &tracing::Event<'_>,
+            _: tracing_subscriber::layer::Context<'_, S>,
+        ) {
+            struct Fields<'a>(&'a mut String);
+
+            impl<'a> tracing::field::Visit for Fields<'a> {
+                fn record_debug(
+                    &mut self,
+                    field: &tracing::field::Field,
+                    value: &dyn std::fmt::Debug,
+                ) {
+                    self.0.push_str(&format!("{}={:?} ", field.name(), value));
+                }
+            }
+
+            let mut fields = String::new();
+            event.record(&mut Fields(&mut fields));
+            self.0
+                .lock()
+                .unwrap()
+                .push((*event.metadata().level(), fields));
+        }
+    }
+
+    #[cfg(feature = "tracing")]
+    #[test]
+    fn forward_stderr_to_tracing() -> Result<()> {
+        use crate::Control as _;
+        use tracing_subscriber::layer::SubscriberExt as _;
+
+        let events = Events::default();
+        let subscriber = tracing_subscriber::registry().with(events.clone());
+        let process = tracing::subscriber::with_default(subscriber, || -> Result<_> {
+            let mut process = Process::spawn(failing_rustc()?)?;
+            let status = (&mut process).wait()?;
+            Ok((process.id(), status))
+        })?;
+        let (pid, status) = process;
+        assert!(!status.success());
+
+        let events = events.0.lock().unwrap();
+        assert!(events.iter().any(|(level, fields)| {
+            *level == tracing::Level::ERROR
+                && fields.contains("file-that-does-not-exist")
+                && fields.contains("program=rustc ")
+                && fields.contains(&format!("pid={} ", pid))
+                && fields.contains("stream=\"stderr\" ")
+        }));
+
+        Ok(())
+    }
+}
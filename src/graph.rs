@@ -0,0 +1,544 @@
+use crate::{
+    output::TailOutput, pool::Queued, Command, Error, OutputTail, ProcessPool, TailOptions,
+};
+use futures::{Async, Future, Poll};
+use std::{
+    fmt,
+    process::ExitStatus,
+    time::{Duration, Instant},
+};
+use tokio_timer::Delay;
+
+/// graph of [`Command`]s depending on the success of other commands
+///
+/// a task can only depend on tasks already added to the graph, so the
+/// graph cannot contain cycles. Once [`run`], every task is spawned as
+/// soon as all of its dependencies have succeeded, within the limit of
+/// the [`ProcessPool`].
+///
+/// ```
+/// # use bawawa::{Command, Program, TaskGraph, Error};
+/// # use futures::Future as _;
+/// let mut graph = TaskGraph::new();
+///
+/// let mut version = Command::new(Program::new("rustc")?);
+/// version.argument("--version");
+/// let version = graph.task("version", version, &[]);
+///
+/// let mut help = Command::new(Program::new("rustc")?);
+/// help.argument("--help");
+/// graph.task("help", help, &[version]);
+///
+/// let report = graph.run().wait()?;
+/// assert!(report.success(), "{}", report);
+/// # Ok::<(), Error>(())
+/// ```
+///
+/// [`Command`]: ./struct.Command.html
+/// [`ProcessPool`]: ./struct.ProcessPool.html
+/// [`run`]: ./struct.TaskGraph.html#method.run
+pub struct TaskGraph {
+    tasks: Vec<Task>,
+    pool: Option<ProcessPool>,
+    fail_fast: bool,
+    stderr_tail: TailOptions,
+}
+
+struct Task {
+    name: String,
+    command: Command,
+    dependencies: Vec<TaskId>,
+    timeout: Option<Duration>,
+}
+
+/// identifier of a task of a [`TaskGraph`]
+///
+/// [`TaskGraph`]: ./struct.TaskGraph.html
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, PartialOrd, Ord)]
+pub struct TaskId(usize);
+
+/// final status of a task of a [`TaskGraph`]
+///
+/// [`TaskGraph`]: ./struct.TaskGraph.html
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum TaskStatus {
+    /// the command exited successfully
+    Succeeded,
+    /// the command did not exit successfully
+    Failed(ExitStatus),
+    /// the command could not be run
+    Error(String),
+    /// the task did not run because one of its dependencies did not succeed
+    Skipped,
+    /// the task has been stopped (or not started) because another
+    /// task failed while failing fast
+    Cancelled,
+    /// the command has been killed as it was still running after its
+    /// timeout (see [`TaskGraph::timeout`])
+    ///
+    /// [`TaskGraph::timeout`]: ./struct.TaskGraph.html#method.timeout
+    TimedOut(Duration),
+}
+
+/// report of a task of a [`TaskGraph`]
+///
+/// [`TaskGraph`]: ./struct.TaskGraph.html
+#[derive(Debug, Clone)]
+pub struct TaskReport {
+    pub id: TaskId,
+    pub name: String,
+    pub command: Command,
+    pub status: TaskStatus,
+    /// how long the command has been running, `None` if it did not run
+    pub duration: Option<Duration>,
+    /// the last lines of the standard error output of the command
+    pub stderr: OutputTail,
+}
+
+/// report of the execution of a [`TaskGraph`], the tasks are in the
+/// order they have been added to the graph
+///
+/// [`TaskGraph`]: ./struct.TaskGraph.html
+#[derive(Debug, Clone)]
+pub struct GraphReport {
+    tasks: Vec<TaskReport>,
+}
+
+/// future returned by [`TaskGraph::run`]
+///
+/// once complete, polling it again returns the same report.
+///
+/// [`TaskGraph::run`]: ./struct.TaskGraph.html#method.run
+pub struct RunGraph {
+    tasks: Vec<Task>,
+    states: Vec<State>,
+    pool: ProcessPool,
+    fail_fast: bool,
+    stderr_tail: TailOptions,
+    stopping: bool,
+}
+
+enum State {
+    Pending,
+    /// the job, when its process started and the deadline of its timeout
+    Running(Box<Queued<TailOutput>>, Option<Instant>, Option<Delay>),
+    Done(TaskStatus, Option<Duration>, OutputTail),
+}
+
+impl TaskGraph {
+    /// number of lines of the standard error output kept by default
+    const DEFAULT_STDERR_LINES: usize = 10;
+
+    /// create an empty graph
+    pub fn new() -> Self {
+        TaskGraph {
+            tasks: Vec::new(),
+            pool: None,
+            fail_fast: true,
+            stderr_tail: TailOptions::new(Self::DEFAULT_STDERR_LINES),
+        }
+    }
+
+    /// add a task running `command` once all the `dependencies` succeeded
+    ///
+    /// # panics
+    ///
+    /// if one of the dependencies is not a task of this graph
+    pub fn task<S: Into<String>>(
+        &mut self,
+        name: S,
+        command: Command,
+        dependencies: &[TaskId],
+    ) -> TaskId {
+        let id = TaskId(self.tasks.len());
+        assert!(
+            dependencies.iter().all(|dependency| *dependency < id),
+            "dependencies must be tasks of the graph"
+        );
+        self.tasks.push(Task {
+            name: name.into(),
+            command,
+            dependencies: dependencies.to_vec(),
+            timeout: None,
+        });
+        id
+    }
+
+    /// kill the command of the `task` if it is still running after
+    /// `timeout`, its status is then `TaskStatus::TimedOut`
+    ///
+    /// the timeouts require a `tokio` runtime with a timer.
+    ///
+    /// # panics
+    ///
+    /// if the task is not a task of this graph
+    pub fn timeout(&mut self, task: TaskId, timeout: Duration) -> &mut Self {
+        self.tasks[task.0].timeout = Some(timeout);
+        self
+    }
+
+    /// run the commands in the given pool, by default a new pool running
+    /// as many processes as there are available CPUs
+    pub fn pool(mut self, pool: ProcessPool) -> Self {
+        self.pool = Some(pool);
+        self
+    }
+
+    /// run at most `limit` commands at a time
+    pub fn max_parallelism(self, limit: usize) -> Self {
+        self.pool(ProcessPool::new(limit))
+    }
+
+    /// stop everything as soon as a task fails (the default), otherwise
+    /// keep running the tasks that do not depend on the failed one
+    pub fn fail_fast(mut self, fail_fast: bool) -> Self {
+        self.fail_fast = fail_fast;
+        self
+    }
+
+    /// set how much of the standard error output of each task is kept
+    /// in the report
+    pub fn stderr_tail(mut self, options: TailOptions) -> Self {
+        self.stderr_tail = options;
+        self
+    }
+
+    /// run the graph
+    pub fn run(self) -> RunGraph {
+        RunGraph {
+            states: self.tasks.iter().map(|_| State::Pending).collect(),
+            tasks: self.tasks,
+            pool: self.pool.unwrap_or_default(),
+            fail_fast: self.fail_fast,
+            stderr_tail: self.stderr_tail,
+            stopping: false,
+        }
+    }
+}
+
+impl Default for TaskGraph {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl TaskStatus {
+    /// tell if the command exited successfully
+    #[inline]
+    pub fn success(&self) -> bool {
+        *self == TaskStatus::Succeeded
+    }
+}
+
+impl fmt::Display for TaskStatus {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            TaskStatus::Succeeded => f.write_str("succeeded"),
+            TaskStatus::Failed(status) => write!(f, "failed: {}", status),
+            TaskStatus::Error(error) => write!(f, "error: {}", error),
+            TaskStatus::Skipped => f.write_str("skipped"),
+            TaskStatus::Cancelled => f.write_str("cancelled"),
+            TaskStatus::TimedOut(timeout) => write!(f, "timed out after {:?}", timeout),
+        }
+    }
+}
+
+impl GraphReport {
+    /// tell if all the tasks succeeded
+    pub fn success(&self) -> bool {
+        self.tasks.iter().all(|task| task.status.success())
+    }
+
+    /// the report of the given task
+    pub fn task(&self, id: TaskId) -> &TaskReport {
+        &self.tasks[id.0]
+    }
+
+    /// the reports of all the tasks
+    pub fn tasks(&self) -> &[TaskReport] {
+        &self.tasks
+    }
+}
+
+impl fmt::Display for GraphReport {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        for task in &self.tasks {
+            write!(f, "{}: {}", task.name, task.status)?;
+            if let Some(duration) = task.duration {
+                write!(f, " ({:?})", duration)?;
+            }
+            if !task.status.success() {
+                write!(f, "{}", task.stderr.report())?;
+            }
+            writeln!(f)?;
+        }
+        Ok(())
+    }
+}
+
+impl RunGraph {
+    /// start the tasks whose dependencies are all done, returns `true` if
+    /// any task changed state
+    fn start_tasks(&mut self) -> bool {
+        let mut progress = false;
+        for index in 0..self.tasks.len() {
+            if !matches!(self.states[index], State::Pending) {
+                continue;
+            }
+
+            let mut ready = true;
+            let mut skipped = false;
+            for dependency in &self.tasks[index].dependencies {
+                match &self.states[dependency.0] {
+                    State::Done(status, ..) => skipped |= !status.success(),
+                    _ => ready = false,
+                }
+            }
+
+            if self.stopping {
+                self.states[index] = self.done(TaskStatus::Cancelled);
+            } else if skipped {
+                self.states[index] = self.done(TaskStatus::Skipped);
+            } else if ready {
+                // only the tail of the standard error output is kept
+                let mut command = self.tasks[index].command.clone();
+                command.output_tail(self.stderr_tail);
+                let job = self.pool.queue(command);
+                self.states[index] = State::Running(Box::new(job), None, None);
+            } else {
+                continue;
+            }
+            progress = true;
+        }
+        progress
+    }
+
+    /// poll the running tasks, returns `true` if any task finished
+    fn poll_tasks(&mut self) -> bool {
+        let mut progress = false;
+        for index in 0..self.tasks.len() {
+            let (job, started, deadline) = match &mut self.states[index] {
+                State::Running(job, started, deadline) => (job, started, deadline),
+                _ => continue,
+            };
+            let timeout = self.tasks[index].timeout;
+            let stderr_tail = self.stderr_tail;
+
+            let result = job.poll();
+            if started.is_none() && job.process().is_some() {
+                let now = Instant::now();
+                *started = Some(now);
+                *deadline = timeout.map(|timeout| Delay::new(now + timeout));
+            }
+            let duration = started.map(|started| started.elapsed());
+
+            let (status, stderr) = match result {
+                Ok(Async::NotReady) => {
+                    let status = match deadline.as_mut().map(Delay::poll) {
+                        None | Some(Ok(Async::NotReady)) => continue,
+                        Some(Ok(Async::Ready(()))) => {
+                            TaskStatus::TimedOut(timeout.expect("a deadline is set"))
+                        }
+                        Some(Err(error)) => TaskStatus::Error(error.to_string()),
+                    };
+                    // dropping the job kills the process
+                    let stderr = job.process().map_or_else(
+                        || OutputTail::new(stderr_tail),
+                        |process| process.output_tail_snapshot(),
+                    );
+                    (status, stderr)
+                }
+                Ok(Async::Ready((status, stderr))) => {
+                    let status = if status.success() {
+                        TaskStatus::Succeeded
+                    } else {
+                        TaskStatus::Failed(status)
+                    };
+                    (status, stderr)
+                }
+                Err(error) => (
+                    TaskStatus::Error(error.to_string()),
+                    OutputTail::new(self.stderr_tail),
+                ),
+            };
+
+            if !status.success() && self.fail_fast {
+                self.stopping = true;
+            }
+            self.states[index] = State::Done(status, duration, stderr);
+            progress = true;
+        }
+
+        if self.stopping {
+            // dropping the jobs kills the running processes
+            for state in &mut self.states {
+                if let State::Running(_, started, _) = state {
+                    let duration = started.map(|started| started.elapsed());
+                    *state = State::Done(
+                        TaskStatus::Cancelled,
+                        duration,
+                        OutputTail::new(self.stderr_tail),
+                    );
+                }
+            }
+        }
+
+        progress
+    }
+
+    fn done(&self, status: TaskStatus) -> State {
+        State::Done(status, None, OutputTail::new(self.stderr_tail))
+    }
+
+    fn report(&self) -> GraphReport {
+        let tasks = self
+            .tasks
+            .iter()
+            .zip(&self.states)
+            .enumerate()
+            .map(|(index, (task, state))| match state {
+                State::Done(status, duration, stderr) => TaskReport {
+                    id: TaskId(index),
+                    name: task.name.clone(),
+                    command: task.command.clone(),
+                    status: status.clone(),
+                    duration: *duration,
+                    stderr: stderr.clone(),
+                },
+                _ => unreachable!("all the tasks should be done"),
+            })
+            .collect();
+        GraphReport { tasks }
+    }
+}
+
+impl Future for RunGraph {
+    type Item = GraphReport;
+    type Error = Error;
+
+    fn poll(&mut self) -> Poll<Self::Item, Self::Error> {
+        while self.start_tasks() | self.poll_tasks() {}
+
+        if self
+            .states
+            .iter()
+            .all(|state| matches!(state, State::Done(..)))
+        {
+            Ok(Async::Ready(self.report()))
+        } else {
+            Ok(Async::NotReady)
+        }
+    }
+}
+
+#[cfg(all(test, unix))]
+mod test {
+    use super::*;
+    use crate::{
+        temp::{block_on, TempPath},
+        Result,
+    };
+
+    #[test]
+    fn run_in_topological_order() -> Result<()> {
+        let dir = TempPath::new("graph");
+        let path = dir.display();
+
+        let mut graph = TaskGraph::new();
+        let mkdir = graph.task(
+            "mkdir",
+            Command::shell(format!("sleep 0.1; mkdir {}", path))?,
+            &[],
+        );
+        let a = graph.task("a", Command::shell(format!("touch {}/a", path))?, &[mkdir]);
+        let b = graph.task("b", Command::shell(format!("touch {}/b", path))?, &[mkdir]);
+        let both = graph.task(
+            "both",
+            Command::shell(format!("test -f {0}/a -a -f {0}/b", path))?,
+            &[a, b],
+        );
+
+        let report = block_on(graph.max_parallelism(4).run())?;
+
+        assert!(report.success(), "{}", report);
+        assert!(report.task(mkdir).duration.unwrap() >= Duration::from_millis(100));
+        assert_eq!(report.task(both).status, TaskStatus::Succeeded);
+
+        Ok(())
+    }
+
+    #[test]
+    fn fail_fast() -> Result<()> {
+        let mut graph = TaskGraph::new();
+        let failing = graph.task("failing", Command::shell("echo broken >&2; exit 2")?, &[]);
+        let slow = graph.task("slow", Command::shell("sleep 10")?, &[]);
+        let dependent = graph.task("dependent", Command::shell("true")?, &[failing]);
+
+        let report = block_on(graph.run())?;
+
+        match &report.task(failing).status {
+            TaskStatus::Failed(status) => assert_eq!(status.code(), Some(2)),
+            status => panic!("unexpected status: {}", status),
+        }
+        assert_eq!(report.task(failing).stderr.to_string(), "broken");
+        assert_eq!(report.task(slow).status, TaskStatus::Cancelled);
+        assert_eq!(report.task(dependent).status, TaskStatus::Cancelled);
+
+        Ok(())
+    }
+
+    #[test]
+    fn continue_on_error() -> Result<()> {
+        let mut graph = TaskGraph::new();
+        let failing = graph.task("failing", Command::shell("exit 1")?, &[]);
+        let other = graph.task("other", Command::shell("sleep 0.1")?, &[]);
+        let dependent = graph.task("dependent", Command::shell("true")?, &[failing]);
+
+        let report = block_on(graph.fail_fast(false).run())?;
+
+        assert!(!report.success());
+        assert_eq!(report.task(other).status, TaskStatus::Succeeded);
+        assert_eq!(report.task(dependent).status, TaskStatus::Skipped);
+        assert_eq!(report.task(dependent).duration, None);
+
+        Ok(())
+    }
+
+    #[test]
+    fn same_report_once_complete() -> Result<()> {
+        let mut graph = TaskGraph::new();
+        let task = graph.task("true", Command::shell("true")?, &[]);
+        let mut running = graph.run();
+
+        let report = block_on(futures::future::poll_fn(|| running.poll()))?;
+        let again = block_on(futures::future::poll_fn(|| running.poll()))?;
+
+        assert_eq!(report.task(task).status, TaskStatus::Succeeded);
+        assert_eq!(again.task(task).status, TaskStatus::Succeeded);
+        assert_eq!(again.task(task).duration, report.task(task).duration);
+
+        Ok(())
+    }
+
+    #[test]
+    fn kill_after_timeout() -> Result<()> {
+        let mut graph = TaskGraph::new();
+        let slow = graph.task("slow", Command::shell("echo started >&2; sleep 10")?, &[]);
+        graph.timeout(slow, Duration::from_millis(200));
+        let quick = graph.task("quick", Command::shell("true")?, &[]);
+        graph.timeout(quick, Duration::from_secs(10));
+
+        let start = Instant::now();
+        let report = block_on(graph.fail_fast(false).run())?;
+
+        assert!(start.elapsed() < Duration::from_secs(5));
+        assert_eq!(
+            report.task(slow).status,
+            TaskStatus::TimedOut(Duration::from_millis(200))
+        );
+        assert_eq!(report.task(slow).stderr.to_string(), "started");
+        assert_eq!(report.task(quick).status, TaskStatus::Succeeded);
+
+        Ok(())
+    }
+}
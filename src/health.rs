@@ -0,0 +1,474 @@
+use crate::{ready::TcpConnect, Command, Control, Error, Process};
+use futures::{Async, Future, Poll, Stream};
+use std::{
+    fmt,
+    net::{Ipv4Addr, SocketAddr},
+    process::ExitStatus,
+    time::{Duration, Instant},
+};
+use tokio_timer::{Delay, Interval};
+
+/// periodic probe checking a running process is still healthy
+///
+/// the probe is run every `interval`, the process is considered unhealthy
+/// once the probe failed `failure_threshold` times in a row. It can then
+/// be killed (see [`HealthCheck::kill_when_unhealthy`]), which is how a
+/// [`Supervisor`] restarts it.
+///
+/// ```
+/// # use bawawa::HealthCheck;
+/// # use std::time::Duration;
+/// let check = HealthCheck::tcp_port(8080)
+///     .interval(Duration::from_secs(10))
+///     .failure_threshold(3)
+///     .kill_when_unhealthy(true);
+/// ```
+///
+/// [`HealthCheck::kill_when_unhealthy`]: ./struct.HealthCheck.html#method.kill_when_unhealthy
+/// [`Supervisor`]: ./struct.Supervisor.html
+pub struct HealthCheck {
+    probe: Probe,
+    interval: Duration,
+    timeout: Duration,
+    failure_threshold: usize,
+    kill: bool,
+}
+
+enum Probe {
+    Command(Box<Command>),
+    Tcp(SocketAddr),
+    Closure(Box<dyn FnMut() -> bool + Send>),
+}
+
+/// result of a health check of the process `id`
+#[derive(Debug, Clone)]
+pub struct HealthEvent {
+    pub command: Command,
+    pub id: u32,
+    pub health: Health,
+}
+
+/// outcome of a health check
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Health {
+    /// the probe succeeded
+    Passed,
+    /// the probe failed, `failures` times in a row
+    Failed { failures: usize, reason: String },
+    /// the failure threshold has been reached, this is reported for
+    /// every failed probe until one passes
+    Unhealthy { failures: usize, killed: bool },
+}
+
+/// stream of the [`HealthEvent`]s of a running [`Process`]
+///
+/// created from [`HealthMonitor::new`], the stream ends once the process
+/// has exited.
+///
+/// [`HealthEvent`]: ./struct.HealthEvent.html
+/// [`Process`]: ./struct.Process.html
+/// [`HealthMonitor::new`]: ./struct.HealthMonitor.html#method.new
+pub struct HealthMonitor {
+    process: Process,
+    checker: HealthChecker,
+    status: Option<ExitStatus>,
+}
+
+/// the running state of a [`HealthCheck`]
+///
+/// [`HealthCheck`]: ./struct.HealthCheck.html
+pub(crate) struct HealthChecker {
+    check: HealthCheck,
+    interval: Interval,
+    running: Option<Running>,
+    failures: usize,
+}
+
+/// a probe in progress
+enum Running {
+    Command(Box<Process>, Delay),
+    Tcp(SocketAddr, TcpConnect),
+}
+
+impl HealthCheck {
+    const DEFAULT_INTERVAL: Duration = Duration::from_secs(10);
+    const DEFAULT_TIMEOUT: Duration = Duration::from_secs(5);
+    const DEFAULT_FAILURE_THRESHOLD: usize = 3;
+
+    fn new(probe: Probe) -> Self {
+        HealthCheck {
+            probe,
+            interval: Self::DEFAULT_INTERVAL,
+            timeout: Self::DEFAULT_TIMEOUT,
+            failure_threshold: Self::DEFAULT_FAILURE_THRESHOLD,
+            kill: false,
+        }
+    }
+
+    /// healthy if the command exits successfully within the timeout
+    pub fn command(command: Command) -> Self {
+        Self::new(Probe::Command(Box::new(command)))
+    }
+
+    /// healthy if the given TCP port accepts connections on localhost
+    pub fn tcp_port(port: u16) -> Self {
+        Self::tcp(SocketAddr::from((Ipv4Addr::LOCALHOST, port)))
+    }
+
+    /// healthy if the given address accepts TCP connections within the timeout
+    pub fn tcp(address: SocketAddr) -> Self {
+        Self::new(Probe::Tcp(address))
+    }
+
+    /// healthy if the closure returns `true`
+    pub fn probe<F>(probe: F) -> Self
+    where
+        F: FnMut() -> bool + Send + 'static,
+    {
+        Self::new(Probe::Closure(Box::new(probe)))
+    }
+
+    /// set the interval between two probes
+    pub fn interval(mut self, interval: Duration) -> Self {
+        self.interval = interval;
+        self
+    }
+
+    /// set the time given to the command or TCP probes to succeed
+    pub fn timeout(mut self, timeout: Duration) -> Self {
+        self.timeout = timeout;
+        self
+    }
+
+    /// set the number of consecutive failures before the process is
+    /// considered unhealthy
+    pub fn failure_threshold(mut self, failure_threshold: usize) -> Self {
+        self.failure_threshold = failure_threshold.max(1);
+        self
+    }
+
+    /// kill the process once it is unhealthy
+    pub fn kill_when_unhealthy(mut self, kill: bool) -> Self {
+        self.kill = kill;
+        self
+    }
+}
+
+impl fmt::Debug for HealthCheck {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let probe = match &self.probe {
+            Probe::Command(command) => format!("Command({})", command),
+            Probe::Tcp(address) => format!("Tcp({})", address),
+            Probe::Closure(_) => "Closure".to_owned(),
+        };
+        f.debug_struct("HealthCheck")
+            .field("probe", &probe)
+            .field("interval", &self.interval)
+            .field("timeout", &self.timeout)
+            .field("failure_threshold", &self.failure_threshold)
+            .field("kill", &self.kill)
+            .finish()
+    }
+}
+
+impl HealthChecker {
+    pub(crate) fn new(check: HealthCheck) -> Self {
+        let interval = Interval::new(Instant::now() + check.interval, check.interval);
+        HealthChecker {
+            check,
+            interval,
+            running: None,
+            failures: 0,
+        }
+    }
+
+    /// start over with a new incarnation of the process
+    pub(crate) fn reset(&mut self) {
+        let interval = self.check.interval;
+        self.interval = Interval::new(Instant::now() + interval, interval);
+        self.running = None;
+        self.failures = 0;
+    }
+
+    /// poll the next result of the probe, the process `control` is killed
+    /// if it is unhealthy and the check is set to kill it
+    pub(crate) fn poll_check<C: Control>(&mut self, control: &mut C) -> Poll<Health, Error> {
+        let result = match self.poll_probe()? {
+            Async::NotReady => return Ok(Async::NotReady),
+            Async::Ready(result) => result,
+        };
+
+        let health = match result {
+            Ok(()) => {
+                self.failures = 0;
+                Health::Passed
+            }
+            Err(reason) => {
+                self.failures += 1;
+                if self.failures >= self.check.failure_threshold {
+                    // once is enough, the process is exiting
+                    if self.check.kill && self.failures == self.check.failure_threshold {
+                        control.kill()?;
+                    }
+                    Health::Unhealthy {
+                        failures: self.failures,
+                        killed: self.check.kill,
+                    }
+                } else {
+                    Health::Failed {
+                        failures: self.failures,
+                        reason,
+                    }
+                }
+            }
+        };
+
+        Ok(Async::Ready(health))
+    }
+
+    fn poll_probe(&mut self) -> Poll<Result<(), String>, Error> {
+        loop {
+            if let Some(running) = &mut self.running {
+                let result = match running {
+                    Running::Command(process, timeout) => match process.poll()? {
+                        Async::Ready(status) if status.success() => Ok(()),
+                        Async::Ready(status) => Err(format!("probe exited with {}", status)),
+                        Async::NotReady => match timeout.poll().map_err(Error::Timer)? {
+                            Async::Ready(()) => {
+                                Err(format!("probe timed out after {:?}", self.check.timeout))
+                            }
+                            Async::NotReady => return Ok(Async::NotReady),
+                        },
+                    },
+                    Running::Tcp(address, connect) => match connect.poll() {
+                        Ok(Async::Ready(())) => Ok(()),
+                        Ok(Async::NotReady) => return Ok(Async::NotReady),
+                        Err(error) => Err(format!("cannot connect to {}: {}", address, error)),
+                    },
+                };
+                // dropping the process kills it if it timed out
+                self.running = None;
+                return Ok(Async::Ready(result));
+            }
+
+            if self.interval.poll().map_err(Error::Timer)?.is_not_ready() {
+                return Ok(Async::NotReady);
+            }
+
+            match &mut self.check.probe {
+                Probe::Command(command) => match Process::spawn(Command::clone(command)) {
+                    Ok(process) => {
+                        let timeout = Delay::new(Instant::now() + self.check.timeout);
+                        self.running = Some(Running::Command(Box::new(process), timeout));
+                    }
+                    Err(error) => return Ok(Async::Ready(Err(error.to_string()))),
+                },
+                Probe::Tcp(address) => {
+                    let connect = TcpConnect::new(address, self.check.timeout);
+                    self.running = Some(Running::Tcp(*address, connect));
+                }
+                Probe::Closure(probe) => {
+                    let result = if probe() {
+                        Ok(())
+                    } else {
+                        Err("probe failed".to_owned())
+                    };
+                    return Ok(Async::Ready(result));
+                }
+            }
+        }
+    }
+}
+
+impl HealthMonitor {
+    /// monitor the health of the given process
+    ///
+    /// the probes require a `tokio` runtime with a timer.
+    pub fn new(process: Process, check: HealthCheck) -> Self {
+        HealthMonitor {
+            process,
+            checker: HealthChecker::new(check),
+            status: None,
+        }
+    }
+
+    /// the monitored process
+    pub fn process(&self) -> &Process {
+        &self.process
+    }
+
+    /// the exit status of the process, once the stream has ended
+    pub fn status(&self) -> Option<ExitStatus> {
+        self.status
+    }
+}
+
+impl Stream for HealthMonitor {
+    type Item = HealthEvent;
+    type Error = Error;
+
+    fn poll(&mut self) -> Poll<Option<Self::Item>, Self::Error> {
+        if self.status.is_some() {
+            return Ok(Async::Ready(None));
+        }
+
+        if let Async::Ready(status) = self.process.poll()? {
+            self.status = Some(status);
+            return Ok(Async::Ready(None));
+        }
+
+        let health = futures::try_ready!(self.checker.poll_check(&mut self.process));
+        Ok(Async::Ready(Some(HealthEvent {
+            command: self.process.command().clone(),
+            id: self.process.id(),
+            health,
+        })))
+    }
+}
+
+#[cfg(all(test, unix))]
+mod test {
+    use super::*;
+    use crate::{Program, Result};
+    use std::sync::{
+        atomic::{AtomicUsize, Ordering},
+        Arc,
+    };
+
+    #[test]
+    fn kill_when_unhealthy() -> Result<()> {
+        let mut cmd = Command::new(Program::new("sleep")?);
+        cmd.argument("10");
+        let process = Process::spawn(cmd)?;
+        let id = process.id();
+
+        let probes = Arc::new(AtomicUsize::new(0));
+        let counter = probes.clone();
+        let check = HealthCheck::probe(move || counter.fetch_add(1, Ordering::SeqCst) == 0)
+            .interval(Duration::from_millis(10))
+            .failure_threshold(2)
+            .kill_when_unhealthy(true);
+
+        let mut runtime = tokio::runtime::Runtime::new().unwrap();
+        let events = runtime.block_on(HealthMonitor::new(process, check).collect())?;
+
+        let health: Vec<_> = events.iter().map(|event| event.health.clone()).collect();
+        assert_eq!(
+            health,
+            vec![
+                Health::Passed,
+                Health::Failed {
+                    failures: 1,
+                    reason: "probe failed".to_owned()
+                },
+                Health::Unhealthy {
+                    failures: 2,
+                    killed: true
+                },
+            ]
+        );
+        assert!(events.iter().all(|event| event.id == id));
+
+        Ok(())
+    }
+
+    #[test]
+    fn unhealthy_until_passed() -> Result<()> {
+        let mut cmd = Command::new(Program::new("sleep")?);
+        cmd.argument("10");
+        let process = Process::spawn(cmd)?;
+
+        let probes = Arc::new(AtomicUsize::new(0));
+        let counter = probes.clone();
+        let check = HealthCheck::probe(move || counter.fetch_add(1, Ordering::SeqCst) == 3)
+            .interval(Duration::from_millis(10))
+            .failure_threshold(2);
+
+        let mut runtime = tokio::runtime::Runtime::new().unwrap();
+        let events = runtime.block_on(HealthMonitor::new(process, check).take(5).collect())?;
+
+        let health: Vec<_> = events.iter().map(|event| event.health.clone()).collect();
+        assert_eq!(
+            health,
+            vec![
+                Health::Failed {
+                    failures: 1,
+                    reason: "probe failed".to_owned()
+                },
+                Health::Unhealthy {
+                    failures: 2,
+                    killed: false
+                },
+                Health::Unhealthy {
+                    failures: 3,
+                    killed: false
+                },
+                Health::Passed,
+                Health::Failed {
+                    failures: 1,
+                    reason: "probe failed".to_owned()
+                },
+            ]
+        );
+
+        Ok(())
+    }
+
+    #[test]
+    fn tcp_probe() -> Result<()> {
+        let listener = std::net::TcpListener::bind((Ipv4Addr::LOCALHOST, 0)).unwrap();
+        let address = listener.local_addr().unwrap();
+        let mut cmd = Command::new(Program::new("sleep")?);
+        cmd.argument("10");
+        let process = Process::spawn(cmd)?;
+
+        let check = HealthCheck::tcp(address).interval(Duration::from_millis(10));
+        let mut runtime = tokio::runtime::Runtime::new().unwrap();
+        let monitor = HealthMonitor::new(process, check);
+        let events = runtime.block_on(monitor.take(1).collect())?;
+        assert_eq!(events[0].health, Health::Passed);
+
+        drop(listener);
+        let check = HealthCheck::tcp(address).interval(Duration::from_millis(10));
+        let process = Process::spawn(Command::new(Program::new("cat")?))?;
+        let monitor = HealthMonitor::new(process, check);
+        let events = runtime.block_on(monitor.take(1).collect())?;
+        match &events[0].health {
+            Health::Failed { reason, .. } => {
+                assert!(reason.starts_with(&format!("cannot connect to {}", address)))
+            }
+            health => panic!("unexpected health: {:?}", health),
+        }
+
+        Ok(())
+    }
+
+    #[test]
+    fn command_probe() -> Result<()> {
+        let mut cmd = Command::new(Program::new("sleep")?);
+        cmd.argument("10");
+        let process = Process::spawn(cmd)?;
+
+        let check = HealthCheck::command(Command::new(Program::new("false")?))
+            .interval(Duration::from_millis(10))
+            .failure_threshold(1)
+            .kill_when_unhealthy(true);
+
+        let mut runtime = tokio::runtime::Runtime::new().unwrap();
+        let monitor = HealthMonitor::new(process, check).into_future();
+        let (event, _monitor) = runtime
+            .block_on(monitor)
+            .map_err(|(error, _monitor)| error)?;
+        let event = event.expect("the monitor should report an event");
+
+        assert_eq!(
+            event.health,
+            Health::Unhealthy {
+                failures: 1,
+                killed: true
+            }
+        );
+
+        Ok(())
+    }
+}
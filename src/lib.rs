@@ -13,7 +13,8 @@ There are a couple of items to keep in mind when utilising this API:
 
 * as soon as [`Process`] is _dropped_ the associated process will be terminated;
 * [`Process`] captures _Stdout_ and _Stderr_, if you don't read the standard output it won't
-  be visible on your terminal;
+  be visible on your terminal (with the `log` or `tracing` features it can be forwarded
+  to the logs instead, see `Command::forward_output`);
 * [`Process`] control _Stdin_ too
 * the API utilizes the `Future` framework. If you don't push it in a runtime or call
   `wait` the functions will do nothing.
@@ -102,55 +103,92 @@ println!("compiler: {}", capture_stdout.next().unwrap()?);
 [`StandardInput::send_stdin`]: ./trait.StandardInput.html#method.send_stdin
 */
 
-#[macro_use(error_chain)]
-extern crate error_chain;
-
 mod capture;
 mod command;
+#[cfg(feature = "config")]
+mod config;
 mod control;
+#[cfg(unix)]
+mod credentials;
+mod drain;
+mod dry_run;
+mod error;
+mod fake;
+#[cfg(any(feature = "log", feature = "tracing"))]
+mod forward;
+mod graph;
+mod health;
+mod output;
+mod pool;
+#[cfg(unix)]
+mod pre_exec;
 mod process;
+mod procfile;
 mod program;
+mod ready;
+#[cfg(feature = "record")]
+mod record;
+mod retry;
+mod rusage;
+#[cfg(target_os = "linux")]
+mod sandbox;
+#[cfg(all(
+    target_os = "linux",
+    any(target_arch = "x86_64", target_arch = "aarch64")
+))]
+mod seccomp;
 mod send_stdin;
+mod shell;
+mod spawner;
+#[cfg(target_os = "linux")]
+mod stats;
+mod stdio;
+mod supervisor;
+mod tail;
+#[cfg(test)]
+mod temp;
+mod template;
+mod trace;
 
 pub use self::capture::Capture;
 pub use self::command::Command;
+#[cfg(feature = "config")]
+pub use self::config::{Definition, Definitions};
 pub use self::control::*;
-pub use self::process::Process;
+pub use self::dry_run::DryRunSpawner;
+pub use self::error::{Error, Result};
+pub use self::fake::{FakeProcess, FakeSpawner};
+#[cfg(any(feature = "log", feature = "tracing"))]
+pub use self::forward::{ForwardOutput, Level};
+pub use self::graph::{GraphReport, RunGraph, TaskGraph, TaskId, TaskReport, TaskStatus};
+pub use self::health::{Health, HealthCheck, HealthEvent, HealthMonitor};
+pub use self::output::{CollectOutput, Output};
+pub use self::pool::{CancelJob, Job, ProcessPool};
+pub use self::process::{ExpectSuccess, Process, WithResourceUsage};
+pub use self::procfile::Procfile;
 pub use self::program::Program;
+pub use self::ready::{Readiness, WaitReady};
+#[cfg(feature = "record")]
+pub use self::record::{
+    RecordedChunk, RecordedPipe, RecordedProcess, Recorder, Recording, Replayer,
+};
+pub use self::retry::{Retry, RetryPolicy};
+pub use self::rusage::ResourceUsage;
+#[cfg(target_os = "linux")]
+pub use self::sandbox::Sandbox;
+#[cfg(all(
+    target_os = "linux",
+    any(target_arch = "x86_64", target_arch = "aarch64")
+))]
+pub use self::seccomp::{Denied, SyscallFilter};
 pub use self::send_stdin::SendStdin;
-
-error_chain! {
-    foreign_links {
-        Io(::std::io::Error);
-    }
-
-    errors {
-        InvalidProgramName(p: Program) {
-            description("invalid program name")
-            display("invalid program name: '{}'", p)
-        }
-
-        CannotSpawnCommand(c: Command) {
-            description("cannot spawn command")
-            display("cannot spawn command: '{}'", c)
-        }
-
-        CannotKillProcess(c: Command, id: u32) {
-            description("cannot kill process")
-            display("cannot kill process '{}' ({})", id, c)
-        }
-
-        Poll(c: Command) {
-            description("error while waiting for command to finish")
-            display("Error while waiting for command to finish: {}", c)
-        }
-
-        Capture {
-            description("error in `capture`")
-        }
-
-        SendStdin {
-            description("error in `send_stdin`")
-        }
-    }
-}
+#[doc(hidden)]
+pub use self::shell::check_script as __check_shell_script;
+pub use self::shell::shell_quote;
+pub use self::spawner::{Child, OsSpawner, Spawned, Spawner};
+#[cfg(target_os = "linux")]
+pub use self::stats::{ProcessStats, SampleStats};
+pub use self::stdio::{ChildStderr, ChildStdin, ChildStdout, OutputPipe};
+pub use self::supervisor::{Restart, RestartPolicy, Supervisor, SupervisorEvent};
+pub use self::tail::{OutputTail, TailOptions};
+pub use self::template::CommandTemplate;
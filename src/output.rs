@@ -0,0 +1,246 @@
+use crate::{drain, ChildStderr, ChildStdout, Command, Control as _, Error, OutputTail, Process};
+use futures::{try_ready, Async, Future, Poll};
+use std::{io, process::ExitStatus};
+use tokio_io::AsyncRead;
+
+/// the output of a finished [`Process`]
+///
+/// [`Process`]: ./struct.Process.html
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Output {
+    pub status: ExitStatus,
+    pub stdout: Vec<u8>,
+    pub stderr: Vec<u8>,
+}
+
+/// future returned by [`Process::output`]
+///
+/// [`Process::output`]: ./struct.Process.html#method.output
+pub struct CollectOutput {
+    process: Process,
+    stdout: Pipe<ChildStdout>,
+    stderr: Pipe<ChildStderr>,
+    status: Option<ExitStatus>,
+}
+
+/// wait for the process to finish, keeping only the [`OutputTail`] of
+/// its output: what is not drained into the tail is discarded
+///
+/// the tail is empty unless `Command::output_tail` was set.
+///
+/// [`OutputTail`]: ./struct.OutputTail.html
+pub(crate) struct TailOutput {
+    process: Process,
+    stdout: Pipe<ChildStdout>,
+    stderr: Pipe<ChildStderr>,
+    status: Option<ExitStatus>,
+}
+
+/// a pipe read until the end of the stream
+struct Pipe<R> {
+    reader: Option<R>,
+    buffer: Vec<u8>,
+    /// what is read is dropped instead of kept in the buffer
+    discard: bool,
+}
+
+impl CollectOutput {
+    pub(crate) fn new(mut process: Process) -> Self {
+        // the standard input is closed so the process does not wait for it
+        process.stdin().take();
+        // the pipes are taken from the process so they are not drained
+        // for the output tail too
+        let stdout = Pipe::new(process.stdout().take());
+        let stderr = Pipe::new(process.stderr().take());
+        CollectOutput {
+            process,
+            stdout,
+            stderr,
+            status: None,
+        }
+    }
+
+    /// the process whose output is collected
+    pub fn process(&self) -> &Process {
+        &self.process
+    }
+
+    /// the process whose output is collected
+    pub fn process_mut(&mut self) -> &mut Process {
+        &mut self.process
+    }
+
+    #[inline]
+    pub fn command(&self) -> &Command {
+        self.process.command()
+    }
+}
+
+impl TailOutput {
+    pub(crate) fn new(mut process: Process) -> Self {
+        process.stdin().take();
+        // the pipes drained for the tail are left to the process
+        let stdout = if process.drains(drain::Pipe::Stdout) {
+            Pipe::discarded(None)
+        } else {
+            Pipe::discarded(process.stdout().take())
+        };
+        let stderr = if process.drains(drain::Pipe::Stderr) {
+            Pipe::discarded(None)
+        } else {
+            Pipe::discarded(process.stderr().take())
+        };
+        TailOutput {
+            process,
+            stdout,
+            stderr,
+            status: None,
+        }
+    }
+
+    pub(crate) fn process(&self) -> &Process {
+        &self.process
+    }
+
+    pub(crate) fn process_mut(&mut self) -> &mut Process {
+        &mut self.process
+    }
+}
+
+impl<R: AsyncRead> Pipe<R> {
+    fn new(reader: Option<R>) -> Self {
+        Pipe {
+            reader,
+            buffer: Vec::new(),
+            discard: false,
+        }
+    }
+
+    fn discarded(reader: Option<R>) -> Self {
+        Pipe {
+            reader,
+            buffer: Vec::new(),
+            discard: true,
+        }
+    }
+
+    /// read everything from the pipe, ready at the end of the stream
+    fn poll_read_to_end(&mut self) -> Poll<(), io::Error> {
+        let reader = match &mut self.reader {
+            None => return Ok(Async::Ready(())),
+            Some(reader) => reader,
+        };
+
+        let mut chunk = [0; 4 * 1024];
+        loop {
+            match try_ready!(reader.poll_read(&mut chunk)) {
+                0 => {
+                    self.reader = None;
+                    return Ok(Async::Ready(()));
+                }
+                _ if self.discard => {}
+                read => self.buffer.extend_from_slice(&chunk[..read]),
+            }
+        }
+    }
+}
+
+impl Future for CollectOutput {
+    type Item = Output;
+    type Error = Error;
+
+    fn poll(&mut self) -> Poll<Self::Item, Self::Error> {
+        let stdout = self.stdout.poll_read_to_end();
+        let stderr = self.stderr.poll_read_to_end();
+        let pipes = stdout
+            .and_then(|stdout| stderr.map(|stderr| stdout.is_ready() && stderr.is_ready()))
+            .map_err(|source| Error::Output {
+                command: Box::new(self.process.command().clone()),
+                id: self.process.id(),
+                source,
+            })?;
+
+        if self.status.is_none() {
+            if let Async::Ready(status) = self.process.poll()? {
+                self.status = Some(status);
+            }
+        }
+
+        match self.status {
+            Some(status) if pipes => Ok(Async::Ready(Output {
+                status,
+                stdout: std::mem::take(&mut self.stdout.buffer),
+                stderr: std::mem::take(&mut self.stderr.buffer),
+            })),
+            _ => Ok(Async::NotReady),
+        }
+    }
+}
+
+impl Future for TailOutput {
+    type Item = (ExitStatus, OutputTail);
+    type Error = Error;
+
+    fn poll(&mut self) -> Poll<Self::Item, Self::Error> {
+        let stdout = self.stdout.poll_read_to_end();
+        let stderr = self.stderr.poll_read_to_end();
+        let pipes = stdout
+            .and_then(|stdout| stderr.map(|stderr| stdout.is_ready() && stderr.is_ready()))
+            .map_err(|source| Error::Output {
+                command: Box::new(self.process.command().clone()),
+                id: self.process.id(),
+                source,
+            })?;
+
+        if self.status.is_none() {
+            if let Async::Ready(status) = self.process.poll()? {
+                self.status = Some(status);
+            }
+        }
+
+        match self.status {
+            Some(status) if pipes => {
+                Ok(Async::Ready((status, self.process.output_tail_snapshot())))
+            }
+            _ => Ok(Async::NotReady),
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::TailOutput;
+    use crate::{Command, Process, Program, Result, TailOptions};
+    use futures::Future as _;
+
+    #[test]
+    fn collect_output() -> Result<()> {
+        let mut cmd = Command::new(Program::new("rustc")?);
+        cmd.arguments(["--version"]);
+
+        let output = Process::spawn(cmd)?.output().wait()?;
+
+        assert!(output.status.success());
+        assert!(output.stdout.starts_with(b"rustc "));
+        assert!(output.stderr.is_empty());
+
+        Ok(())
+    }
+
+    #[test]
+    fn tail_output() -> Result<()> {
+        let mut cmd = Command::new(Program::new("sh")?);
+        cmd.arguments([
+            "-c",
+            "head -c 1000000 /dev/zero; echo one >&2; echo two >&2",
+        ])
+        .output_tail(TailOptions::new(1));
+
+        let (status, tail) = TailOutput::new(Process::spawn(cmd)?).wait()?;
+
+        assert!(status.success());
+        assert_eq!(tail.lines().collect::<Vec<_>>(), vec!["two"]);
+
+        Ok(())
+    }
+}
@@ -0,0 +1,473 @@
+use crate::{output::TailOutput, CollectOutput, Command, Control as _, Error, Output, Process};
+use futures::{
+    task::{self, Task},
+    Async, Future, Poll,
+};
+use std::{
+    collections::VecDeque,
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        Arc, Mutex, MutexGuard,
+    },
+};
+
+/// spawn [`Command`]s with at most `limit` of them running at a time
+///
+/// the commands are queued until a slot is available, each of them
+/// returns a [`Job`] future yielding its [`Output`]. The pool can be
+/// cloned to share the same limit.
+///
+/// ```
+/// # use bawawa::{Command, ProcessPool, Program, Error};
+/// # use futures::Future as _;
+/// let pool = ProcessPool::default();
+///
+/// let mut get_rustc_version = Command::new(Program::new("rustc")?);
+/// get_rustc_version.arguments(&["--version"]);
+///
+/// let output = pool.spawn(get_rustc_version).wait()?;
+/// assert!(output.status.success());
+/// # Ok::<(), Error>(())
+/// ```
+///
+/// [`Command`]: ./struct.Command.html
+/// [`Job`]: ./struct.Job.html
+/// [`Output`]: ./struct.Output.html
+#[derive(Clone)]
+pub struct ProcessPool {
+    shared: Arc<Mutex<Shared>>,
+}
+
+struct Shared {
+    limit: usize,
+    running: usize,
+    next_id: u64,
+    /// the queued jobs, with the task to notify once a slot is available
+    queue: VecDeque<(u64, Option<Task>)>,
+}
+
+/// future returned by [`ProcessPool::spawn`], yields the [`Output`] of
+/// the command once it has finished
+///
+/// dropping the job removes it from the queue, or kills the process if
+/// it is already running.
+///
+/// [`ProcessPool::spawn`]: ./struct.ProcessPool.html#method.spawn
+/// [`Output`]: ./struct.Output.html
+pub struct Job {
+    job: Queued<CollectOutput>,
+}
+
+/// a job of the pool, running the process with `R` once it has a slot
+pub(crate) struct Queued<R> {
+    pool: ProcessPool,
+    command: Command,
+    state: JobState<R>,
+    cancel: Arc<Cancel>,
+}
+
+/// what a job does with its process
+pub(crate) trait Run: Future<Error = Error> {
+    fn run(process: Process) -> Self;
+
+    fn process(&self) -> &Process;
+
+    fn process_mut(&mut self) -> &mut Process;
+}
+
+enum JobState<R> {
+    Queued(u64),
+    Running(Box<R>),
+    Done,
+}
+
+/// handle to cancel a [`Job`] from anywhere
+///
+/// [`Job`]: ./struct.Job.html
+#[derive(Clone)]
+pub struct CancelJob {
+    cancel: Arc<Cancel>,
+}
+
+struct Cancel {
+    cancelled: AtomicBool,
+    task: Mutex<Option<Task>>,
+}
+
+impl ProcessPool {
+    /// create a pool running at most `limit` processes at a time
+    ///
+    /// a `limit` of 0 is treated as 1.
+    pub fn new(limit: usize) -> Self {
+        ProcessPool {
+            shared: Arc::new(Mutex::new(Shared {
+                limit: limit.max(1),
+                running: 0,
+                next_id: 0,
+                queue: VecDeque::new(),
+            })),
+        }
+    }
+
+    /// queue the command, it is spawned once a slot is available
+    ///
+    /// the returned [`Job`] needs to be polled to start the command.
+    ///
+    /// [`Job`]: ./struct.Job.html
+    pub fn spawn(&self, command: Command) -> Job {
+        Job {
+            job: self.queue(command),
+        }
+    }
+
+    /// queue the command, it is run with `R` once a slot is available
+    pub(crate) fn queue<R: Run>(&self, command: Command) -> Queued<R> {
+        let mut shared = self.lock();
+        let id = shared.next_id;
+        shared.next_id += 1;
+        shared.queue.push_back((id, None));
+
+        Queued {
+            pool: self.clone(),
+            command,
+            state: JobState::Queued(id),
+            cancel: Arc::new(Cancel {
+                cancelled: AtomicBool::new(false),
+                task: Mutex::new(None),
+            }),
+        }
+    }
+
+    /// the maximum number of processes running at a time
+    pub fn limit(&self) -> usize {
+        self.lock().limit
+    }
+
+    /// the number of jobs waiting for a slot
+    pub fn queued(&self) -> usize {
+        self.lock().queue.len()
+    }
+
+    /// the number of processes currently running
+    pub fn running(&self) -> usize {
+        self.lock().running
+    }
+
+    fn lock(&self) -> MutexGuard<'_, Shared> {
+        // the shared state is consistent even if a thread panicked
+        self.shared
+            .lock()
+            .unwrap_or_else(|poisoned| poisoned.into_inner())
+    }
+
+    /// take a slot for the queued job `id` if one is available for it,
+    /// otherwise the current task is notified once one may be
+    ///
+    /// the slots are granted in the order of the queue: a job only takes
+    /// one if there is one for every job queued before it.
+    fn acquire(&self, id: u64) -> bool {
+        let mut shared = self.lock();
+        let index = match shared.queue.iter().position(|(queued, _)| *queued == id) {
+            Some(index) => index,
+            None => {
+                shared.queue.push_back((id, None));
+                shared.queue.len() - 1
+            }
+        };
+
+        if index < shared.limit.saturating_sub(shared.running) {
+            shared.queue.remove(index);
+            shared.running += 1;
+            true
+        } else {
+            shared.queue[index].1 = Some(task::current());
+            false
+        }
+    }
+
+    fn dequeue(&self, id: u64) {
+        self.lock().queue.retain(|(queued, _)| *queued != id);
+    }
+
+    /// release the slot of a finished job and wake up the next waiting one
+    fn release(&self) {
+        let mut shared = self.lock();
+        shared.running -= 1;
+        let available = shared.limit - shared.running;
+        shared
+            .queue
+            .iter_mut()
+            .take(available)
+            .filter_map(|(_, task)| task.take())
+            .for_each(|task| task.notify());
+    }
+}
+
+impl Default for ProcessPool {
+    /// a pool running as many processes as there are available CPUs
+    fn default() -> Self {
+        let cpus = std::thread::available_parallelism()
+            .map(|cpus| cpus.get())
+            .unwrap_or(1);
+        Self::new(cpus)
+    }
+}
+
+impl Job {
+    /// the command of the job
+    #[inline]
+    pub fn command(&self) -> &Command {
+        &self.job.command
+    }
+
+    /// the running process, if the job has left the queue
+    pub fn process(&self) -> Option<&Process> {
+        self.job.process()
+    }
+
+    /// get a handle to cancel the job
+    pub fn cancel_handle(&self) -> CancelJob {
+        self.job.cancel_handle()
+    }
+}
+
+impl Future for Job {
+    type Item = Output;
+    type Error = Error;
+
+    #[inline]
+    fn poll(&mut self) -> Poll<Self::Item, Self::Error> {
+        self.job.poll()
+    }
+}
+
+impl<R: Run> Queued<R> {
+    /// the running process, if the job has left the queue
+    pub(crate) fn process(&self) -> Option<&Process> {
+        match &self.state {
+            JobState::Running(running) => Some(running.process()),
+            _ => None,
+        }
+    }
+
+    pub(crate) fn cancel_handle(&self) -> CancelJob {
+        CancelJob {
+            cancel: self.cancel.clone(),
+        }
+    }
+
+    fn cancelled(&mut self) -> Error {
+        if let JobState::Running(running) = &mut self.state {
+            // the process is killed when dropped anyway
+            let _ = running.process_mut().kill();
+        }
+        self.finish();
+        Error::Cancelled {
+            command: Box::new(self.command.clone()),
+        }
+    }
+}
+
+impl<R: Run> Future for Queued<R> {
+    type Item = R::Item;
+    type Error = Error;
+
+    fn poll(&mut self) -> Poll<Self::Item, Self::Error> {
+        *self.cancel.task.lock().unwrap() = Some(task::current());
+        if self.cancel.cancelled.load(Ordering::SeqCst) {
+            return Err(self.cancelled());
+        }
+
+        if let JobState::Queued(id) = self.state {
+            if !self.pool.acquire(id) {
+                return Ok(Async::NotReady);
+            }
+            match Process::spawn(self.command.clone()) {
+                Ok(process) => self.state = JobState::Running(Box::new(R::run(process))),
+                Err(error) => {
+                    self.state = JobState::Done;
+                    self.pool.release();
+                    return Err(error);
+                }
+            }
+        }
+
+        let result = match &mut self.state {
+            JobState::Running(running) => running.poll(),
+            _ => panic!("cannot poll Job twice after completion"),
+        };
+        if !matches!(result, Ok(Async::NotReady)) {
+            self.finish();
+        }
+        result
+    }
+}
+
+impl<R> Queued<R> {
+    fn finish(&mut self) {
+        match std::mem::replace(&mut self.state, JobState::Done) {
+            JobState::Queued(id) => self.pool.dequeue(id),
+            JobState::Running(_) => self.pool.release(),
+            JobState::Done => {}
+        }
+    }
+}
+
+impl<R> Drop for Queued<R> {
+    fn drop(&mut self) {
+        self.finish();
+    }
+}
+
+impl Run for CollectOutput {
+    #[inline]
+    fn run(process: Process) -> Self {
+        process.output()
+    }
+
+    #[inline]
+    fn process(&self) -> &Process {
+        self.process()
+    }
+
+    #[inline]
+    fn process_mut(&mut self) -> &mut Process {
+        self.process_mut()
+    }
+}
+
+impl Run for TailOutput {
+    #[inline]
+    fn run(process: Process) -> Self {
+        TailOutput::new(process)
+    }
+
+    #[inline]
+    fn process(&self) -> &Process {
+        self.process()
+    }
+
+    #[inline]
+    fn process_mut(&mut self) -> &mut Process {
+        self.process_mut()
+    }
+}
+
+impl CancelJob {
+    /// cancel the job: it is removed from the queue, or its process is
+    /// killed if it is already running
+    ///
+    /// the job then fails with `Error::Cancelled`.
+    pub fn cancel(&self) {
+        self.cancel.cancelled.store(true, Ordering::SeqCst);
+        if let Some(task) = self.cancel.task.lock().unwrap().take() {
+            task.notify();
+        }
+    }
+
+    /// whether the job has been cancelled
+    pub fn is_cancelled(&self) -> bool {
+        self.cancel.cancelled.load(Ordering::SeqCst)
+    }
+}
+
+#[cfg(all(test, unix))]
+mod test {
+    use super::*;
+    use crate::{Program, Result};
+    use futures::future;
+    use std::time::{Duration, Instant};
+
+    fn sleep(seconds: &str) -> Result<Command> {
+        let mut cmd = Command::new(Program::new("sleep")?);
+        cmd.argument(seconds);
+        Ok(cmd)
+    }
+
+    #[test]
+    fn limit_running_processes() -> Result<()> {
+        let pool = ProcessPool::new(2);
+        let jobs = (0..4)
+            .map(|_| sleep("0.2").map(|cmd| pool.spawn(cmd)))
+            .collect::<Result<Vec<_>>>()?;
+        assert_eq!(pool.queued(), 4);
+
+        let observer = pool.clone();
+        let mut max_running = 0;
+        let watch = future::poll_fn(move || -> Poll<usize, Error> {
+            max_running = max_running.max(observer.running());
+            if observer.queued() == 0 && observer.running() == 0 {
+                Ok(Async::Ready(max_running))
+            } else {
+                task::current().notify();
+                Ok(Async::NotReady)
+            }
+        });
+
+        let start = Instant::now();
+        let mut runtime = tokio::runtime::Runtime::new().unwrap();
+        let (outputs, max_running) = runtime.block_on(future::join_all(jobs).join(watch))?;
+
+        assert!(outputs.iter().all(|output| output.status.success()));
+        assert_eq!(max_running, 2);
+        assert!(start.elapsed() >= Duration::from_millis(400));
+
+        Ok(())
+    }
+
+    #[test]
+    fn slots_in_queue_order() -> Result<()> {
+        let pool = ProcessPool::new(1);
+        let mut first = pool.spawn(sleep("0")?);
+        let mut second = pool.spawn(sleep("0")?);
+
+        let mut runtime = tokio::runtime::current_thread::Runtime::new().unwrap();
+        runtime.block_on(future::lazy(|| -> Result<()> {
+            // the slot is kept for the first job
+            assert!(second.poll()?.is_not_ready());
+            assert_eq!(pool.running(), 0);
+            assert!(first.poll()?.is_not_ready());
+            assert_eq!(pool.running(), 1);
+            Ok(())
+        }))?;
+        runtime.block_on(first)?;
+
+        let mut third = pool.spawn(sleep("0")?);
+        runtime.block_on(future::lazy(|| -> Result<()> {
+            assert!(third.poll()?.is_not_ready());
+            assert_eq!(pool.running(), 0);
+            assert!(second.poll()?.is_not_ready());
+            assert_eq!(pool.running(), 1);
+            Ok(())
+        }))?;
+        runtime.block_on(second.join(third))?;
+
+        Ok(())
+    }
+
+    #[test]
+    fn cancel_running_job() -> Result<()> {
+        let pool = ProcessPool::new(1);
+        let running = pool.spawn(sleep("10")?);
+        let queued = pool.spawn(sleep("0")?);
+        let cancel = running.cancel_handle();
+
+        let mut runtime = tokio::runtime::Runtime::new().unwrap();
+        let cancelling =
+            tokio_timer::Delay::new(Instant::now() + Duration::from_millis(100)).then(move |_| {
+                cancel.cancel();
+                Ok::<_, Error>(())
+            });
+        let running = running.then(Ok::<_, Error>);
+        let (result, ()) = runtime.block_on(running.join(cancelling))?;
+
+        match result.expect_err("the job should be cancelled") {
+            Error::Cancelled { .. } => {}
+            error => panic!("unexpected error: {}", error),
+        }
+        assert_eq!(pool.running(), 0);
+        assert!(runtime.block_on(queued)?.status.success());
+
+        Ok(())
+    }
+}
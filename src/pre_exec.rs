@@ -0,0 +1,304 @@
+use crate::{Command, Error};
+use std::{
+    fmt, hash, io,
+    os::unix::{io::RawFd, process::CommandExt as _},
+    sync::Arc,
+};
+
+/// a step run in the child before `exec`
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) enum Step {
+    Groups = 1,
+    Gid = 2,
+    Uid = 3,
+    #[cfg(target_os = "linux")]
+    Sandbox = 4,
+    #[cfg(all(
+        target_os = "linux",
+        any(target_arch = "x86_64", target_arch = "aarch64")
+    ))]
+    Seccomp = 5,
+    Hook = 6,
+}
+
+/// a closure run in the child before `exec`, set with
+/// [`Command::pre_exec`]: two handles are equal if they share the same
+/// closure
+///
+/// [`Command::pre_exec`]: ./struct.Command.html#method.pre_exec
+#[derive(Clone)]
+pub(crate) struct PreExecHook(Arc<dyn Fn() -> io::Result<()> + Send + Sync>);
+
+/// the pipe the child writes the step that failed to, closed on `exec`
+pub(crate) struct Report {
+    read: RawFd,
+    write: RawFd,
+}
+
+/// the write end of a [`Report`], used in the child
+///
+/// [`Report`]: ./struct.Report.html
+#[derive(Debug, Clone, Copy)]
+pub(crate) struct Reporter(RawFd);
+
+/// the step that failed, carried by the `io::Error` of the spawn
+#[derive(Debug)]
+struct Failure {
+    step: Step,
+    source: io::Error,
+}
+
+impl Report {
+    /// set the steps of the command to run before `exec`, `None` if
+    /// there are none
+    pub(crate) fn prepare(
+        command: &Command,
+        process: &mut std::process::Command,
+    ) -> io::Result<Option<Self>> {
+        let credentials = command.credentials();
+        #[cfg(target_os = "linux")]
+        let sandboxed = command.sandbox_options().is_some();
+        #[cfg(not(target_os = "linux"))]
+        let sandboxed = false;
+        #[cfg(all(
+            target_os = "linux",
+            any(target_arch = "x86_64", target_arch = "aarch64")
+        ))]
+        let filtered = command.syscall_filter_options().is_some();
+        #[cfg(not(all(
+            target_os = "linux",
+            any(target_arch = "x86_64", target_arch = "aarch64")
+        )))]
+        let filtered = false;
+        if !credentials.is_set() && !sandboxed && command.pre_exec_hooks().is_empty() && !filtered {
+            return Ok(None);
+        }
+
+        let report = Report::new()?;
+        credentials.apply(process, report.reporter());
+        #[cfg(target_os = "linux")]
+        if let Some(sandbox) = command.sandbox_options() {
+            sandbox.apply(command, process, report.reporter());
+        }
+        for hook in command.pre_exec_hooks() {
+            let hook = hook.clone();
+            let reporter = report.reporter();
+            unsafe {
+                process.pre_exec(move || (hook.0)().inspect_err(|_| reporter.report(Step::Hook)));
+            }
+        }
+        // last, the other steps may need the filtered system calls
+        #[cfg(all(
+            target_os = "linux",
+            any(target_arch = "x86_64", target_arch = "aarch64")
+        ))]
+        if let Some(filter) = command.syscall_filter_options() {
+            filter.apply(process, report.reporter());
+        }
+        Ok(Some(report))
+    }
+
+    fn new() -> io::Result<Self> {
+        let mut fds = [0; 2];
+        #[cfg(target_os = "linux")]
+        let created = unsafe { libc::pipe2(fds.as_mut_ptr(), libc::O_CLOEXEC) };
+        #[cfg(not(target_os = "linux"))]
+        let created = unsafe { libc::pipe(fds.as_mut_ptr()) };
+        if created != 0 {
+            return Err(io::Error::last_os_error());
+        }
+        let report = Report {
+            read: fds[0],
+            write: fds[1],
+        };
+        #[cfg(not(target_os = "linux"))]
+        for fd in fds.iter() {
+            unsafe { libc::fcntl(*fd, libc::F_SETFD, libc::FD_CLOEXEC) };
+        }
+        // the step is written before the spawn fails, no need to wait
+        unsafe { libc::fcntl(report.read, libc::F_SETFL, libc::O_NONBLOCK) };
+        Ok(report)
+    }
+
+    fn reporter(&self) -> Reporter {
+        Reporter(self.write)
+    }
+
+    /// the error of the spawn, with the step that failed
+    pub(crate) fn error(self, source: io::Error) -> io::Error {
+        let mut step = 0u8;
+        let read = unsafe { libc::read(self.read, &mut step as *mut u8 as *mut _, 1) };
+        let step = match (read, step) {
+            (1, 1) => Step::Groups,
+            (1, 2) => Step::Gid,
+            (1, 3) => Step::Uid,
+            #[cfg(target_os = "linux")]
+            (1, 4) => Step::Sandbox,
+            #[cfg(all(
+                target_os = "linux",
+                any(target_arch = "x86_64", target_arch = "aarch64")
+            ))]
+            (1, 5) => Step::Seccomp,
+            (1, 6) => Step::Hook,
+            _ => return source,
+        };
+        io::Error::new(source.kind(), Failure { step, source })
+    }
+}
+
+impl Drop for Report {
+    fn drop(&mut self) {
+        unsafe {
+            libc::close(self.read);
+            libc::close(self.write);
+        }
+    }
+}
+
+impl Reporter {
+    /// report that `step` failed, with the error of the last system call
+    ///
+    /// only async-signal-safe functions are called.
+    pub(crate) fn fail(self, step: Step) -> io::Error {
+        let error = io::Error::last_os_error();
+        self.report(step);
+        error
+    }
+
+    /// report that `step` failed
+    fn report(self, step: Step) {
+        unsafe { libc::write(self.0, &(step as u8) as *const u8 as *const _, 1) };
+    }
+}
+
+/// the error of a spawn that failed in one of the steps run before
+/// `exec`, the `io::Error` is given back otherwise
+pub(crate) fn spawn_error(
+    command: &Command,
+    source: io::Error,
+) -> std::result::Result<Error, io::Error> {
+    if !source.get_ref().is_some_and(|inner| inner.is::<Failure>()) {
+        return Err(source);
+    }
+    let Failure { step, source } = *source
+        .into_inner()
+        .and_then(|inner| inner.downcast::<Failure>().ok())
+        .expect("checked above");
+    Ok(match step {
+        #[cfg(target_os = "linux")]
+        Step::Sandbox => Error::CannotSandbox {
+            command: Box::new(command.clone()),
+            source,
+        },
+        #[cfg(all(
+            target_os = "linux",
+            any(target_arch = "x86_64", target_arch = "aarch64")
+        ))]
+        Step::Seccomp => Error::CannotFilterSyscalls {
+            command: Box::new(command.clone()),
+            source,
+        },
+        Step::Hook => Error::PreExec {
+            command: Box::new(command.clone()),
+            source,
+        },
+        step => command.credentials().error(command, step, source),
+    })
+}
+
+impl PreExecHook {
+    pub(crate) fn new<F>(hook: F) -> Self
+    where
+        F: Fn() -> io::Result<()> + Send + Sync + 'static,
+    {
+        PreExecHook(Arc::new(hook))
+    }
+}
+
+impl PartialEq for PreExecHook {
+    fn eq(&self, other: &Self) -> bool {
+        Arc::ptr_eq(&self.0, &other.0)
+    }
+}
+
+impl Eq for PreExecHook {}
+
+impl hash::Hash for PreExecHook {
+    fn hash<H: hash::Hasher>(&self, state: &mut H) {
+        (Arc::as_ptr(&self.0) as *const u8).hash(state)
+    }
+}
+
+impl fmt::Debug for PreExecHook {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "PreExecHook({:p})", Arc::as_ptr(&self.0))
+    }
+}
+
+impl fmt::Display for Failure {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        self.source.fmt(f)
+    }
+}
+
+impl std::error::Error for Failure {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        Some(&self.source)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use crate::{Command, Error, Program, Result};
+    use futures::Future as _;
+    use std::io;
+
+    #[test]
+    fn hooks_run_before_exec() -> Result<()> {
+        let niceness = unsafe { libc::getpriority(libc::PRIO_PROCESS, 0) };
+        let mut command = Command::new(Program::new("nice")?);
+        unsafe {
+            command
+                .pre_exec(|| {
+                    libc::nice(2);
+                    Ok(())
+                })
+                .pre_exec(|| {
+                    libc::nice(3);
+                    Ok(())
+                });
+        }
+
+        // the hooks are shared by the clones
+        let clone = command.clone();
+        assert_eq!(clone, command);
+        for command in [command, clone] {
+            let output = command.spawn()?.output().wait()?;
+            let expected = format!("{}\n", (niceness + 5).min(19));
+            assert_eq!(String::from_utf8_lossy(&output.stdout), expected);
+        }
+        Ok(())
+    }
+
+    #[test]
+    fn failing_hook() -> Result<()> {
+        let mut command = Command::new(Program::new("true")?);
+        unsafe {
+            command.pre_exec(|| Err(io::Error::from_raw_os_error(libc::EACCES)));
+        }
+
+        let error = command.spawn().err().expect("the hook fails");
+        assert_eq!(error.to_string(), "pre-exec hook failed for command 'true'");
+        match error {
+            Error::PreExec {
+                command: failed,
+                source,
+            } => {
+                assert_eq!(*failed, command);
+                assert_eq!(source.raw_os_error(), Some(libc::EACCES));
+            }
+            error => panic!("unexpected error: {}", error),
+        }
+        Ok(())
+    }
+}
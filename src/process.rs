@@ -1,9 +1,14 @@
+#[cfg(any(feature = "log", feature = "tracing"))]
+use crate::forward::Forwarder;
 use crate::{
-    Command, Control, Error, ErrorKind, Result, ResultExt as _, StandardError, StandardInput,
-    StandardOutput,
+    drain::{LineDrain, Pipe},
+    trace::Lifecycle,
+    Child, ChildStderr, ChildStdin, ChildStdout, CollectOutput, Command, Control, Error,
+    OutputTail, Readiness, ResourceUsage, Result, Spawned, StandardError, StandardInput,
+    StandardOutput, WaitReady,
 };
-use futures::prelude::*;
-use tokio_process::{ChildStderr, ChildStdin, ChildStdout, CommandExt as _};
+use futures::{prelude::*, try_ready};
+use std::{mem, process::ExitStatus, time::Duration};
 
 /// a `Process` object to monitor the execution of a [`Command`].
 ///
@@ -14,7 +19,51 @@ use tokio_process::{ChildStderr, ChildStdin, ChildStdout, CommandExt as _};
 /// [`Command`]: ./struct.Command.html
 pub struct Process {
     command: Command,
-    process: tokio_process::Child,
+    process: Box<dyn Child>,
+    stdin: Option<ChildStdin>,
+    stdout: Option<ChildStdout>,
+    stderr: Option<ChildStderr>,
+    drained: Drained,
+    lifecycle: Lifecycle,
+    resource_usage: Option<ResourceUsage>,
+}
+
+/// future returned by [`Process::expect_success`]
+///
+/// [`Process::expect_success`]: ./struct.Process.html#method.expect_success
+pub struct ExpectSuccess {
+    process: Process,
+}
+
+/// future returned by [`Process::with_resource_usage`]
+///
+/// [`Process::with_resource_usage`]: ./struct.Process.html#method.with_resource_usage
+pub struct WithResourceUsage {
+    process: Process,
+}
+
+/// the pipes drained while the process is polled and where their
+/// lines go: the [`OutputTail`] and/or the logs
+///
+/// [`OutputTail`]: ./struct.OutputTail.html
+#[derive(Default)]
+struct Drained {
+    tail: Option<OutputTail>,
+    #[cfg(any(feature = "log", feature = "tracing"))]
+    forwarder: Option<Forwarder>,
+    stdout: Option<LineDrain>,
+    stderr: Option<LineDrain>,
+    watch: Option<Watch>,
+}
+
+/// the lines of a pipe kept for a [`WaitReady`] until it takes them
+///
+/// [`WaitReady`]: ./struct.WaitReady.html
+struct Watch {
+    pipe: Pipe,
+    lines: Vec<String>,
+    /// the pipe is only drained for the watch
+    added: bool,
 }
 
 impl Process {
@@ -29,23 +78,246 @@ impl Process {
     /// [`Program`]: ./struct.Program.html
     /// [`Command`]: ./struct.Command.html
     pub fn spawn(command: Command) -> Result<Self> {
-        let mut cmd = command.process_command();
-        let process = cmd
-            .spawn_async()
-            .chain_err(|| ErrorKind::CannotSpawnCommand(command.clone()))?;
-        Ok(Process { command, process })
+        let mut lifecycle = Lifecycle::spawning(&command);
+        let Spawned {
+            child: process,
+            stdin,
+            stdout,
+            stderr,
+        } = command
+            .spawner_ref()
+            .spawn(&command)
+            .inspect_err(|error| lifecycle.cannot_spawn(error))
+            .map_err(|source| Error::cannot_spawn(&command, source))?;
+        lifecycle.spawned(process.id());
+        let drained = Drained::new(&command, process.id());
+        Ok(Process {
+            command,
+            process,
+            stdin,
+            stdout,
+            stderr,
+            drained,
+            lifecycle,
+            resource_usage: None,
+        })
     }
 
     pub fn stdin(&mut self) -> &mut Option<ChildStdin> {
-        self.process.stdin()
+        &mut self.stdin
     }
 
     pub fn stdout(&mut self) -> &mut Option<ChildStdout> {
-        self.process.stdout()
+        &mut self.stdout
     }
 
     pub fn stderr(&mut self) -> &mut Option<ChildStderr> {
-        self.process.stderr()
+        &mut self.stderr
+    }
+
+    /// the last lines printed by the process so far
+    ///
+    /// this is `None` unless [`Command::output_tail`] was set before
+    /// spawning the process.
+    ///
+    /// [`Command::output_tail`]: ./struct.Command.html#method.output_tail
+    pub fn output_tail(&self) -> Option<&OutputTail> {
+        self.drained.tail.as_ref()
+    }
+
+    /// wait for the process to finish and fail if it did not exit
+    /// successfully
+    ///
+    /// the returned error will display the [`OutputTail`] if
+    /// [`Command::output_tail`] was set.
+    ///
+    /// [`OutputTail`]: ./struct.OutputTail.html
+    /// [`Command::output_tail`]: ./struct.Command.html#method.output_tail
+    pub fn expect_success(self) -> ExpectSuccess {
+        ExpectSuccess { process: self }
+    }
+
+    /// wait for the process to finish and collect all its standard output
+    /// and standard error output
+    ///
+    /// the standard input is closed so the process does not wait on it.
+    pub fn output(self) -> CollectOutput {
+        CollectOutput::new(self)
+    }
+
+    /// wait for the process to be ready: it resolves to the `Process`
+    /// once the [`Readiness`] condition holds.
+    ///
+    /// The future fails if the process exits before being ready or if it is
+    /// not ready after `timeout`, the error then displays the output seen
+    /// so far: the lines read for the conditions on the output, otherwise
+    /// the [`OutputTail`] if [`Command::output_tail`] was set.
+    ///
+    /// The conditions on the output see the lines as the process is polled,
+    /// like the [`OutputTail`] and the forwarded output do: the lines read
+    /// until the process is ready won't be captured afterward. The timer
+    /// requires a `tokio` runtime.
+    ///
+    /// [`Readiness`]: ./struct.Readiness.html
+    /// [`OutputTail`]: ./struct.OutputTail.html
+    /// [`Command::output_tail`]: ./struct.Command.html#method.output_tail
+    pub fn ready_when(self, readiness: Readiness, timeout: Duration) -> WaitReady {
+        WaitReady::new(self, readiness, timeout)
+    }
+
+    /// the resources used by the process, available once it has finished
+    ///
+    /// only collected on linux, this is always `None` on the other
+    /// platforms. See [`Process::with_resource_usage`].
+    ///
+    /// [`Process::with_resource_usage`]: ./struct.Process.html#method.with_resource_usage
+    pub fn resource_usage(&self) -> Option<&ResourceUsage> {
+        self.resource_usage.as_ref()
+    }
+
+    /// wait for the process to finish and yield its [`ResourceUsage`]
+    /// alongside the exit status
+    ///
+    /// The resource usage is collected right before the process is reaped,
+    /// it is `None` if it could not be collected (not on linux, or the
+    /// process has been reaped before).
+    ///
+    /// [`ResourceUsage`]: ./struct.ResourceUsage.html
+    pub fn with_resource_usage(self) -> WithResourceUsage {
+        WithResourceUsage { process: self }
+    }
+
+    /// keep the lines of `pipe` as it is drained, until [`unwatch`]
+    ///
+    /// [`unwatch`]: #method.unwatch
+    pub(crate) fn watch(&mut self, pipe: Pipe) {
+        let drain = match pipe {
+            Pipe::Stdout => &mut self.drained.stdout,
+            Pipe::Stderr => &mut self.drained.stderr,
+        };
+        let added = drain.is_none();
+        if added {
+            *drain = Some(LineDrain::new());
+        }
+        self.drained.watch = Some(Watch {
+            pipe,
+            lines: Vec::new(),
+            added,
+        });
+    }
+
+    /// the lines of the watched pipe drained since the previous call
+    pub(crate) fn watched_lines(&mut self) -> Vec<String> {
+        self.drained
+            .watch
+            .as_mut()
+            .map(|watch| mem::take(&mut watch.lines))
+            .unwrap_or_default()
+    }
+
+    /// stop keeping the lines of the watched pipe, the pipe can be
+    /// captured again if it was only drained for the watch
+    pub(crate) fn unwatch(&mut self) {
+        if let Some(watch) = self.drained.watch.take() {
+            if watch.added {
+                match watch.pipe {
+                    Pipe::Stdout => self.drained.stdout = None,
+                    Pipe::Stderr => self.drained.stderr = None,
+                }
+            }
+        }
+    }
+
+    /// tell if the lines of `pipe` are drained as the process is polled
+    pub(crate) fn drains(&self, pipe: Pipe) -> bool {
+        match pipe {
+            Pipe::Stdout => self.drained.stdout.is_some(),
+            Pipe::Stderr => self.drained.stderr.is_some(),
+        }
+    }
+
+    /// a copy of the current tail, empty if the output was not drained
+    pub(crate) fn output_tail_snapshot(&self) -> OutputTail {
+        self.output_tail().cloned().unwrap_or_default()
+    }
+
+    /// read everything currently available from the drained pipes
+    ///
+    /// once the process has `exited`, read what is left in the pipes
+    /// without waiting for the reactor.
+    fn drain_output(&mut self, exited: bool) -> std::io::Result<()> {
+        let Drained {
+            tail,
+            #[cfg(any(feature = "log", feature = "tracing"))]
+            forwarder,
+            stdout,
+            stderr,
+            watch,
+        } = &mut self.drained;
+        let mut on_line = |pipe, line: String| {
+            #[cfg(any(feature = "log", feature = "tracing"))]
+            {
+                if let Some(forwarder) = forwarder {
+                    forwarder.forward(pipe, &line);
+                }
+            }
+            if let Some(watch) = watch {
+                if watch.pipe == pipe {
+                    watch.lines.push(line.clone());
+                }
+            }
+            if let Some(tail) = tail {
+                if pipe == Pipe::Stderr || tail.options().stdout() {
+                    tail.push(line);
+                }
+            }
+        };
+
+        if let (Some(drain), Some(pipe)) = (stderr, &mut self.stderr) {
+            if exited {
+                drain.drain_remaining(pipe, |line| on_line(Pipe::Stderr, line))?;
+            } else {
+                drain.poll_drain(pipe, |line| on_line(Pipe::Stderr, line))?;
+            }
+        }
+        if let (Some(drain), Some(pipe)) = (stdout, &mut self.stdout) {
+            if exited {
+                drain.drain_remaining(pipe, |line| on_line(Pipe::Stdout, line))?;
+            } else {
+                drain.poll_drain(pipe, |line| on_line(Pipe::Stdout, line))?;
+            }
+        }
+
+        Ok(())
+    }
+}
+
+impl Drained {
+    fn new(command: &Command, #[allow(unused_variables)] pid: u32) -> Self {
+        let mut drained = Drained::default();
+
+        if let Some(options) = command.output_tail_options() {
+            drained.tail = Some(OutputTail::new(options));
+            drained.stderr = Some(LineDrain::new());
+            if options.stdout() {
+                drained.stdout = Some(LineDrain::new());
+            }
+        }
+
+        #[cfg(any(feature = "log", feature = "tracing"))]
+        {
+            if let Some(options) = command.forward_output_options() {
+                if options.forwards(Pipe::Stderr) {
+                    drained.stderr = Some(LineDrain::new());
+                }
+                if options.forwards(Pipe::Stdout) {
+                    drained.stdout = Some(LineDrain::new());
+                }
+                drained.forwarder = Some(Forwarder::new(options, command, pid));
+            }
+        }
+
+        drained
     }
 }
 
@@ -66,16 +338,33 @@ impl Control for Process {
     /// this is equivalent to `SIGKILL` on unix platform
     #[inline]
     fn kill(&mut self) -> Result<()> {
-        self.process
-            .kill()
-            .chain_err(|| ErrorKind::CannotKillProcess(self.command().clone(), self.id()))
+        let result = self.process.kill();
+        self.lifecycle.killed(&result);
+        result.map_err(|source| Error::CannotKillProcess {
+            command: Box::new(self.command().clone()),
+            id: self.id(),
+            source,
+        })
+    }
+
+    /// ask the process to finish
+    ///
+    /// this is equivalent to `SIGTERM` on unix platform
+    fn terminate(&mut self) -> Result<()> {
+        let result = self.process.terminate();
+        self.lifecycle.terminated(&result);
+        result.map_err(|source| Error::CannotKillProcess {
+            command: Box::new(self.command().clone()),
+            id: self.id(),
+            source,
+        })
     }
 }
 
 impl<'a> StandardInput<'a> for Process {
     #[inline]
     fn standard_input(&mut self) -> &mut ChildStdin {
-        match self.process.stdin() {
+        match &mut self.stdin {
             None => unreachable!(),
             Some(stdin) => stdin,
         }
@@ -85,7 +374,7 @@ impl<'a> StandardInput<'a> for Process {
 impl<'a> StandardOutput<'a> for Process {
     #[inline]
     fn standard_output(&mut self) -> &mut ChildStdout {
-        match self.process.stdout() {
+        match &mut self.stdout {
             None => unreachable!(),
             Some(stdout) => stdout,
         }
@@ -95,7 +384,7 @@ impl<'a> StandardOutput<'a> for Process {
 impl<'a> StandardError<'a> for Process {
     #[inline]
     fn standard_error(&mut self) -> &mut ChildStderr {
-        match self.process.stderr() {
+        match &mut self.stderr {
             None => unreachable!(),
             Some(stderr) => stderr,
         }
@@ -103,18 +392,73 @@ impl<'a> StandardError<'a> for Process {
 }
 
 impl Future for Process {
-    type Item = <tokio_process::Child as Future>::Item;
+    type Item = ExitStatus;
+    type Error = Error;
+
+    fn poll(&mut self) -> Poll<Self::Item, Self::Error> {
+        let result = self
+            .drain_output(false)
+            .and_then(|()| self.process.poll_exit())
+            .and_then(|status| {
+                if status.is_ready() {
+                    self.resource_usage = self.process.resource_usage();
+                    // collect what has been printed right before exiting
+                    self.drain_output(true)?;
+                }
+                Ok(status)
+            });
+
+        match &result {
+            Ok(Async::Ready(status)) => self.lifecycle.exited(*status, self.resource_usage),
+            Ok(Async::NotReady) => (),
+            Err(error) => self.lifecycle.wait_failed(error),
+        }
+
+        result.map_err(|source| Error::Poll {
+            command: Box::new(self.command.clone()),
+            id: self.process.id(),
+            tail: self.output_tail_snapshot(),
+            source,
+        })
+    }
+}
+
+impl Future for ExpectSuccess {
+    type Item = ();
+    type Error = Error;
+
+    fn poll(&mut self) -> Poll<Self::Item, Self::Error> {
+        let status: ExitStatus = try_ready!(self.process.poll());
+
+        if status.success() {
+            Ok(Async::Ready(()))
+        } else {
+            Err(Error::ExitStatus {
+                command: Box::new(self.process.command().clone()),
+                id: self.process.id(),
+                status,
+                tail: self.process.output_tail_snapshot(),
+            })
+        }
+    }
+}
+
+impl Future for WithResourceUsage {
+    type Item = (ExitStatus, Option<ResourceUsage>);
     type Error = Error;
 
-    #[inline]
     fn poll(&mut self) -> Poll<Self::Item, Self::Error> {
-        self.process
-            .poll()
-            .chain_err(|| ErrorKind::Poll(self.command.clone()))
+        let status = try_ready!(self.process.poll());
+
+        Ok(Async::Ready((status, self.process.resource_usage)))
     }
 }
 
 #[cfg(test)]
+#[allow(
+    clippy::unnecessary_to_owned,
+    clippy::needless_borrows_for_generic_args
+)]
 mod test {
     use super::*;
     use crate::Program;
@@ -153,6 +497,89 @@ mod test {
         Ok(())
     }
 
+    #[test]
+    fn output_tail_in_exit_status_error() -> Result<()> {
+        use crate::TailOptions;
+
+        let mut cmd = Command::new(Program::new("rustc")?);
+        cmd.arguments(["file-that-does-not-exist"])
+            .output_tail(TailOptions::new(5));
+
+        let error = Process::spawn(cmd)?
+            .expect_success()
+            .wait()
+            .expect_err("rustc should fail to compile a missing file");
+
+        match &error {
+            Error::ExitStatus { status, tail, .. } => {
+                assert!(!status.success());
+                assert!(tail
+                    .lines()
+                    .any(|line| line.contains("file-that-does-not-exist")));
+            }
+            _ => panic!("unexpected error: {}", error),
+        }
+        assert!(error.to_string().contains("\n  | error: couldn't read"));
+
+        Ok(())
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn terminate_gracefully() -> Result<()> {
+        let mut cmd = Command::new(Program::new("sh")?);
+        cmd.arguments([
+            "-c",
+            "trap 'echo bye; exit 3' TERM; echo ready; sleep 10 & wait",
+        ]);
+
+        let mut process = Process::spawn(cmd)?;
+        let ready = process
+            .framed_stdout(LinesCodec::new())
+            .wait()
+            .next()
+            .unwrap()?;
+        assert_eq!(ready, "ready");
+        process.terminate()?;
+
+        assert_eq!(process.wait()?.code(), Some(3));
+        Ok(())
+    }
+
+    #[cfg(target_os = "linux")]
+    #[test]
+    fn resource_usage_on_exit() -> Result<()> {
+        let mut cmd = Command::new(Program::new("rustc")?);
+        cmd.arguments(["--version"]);
+
+        let (status, usage) = Process::spawn(cmd)?.with_resource_usage().wait()?;
+
+        assert!(status.success());
+        let usage = usage.expect("resource usage should be collected on linux");
+        assert!(usage.max_resident_set_size > 0);
+        assert!(usage.minor_page_faults > 0);
+
+        Ok(())
+    }
+
+    #[cfg(target_os = "linux")]
+    #[test]
+    fn resource_usage_of_short_lived_processes() -> Result<()> {
+        let program = Program::new("true")?;
+
+        // the processes exit while being polled, their usage is still
+        // collected before they are reaped
+        for _ in 0..50 {
+            let (status, usage) = Process::spawn(Command::new(program.clone()))?
+                .with_resource_usage()
+                .wait()?;
+            assert!(status.success());
+            assert!(usage.is_some());
+        }
+
+        Ok(())
+    }
+
     fn send_and_check<P, I>(process: P, item: I) -> Result<P>
     where
         P: Stream<Item = I, Error = Error> + Sink<SinkItem = I, SinkError = Error>,
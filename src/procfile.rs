@@ -0,0 +1,234 @@
+use crate::{Command, Error, Program, Result};
+use std::{
+    collections::BTreeMap,
+    path::{Path, PathBuf},
+};
+
+/// the processes of a `Procfile`, every `name: command line` entry being
+/// a [`Command`] run with `/bin/sh -c`
+///
+/// ```text
+/// # comments and empty lines are ignored
+/// web: bundle exec rails server -p $PORT
+/// worker: bundle exec sidekiq
+/// ```
+///
+/// the environment variables of a `.env` file can be added to all the
+/// commands with [`env_file`].
+///
+/// [`Command`]: ./struct.Command.html
+/// [`env_file`]: #method.env_file
+#[derive(Debug, Clone)]
+pub struct Procfile {
+    processes: Vec<(String, Command)>,
+}
+
+impl Procfile {
+    /// load the `Procfile` at the given path
+    ///
+    /// # Error
+    ///
+    /// fails with [`Error::Load`] if the file cannot be read, if a line is
+    /// not a `name: command line` entry or if a name is used twice.
+    ///
+    /// [`Error::Load`]: ./enum.Error.html#variant.Load
+    pub fn load<P: AsRef<Path>>(path: P) -> Result<Self> {
+        let path = path.as_ref();
+        let content = read(path)?;
+        let shell = Program::new("/bin/sh")?;
+
+        let mut processes: Vec<(String, Command)> = Vec::new();
+        for (number, line) in lines(&content) {
+            let (name, command_line) = line
+                .split_once(':')
+                .map(|(name, command_line)| (name.trim(), command_line.trim()))
+                .filter(|(name, command_line)| {
+                    is_identifier(name, |c| c.is_ascii_alphanumeric() || c == '_' || c == '-')
+                        && !command_line.is_empty()
+                })
+                .ok_or_else(|| error(path, number, "expected `name: command line`".to_owned()))?;
+            if processes.iter().any(|(existing, _)| existing == name) {
+                return Err(error(
+                    path,
+                    number,
+                    format!("process '{}' is defined twice", name),
+                ));
+            }
+            processes.push((
+                name.to_owned(),
+                Command::shell_with(shell.clone(), command_line),
+            ));
+        }
+
+        Ok(Procfile { processes })
+    }
+
+    /// set the variables of the `.env` file at the given path in the
+    /// environment of every command
+    ///
+    /// every line is a `KEY=value` (optionally preceded by `export`), the
+    /// value may be quoted with `'` (kept as is) or `"` (where `\n`, `\"`
+    /// and `\\` are escaped). Comments start with `#`.
+    ///
+    /// `.env` files usually hold credentials: the values are secret, they
+    /// are displayed as `***` with the commands.
+    pub fn env_file<P: AsRef<Path>>(&mut self, path: P) -> Result<&mut Self> {
+        let variables = load_env_file(path.as_ref())?;
+        for (_, command) in self.processes.iter_mut() {
+            for (key, value) in variables.iter() {
+                command.secret_environment_variable(key, value);
+            }
+        }
+        Ok(self)
+    }
+
+    /// the names and commands of the processes, in the order of the file
+    pub fn processes(&self) -> impl Iterator<Item = (&str, &Command)> {
+        self.processes
+            .iter()
+            .map(|(name, command)| (name.as_str(), command))
+    }
+
+    /// the command of the process with the given name
+    pub fn get(&self, name: &str) -> Option<&Command> {
+        self.processes
+            .iter()
+            .find(|(existing, _)| existing == name)
+            .map(|(_, command)| command)
+    }
+}
+
+fn load_env_file(path: &Path) -> Result<BTreeMap<String, String>> {
+    let content = read(path)?;
+
+    let mut variables = BTreeMap::new();
+    for (number, line) in lines(&content) {
+        let line = line.strip_prefix("export ").unwrap_or(line);
+        let (key, value) = line
+            .split_once('=')
+            .map(|(key, value)| (key.trim(), value.trim()))
+            .filter(|(key, _)| {
+                is_identifier(key, |c| c.is_ascii_alphanumeric() || c == '_')
+                    && !key.starts_with(|c: char| c.is_ascii_digit())
+            })
+            .ok_or_else(|| error(path, number, "expected `KEY=value`".to_owned()))?;
+        let value = env_value(value)
+            .ok_or_else(|| error(path, number, format!("unterminated value of '{}'", key)))?;
+        variables.insert(key.to_owned(), value);
+    }
+    Ok(variables)
+}
+
+/// the value of a variable of a `.env` file, `None` if a quote is missing
+fn env_value(value: &str) -> Option<String> {
+    if let Some(quoted) = value.strip_prefix('\'') {
+        let (value, _comment) = quoted.split_once('\'')?;
+        return Some(value.to_owned());
+    }
+
+    if let Some(quoted) = value.strip_prefix('"') {
+        let mut result = String::new();
+        let mut chars = quoted.chars();
+        while let Some(c) = chars.next() {
+            match c {
+                '"' => return Some(result),
+                '\\' => match chars.next()? {
+                    'n' => result.push('\n'),
+                    c => result.push(c),
+                },
+                c => result.push(c),
+            }
+        }
+        return None;
+    }
+
+    let value = match value.find(" #") {
+        Some(comment) => &value[..comment],
+        None => value,
+    };
+    Some(value.trim_end().to_owned())
+}
+
+fn read(path: &Path) -> Result<String> {
+    std::fs::read_to_string(path).map_err(|error| Error::Load {
+        path: path.to_owned(),
+        line: None,
+        column: None,
+        reason: "cannot read the file".to_owned(),
+        source: Some(Box::new(error)),
+    })
+}
+
+/// the numbered lines that are neither empty nor comments
+fn lines(content: &str) -> impl Iterator<Item = (usize, &str)> {
+    content
+        .lines()
+        .enumerate()
+        .map(|(index, line)| (index + 1, line.trim()))
+        .filter(|(_, line)| !line.is_empty() && !line.starts_with('#'))
+}
+
+fn is_identifier(name: &str, valid: impl Fn(char) -> bool) -> bool {
+    !name.is_empty() && name.chars().all(valid)
+}
+
+fn error(path: &Path, line: usize, reason: String) -> Error {
+    Error::Load {
+        path: PathBuf::from(path),
+        line: Some(line),
+        column: None,
+        reason,
+        source: None,
+    }
+}
+
+#[cfg(all(test, unix))]
+mod test {
+    use super::*;
+    use crate::temp::TempPath;
+    use futures::Future as _;
+
+    #[test]
+    fn procfile_with_env_file() -> Result<()> {
+        let procfile = TempPath::file(
+            "Procfile",
+            "# the processes\nweb: echo \"$GREETING on $PORT\"\n\nworker:   echo working\n",
+        );
+        let env = TempPath::file(
+            "env",
+            "export PORT=5000 # the port\nGREETING=\"hello\\nworld\"\nQUOTED='$HOME'\n",
+        );
+
+        let mut processes = Procfile::load(&procfile)?;
+        processes.env_file(&env)?;
+
+        let names: Vec<_> = processes.processes().map(|(name, _)| name).collect();
+        assert_eq!(names, ["web", "worker"]);
+        let web = processes.get("web").expect("the web process");
+        assert_eq!(web.environment()["QUOTED"], "$HOME");
+        assert_eq!(
+            web.to_string(),
+            "GREETING=*** PORT=*** QUOTED=*** echo \"$GREETING on $PORT\""
+        );
+
+        let output = web.spawn()?.output().wait()?;
+        assert_eq!(output.stdout, b"hello\nworld on 5000\n");
+        Ok(())
+    }
+
+    #[test]
+    fn invalid_lines() {
+        let procfile = TempPath::file("Procfile.invalid", "web: serve\n\nno command line\n");
+        let error = Procfile::load(&procfile).expect_err("the third line is invalid");
+        assert!(error
+            .to_string()
+            .ends_with(":3: expected `name: command line`"));
+
+        let env = TempPath::file("env.invalid", "KEY=\"unterminated\n");
+        let error = load_env_file(&env).expect_err("the value is unterminated");
+        assert_eq!(error.id(), None);
+        assert!(error
+            .to_string()
+            .ends_with(":1: unterminated value of 'KEY'"));
+    }
+}
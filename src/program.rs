@@ -1,4 +1,4 @@
-use crate::{Command, ErrorKind, Result, ResultExt as _};
+use crate::{Error, OsSpawner, Result, Spawner};
 use std::{ffi, fmt};
 
 /// a program, pre-checked and known to exist in the environment $PATH
@@ -23,34 +23,46 @@ impl Program {
     /// # Error
     ///
     /// the function will fail if the program cannot be found or cannot
-    /// be executed. The following program will return an error of kind
-    /// [`ErrorKind`]::InvalidProgramName:
+    /// be executed. The following program will return an
+    /// [`Error`]::InvalidProgramName:
     ///
     /// ```
-    /// # use bawawa::{Program, ErrorKind};
+    /// # use bawawa::{Program, Error};
     /// let error = Program::new("unknown-program").unwrap_err();
     ///
-    /// match error.kind() {
-    ///   ErrorKind::InvalidProgramName(_) => (),
+    /// match error {
+    ///   Error::InvalidProgramName { .. } => (),
     /// #   _ => panic!("wrong error, {:?}", error)
     ///   // ...
     /// }
     /// ```
     ///
-    /// [`ErrorKind`]: ./enum.ErrorKind.html
+    /// [`Error`]: ./enum.Error.html
     ///
     pub fn new<P: AsRef<str>>(program: P) -> Result<Self> {
-        let program = Program::new_unchecked(program.as_ref().to_owned());
-        let mut cmd = Command::new(program.clone());
-        cmd.arguments(&["--help"]);
-        let child = cmd
-            .spawn()
-            .chain_err(|| ErrorKind::InvalidProgramName(program.clone()))?;
+        Self::with_spawner(program, &OsSpawner)
+    }
 
-        // the process has started successfully
-        // we drop the `child` so it is then killed
-        // see: https://docs.rs/tokio-process/0.2.4/tokio_process/struct.Child.html
-        std::mem::drop(child);
+    /// create a new `Program`, checked by the given [`Spawner`]
+    ///
+    /// this is for the programs of the [`Command`]s spawned with this
+    /// spawner, see [`Command::spawner`].
+    ///
+    /// [`Spawner`]: ./trait.Spawner.html
+    /// [`Command`]: ./struct.Command.html
+    /// [`Command::spawner`]: ./struct.Command.html#method.spawner
+    pub fn with_spawner<P, S>(program: P, spawner: &S) -> Result<Self>
+    where
+        P: AsRef<str>,
+        S: Spawner + ?Sized,
+    {
+        let program = Program::new_unchecked(program.as_ref().to_owned());
+        spawner
+            .check_program(&program)
+            .map_err(|source| Error::InvalidProgramName {
+                program: program.clone(),
+                source,
+            })?;
 
         Ok(program)
     }
@@ -75,33 +87,32 @@ impl fmt::Display for Program {
 }
 
 #[cfg(test)]
+#[allow(clippy::unnecessary_to_owned)]
 mod test {
     use super::*;
 
     #[cfg(unix)]
     #[test]
     fn program_exists() {
-        use crate::error_chain::ChainedError as _;
-
         const PROGRAM_NAME: &str = "sh";
 
         if let Err(error) = Program::new(PROGRAM_NAME.to_owned()) {
-            eprintln!("{}", error.display_chain().to_string());
+            eprintln!("{:?}", error);
             panic!("The program does not seem to exist, we are expected it to");
         }
     }
 
     #[test]
     fn program_does_not_exists() {
-        use crate::error_chain::ChainedError as _;
-
         const PROGRAM_NAME: &str = "the-impossible-program-that-does-not-exist";
 
         let error = Program::new(PROGRAM_NAME.to_owned()).expect_err("program should not exist");
 
-        match error.kind() {
-            ErrorKind::InvalidProgramName(program) => assert_eq!(program.0.as_str(), PROGRAM_NAME),
-            _ => panic!("unexpected error: {}", error.display_chain().to_string()),
+        match error {
+            Error::InvalidProgramName { program, .. } => {
+                assert_eq!(program.0.as_str(), PROGRAM_NAME)
+            }
+            _ => panic!("unexpected error: {:?}", error),
         }
     }
 }
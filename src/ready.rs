@@ -0,0 +1,397 @@
+use crate::{drain::Pipe, Control as _, Error, OutputTail, Process, TailOptions};
+use futures::{Async, Future, Poll, Stream as _};
+use std::{
+    fmt, io,
+    net::{Ipv4Addr, SocketAddr},
+    path::PathBuf,
+    time::{Duration, Instant},
+};
+use tokio_timer::{Delay, Interval};
+
+/// condition for a [`Process`] to be considered ready
+///
+/// see [`Process::ready_when`].
+///
+/// [`Process`]: ./struct.Process.html
+/// [`Process::ready_when`]: ./struct.Process.html#method.ready_when
+pub struct Readiness {
+    condition: Condition,
+}
+
+enum Condition {
+    Line(Pipe, Box<dyn FnMut(&str) -> bool + Send>),
+    TcpPort(u16),
+    File(PathBuf),
+    Probe(Box<dyn FnMut() -> bool + Send>),
+}
+
+/// future returned by [`Process::ready_when`], yields the [`Process`] once
+/// it is ready
+///
+/// [`Process`]: ./struct.Process.html
+/// [`Process::ready_when`]: ./struct.Process.html#method.ready_when
+pub struct WaitReady {
+    process: Option<Process>,
+    condition: Condition,
+    timeout: Duration,
+    deadline: Delay,
+    interval: Interval,
+    connecting: Option<TcpConnect>,
+    seen: OutputTail,
+}
+
+/// a connection to a TCP address, failing with `TimedOut` after a timeout
+///
+/// the readiness and the health checks only tell whether the address
+/// accepts connections: the connection is closed right away.
+pub(crate) struct TcpConnect {
+    connect: tokio_tcp::ConnectFuture,
+    timeout: Delay,
+}
+
+impl Readiness {
+    /// interval between two checks of the conditions that are not
+    /// based on the output of the process
+    const PROBE_INTERVAL: Duration = Duration::from_millis(50);
+
+    /// number of lines of the output kept to report readiness errors
+    const SEEN_LINES: usize = 20;
+
+    /// ready once a line of the standard output matches
+    pub fn stdout_line<F>(matches: F) -> Self
+    where
+        F: FnMut(&str) -> bool + Send + 'static,
+    {
+        Self::new(Condition::Line(Pipe::Stdout, Box::new(matches)))
+    }
+
+    /// ready once a line of the standard error output matches
+    pub fn stderr_line<F>(matches: F) -> Self
+    where
+        F: FnMut(&str) -> bool + Send + 'static,
+    {
+        Self::new(Condition::Line(Pipe::Stderr, Box::new(matches)))
+    }
+
+    /// ready once a line of the standard output contains `pattern`
+    pub fn stdout_contains<S: Into<String>>(pattern: S) -> Self {
+        let pattern = pattern.into();
+        Self::stdout_line(move |line| line.contains(&pattern))
+    }
+
+    /// ready once a line of the standard error output contains `pattern`
+    pub fn stderr_contains<S: Into<String>>(pattern: S) -> Self {
+        let pattern = pattern.into();
+        Self::stderr_line(move |line| line.contains(&pattern))
+    }
+
+    /// ready once the given TCP port accepts connections on localhost
+    pub fn tcp_port(port: u16) -> Self {
+        Self::new(Condition::TcpPort(port))
+    }
+
+    /// ready once the given file exists
+    pub fn file<P: Into<PathBuf>>(path: P) -> Self {
+        Self::new(Condition::File(path.into()))
+    }
+
+    /// ready once the given probe returns `true`
+    pub fn probe<F>(probe: F) -> Self
+    where
+        F: FnMut() -> bool + Send + 'static,
+    {
+        Self::new(Condition::Probe(Box::new(probe)))
+    }
+
+    fn new(condition: Condition) -> Self {
+        Readiness { condition }
+    }
+}
+
+impl Condition {
+    /// check the conditions that are not based on the output nor on a
+    /// connection
+    fn check(&mut self) -> bool {
+        match self {
+            Condition::Line(..) | Condition::TcpPort(_) => false,
+            Condition::File(path) => path.exists(),
+            Condition::Probe(probe) => probe(),
+        }
+    }
+}
+
+impl TcpConnect {
+    pub(crate) fn new(address: &SocketAddr, timeout: Duration) -> Self {
+        TcpConnect {
+            connect: tokio_tcp::TcpStream::connect(address),
+            timeout: Delay::new(Instant::now() + timeout),
+        }
+    }
+}
+
+impl Future for TcpConnect {
+    type Item = ();
+    type Error = io::Error;
+
+    fn poll(&mut self) -> Poll<Self::Item, Self::Error> {
+        if self.connect.poll()?.is_ready() {
+            return Ok(Async::Ready(()));
+        }
+        match self.timeout.poll() {
+            Ok(Async::NotReady) => Ok(Async::NotReady),
+            Ok(Async::Ready(())) => Err(io::ErrorKind::TimedOut.into()),
+            Err(error) => Err(io::Error::other(error)),
+        }
+    }
+}
+
+impl fmt::Debug for Readiness {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match &self.condition {
+            Condition::Line(pipe, _) => write!(f, "Readiness::Line({:?})", pipe),
+            Condition::TcpPort(port) => write!(f, "Readiness::TcpPort({})", port),
+            Condition::File(path) => write!(f, "Readiness::File({})", path.display()),
+            Condition::Probe(_) => f.write_str("Readiness::Probe"),
+        }
+    }
+}
+
+impl WaitReady {
+    pub(crate) fn new(mut process: Process, readiness: Readiness, timeout: Duration) -> Self {
+        if let Condition::Line(pipe, _) = &readiness.condition {
+            process.watch(*pipe);
+        }
+        let now = Instant::now();
+        WaitReady {
+            process: Some(process),
+            condition: readiness.condition,
+            timeout,
+            deadline: Delay::new(now + timeout),
+            interval: Interval::new(now, Readiness::PROBE_INTERVAL),
+            connecting: None,
+            seen: OutputTail::new(TailOptions::new(Readiness::SEEN_LINES)),
+        }
+    }
+
+    /// the output seen so far, to report in the errors
+    fn seen(&self, process: &Process) -> OutputTail {
+        match self.condition {
+            Condition::Line(..) => self.seen.clone(),
+            _ => process.output_tail_snapshot(),
+        }
+    }
+
+    fn poll_ready(&mut self, process: &mut Process) -> Poll<(), Error> {
+        // the lines are drained by the process, with its tail and its
+        // forwarded output
+        let exited = process.poll()?;
+        if self.poll_condition(process)?.is_ready() {
+            return Ok(Async::Ready(()));
+        }
+
+        if let Async::Ready(status) = exited {
+            return Err(Error::ExitedBeforeReady {
+                command: Box::new(process.command().clone()),
+                id: process.id(),
+                status,
+                tail: self.seen(process),
+            });
+        }
+
+        if self.deadline.poll().map_err(Error::Timer)?.is_ready() {
+            return Err(Error::NotReady {
+                command: Box::new(process.command().clone()),
+                id: process.id(),
+                timeout: self.timeout,
+                tail: self.seen(process),
+            });
+        }
+
+        Ok(Async::NotReady)
+    }
+
+    fn poll_condition(&mut self, process: &mut Process) -> Poll<(), Error> {
+        if let Condition::Line(_, matches) = &mut self.condition {
+            let mut ready = false;
+            for line in process.watched_lines() {
+                ready = ready || matches(&line);
+                self.seen.push(line);
+            }
+            return Ok(if ready {
+                Async::Ready(())
+            } else {
+                Async::NotReady
+            });
+        }
+
+        loop {
+            if let Some(connecting) = &mut self.connecting {
+                match connecting.poll() {
+                    Ok(Async::Ready(())) => return Ok(Async::Ready(())),
+                    Ok(Async::NotReady) => return Ok(Async::NotReady),
+                    // not listening yet, try again on the next tick
+                    Err(_) => self.connecting = None,
+                }
+            }
+
+            if self.interval.poll().map_err(Error::Timer)?.is_not_ready() {
+                return Ok(Async::NotReady);
+            }
+            if let Condition::TcpPort(port) = self.condition {
+                let address = SocketAddr::from((Ipv4Addr::LOCALHOST, port));
+                self.connecting = Some(TcpConnect::new(&address, Readiness::PROBE_INTERVAL));
+            } else if self.condition.check() {
+                return Ok(Async::Ready(()));
+            }
+        }
+    }
+}
+
+impl Future for WaitReady {
+    type Item = Process;
+    type Error = Error;
+
+    fn poll(&mut self) -> Poll<Self::Item, Self::Error> {
+        let mut process = self
+            .process
+            .take()
+            .expect("cannot poll WaitReady twice after completion");
+
+        let result = self.poll_ready(&mut process);
+        match result {
+            Ok(Async::Ready(())) => {
+                process.unwatch();
+                Ok(Async::Ready(process))
+            }
+            Ok(Async::NotReady) => {
+                self.process = Some(process);
+                Ok(Async::NotReady)
+            }
+            Err(error) => Err(error),
+        }
+    }
+}
+
+#[cfg(all(test, unix))]
+mod test {
+    use super::*;
+    use crate::{temp::block_on, Command, Result};
+
+    fn wait(process: Process, readiness: Readiness, timeout: Duration) -> Result<Process> {
+        block_on(process.ready_when(readiness, timeout))
+    }
+
+    #[test]
+    fn ready_on_line() -> Result<()> {
+        let process = Process::spawn(Command::shell(
+            "echo starting; sleep 0.1; echo listening; sleep 5",
+        )?)?;
+
+        wait(
+            process,
+            Readiness::stdout_contains("listening"),
+            Duration::from_secs(5),
+        )?;
+
+        Ok(())
+    }
+
+    #[test]
+    fn ready_on_line_kept_in_tail() -> Result<()> {
+        let mut cmd = Command::shell("echo starting; echo listening; sleep 5")?;
+        cmd.output_tail(TailOptions::new(5).include_stdout(true));
+        let process = Process::spawn(cmd)?;
+
+        let process = wait(
+            process,
+            Readiness::stdout_contains("listening"),
+            Duration::from_secs(5),
+        )?;
+
+        let tail = process.output_tail().expect("the tail is kept");
+        assert_eq!(tail.to_string(), "starting\nlistening");
+        Ok(())
+    }
+
+    #[test]
+    fn ready_on_tcp_port() -> Result<()> {
+        let listener = std::net::TcpListener::bind((Ipv4Addr::LOCALHOST, 0)).unwrap();
+        let port = listener.local_addr().unwrap().port();
+        let process = Process::spawn(Command::shell("sleep 5")?)?;
+
+        wait(process, Readiness::tcp_port(port), Duration::from_secs(5))?;
+
+        Ok(())
+    }
+
+    #[test]
+    fn ready_once_tcp_port_listens() -> Result<()> {
+        let listener = std::net::TcpListener::bind((Ipv4Addr::LOCALHOST, 0)).unwrap();
+        let address = listener.local_addr().unwrap();
+        drop(listener);
+        let listening = std::thread::spawn(move || {
+            std::thread::sleep(Duration::from_millis(200));
+            std::net::TcpListener::bind(address).unwrap()
+        });
+        let process = Process::spawn(Command::shell("sleep 5")?)?;
+
+        let started = Instant::now();
+        wait(
+            process,
+            Readiness::tcp_port(address.port()),
+            Duration::from_secs(5),
+        )?;
+
+        assert!(started.elapsed() >= Duration::from_millis(200));
+        let _listener = listening.join().unwrap();
+        Ok(())
+    }
+
+    #[test]
+    fn timeout_reports_seen_lines() -> Result<()> {
+        let process = Process::spawn(Command::shell("echo starting; sleep 5")?)?;
+
+        let error = wait(
+            process,
+            Readiness::stdout_contains("listening"),
+            Duration::from_millis(200),
+        )
+        .err()
+        .expect("the process should not be ready");
+
+        match &error {
+            Error::NotReady { timeout, tail, .. } => {
+                assert_eq!(*timeout, Duration::from_millis(200));
+                assert_eq!(tail.to_string(), "starting");
+            }
+            _ => panic!("unexpected error: {}", error),
+        }
+
+        Ok(())
+    }
+
+    #[test]
+    fn exited_before_ready() -> Result<()> {
+        let mut cmd = Command::shell("echo 'address already in use' >&2; exit 3")?;
+        cmd.output_tail(TailOptions::new(5));
+        let process = Process::spawn(cmd)?;
+
+        let error = wait(
+            process,
+            Readiness::file("/file/that/does/not/exist"),
+            Duration::from_secs(5),
+        )
+        .err()
+        .expect("the process should not be ready");
+
+        match &error {
+            Error::ExitedBeforeReady { status, tail, .. } => {
+                assert_eq!(status.code(), Some(3));
+                assert_eq!(tail.to_string(), "address already in use");
+            }
+            _ => panic!("unexpected error: {}", error),
+        }
+
+        Ok(())
+    }
+}
@@ -0,0 +1,701 @@
+use crate::{
+    fake::{self, FakeStdin, FakeStdinState},
+    Child, ChildStderr, ChildStdin, ChildStdout, Command, OsSpawner, OutputPipe, Program,
+    ResourceUsage, Spawned, Spawner,
+};
+use futures::{task, Async, Future as _, Poll};
+use serde::{Deserialize, Serialize};
+use std::{
+    collections::{BTreeMap, VecDeque},
+    fs,
+    io::{self, Read, Write},
+    path::{Path, PathBuf},
+    process::ExitStatus,
+    sync::{Arc, Mutex},
+    time::{Duration, Instant},
+};
+use tokio_io::{AsyncRead, AsyncWrite};
+use tokio_timer::Delay;
+
+/// the processes run through a [`Recorder`], to be replayed with a
+/// [`Replayer`]
+///
+/// saved as JSON, the bytes sent and printed by the processes are kept
+/// as arrays of bytes. Like when the [`Command`] is displayed, the secret
+/// arguments and environment variables are recorded as `***`.
+///
+/// [`Recorder`]: ./struct.Recorder.html
+/// [`Replayer`]: ./struct.Replayer.html
+/// [`Command`]: ./struct.Command.html
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Recording {
+    pub processes: Vec<RecordedProcess>,
+}
+
+/// a process of a [`Recording`]
+///
+/// [`Recording`]: ./struct.Recording.html
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct RecordedProcess {
+    pub program: String,
+    pub arguments: Vec<String>,
+    pub working_directory: Option<PathBuf>,
+    pub environment: BTreeMap<String, String>,
+    /// everything sent to the standard input
+    pub stdin: Vec<u8>,
+    /// the chunks read from the outputs, in order
+    pub output: Vec<RecordedChunk>,
+    pub exit_code: Option<i32>,
+    /// the signal that terminated the process (unix only)
+    pub signal: Option<i32>,
+    /// time from the spawn to the exit of the process
+    pub duration: Duration,
+}
+
+/// a chunk read from one of the outputs of a [`RecordedProcess`]
+///
+/// [`RecordedProcess`]: ./struct.RecordedProcess.html
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct RecordedChunk {
+    pub pipe: RecordedPipe,
+    /// time from the spawn of the process to the read of the chunk
+    pub at: Duration,
+    pub data: Vec<u8>,
+}
+
+/// the output a [`RecordedChunk`] has been read from
+///
+/// [`RecordedChunk`]: ./struct.RecordedChunk.html
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum RecordedPipe {
+    Stdout,
+    Stderr,
+}
+
+/// a [`Spawner`] recording the processes it spawns
+///
+/// ```no_run
+/// # use bawawa::{Command, Process, Program, Recorder, Error};
+/// # use futures::Future as _;
+/// let recorder = Recorder::new();
+///
+/// let mut command = Command::new(Program::new("git")?);
+/// command.arguments(&["describe", "--tags"]).spawner(recorder.clone());
+/// Process::spawn(command)?.output().wait()?;
+///
+/// recorder.save("tests/recordings/git-describe.json")?;
+/// # Ok::<(), Error>(())
+/// ```
+///
+/// [`Spawner`]: ./trait.Spawner.html
+#[derive(Clone)]
+pub struct Recorder {
+    spawner: Arc<dyn Spawner>,
+    processes: Arc<Mutex<Vec<Record>>>,
+}
+
+type Record = Arc<Mutex<RecordedProcess>>;
+
+struct RecordingChild {
+    child: Box<dyn Child>,
+    record: Record,
+    started: Instant,
+}
+
+struct RecordingStdin {
+    stdin: ChildStdin,
+    record: Record,
+}
+
+struct RecordingOutput<R> {
+    pipe: R,
+    name: RecordedPipe,
+    record: Record,
+    started: Instant,
+}
+
+/// a [`Spawner`] replaying a [`Recording`] instead of spawning processes
+///
+/// every command spawned must be one of the recording, with the same
+/// program, arguments, working directory and environment. Its standard
+/// input must receive what was recorded, otherwise waiting for the process
+/// fails.
+///
+/// ```
+/// # use bawawa::{Command, Process, Program, RecordedProcess, Recording, Replayer, Error};
+/// # use futures::Future as _;
+/// # let recording = Recording {
+/// #     processes: vec![RecordedProcess {
+/// #         program: "git".to_owned(),
+/// #         arguments: vec!["describe".to_owned()],
+/// #         exit_code: Some(0),
+/// #         ..RecordedProcess::default()
+/// #     }],
+/// # };
+/// // let recording = Recording::load("tests/recordings/git-describe.json")?;
+/// let replayer = Replayer::new(recording);
+///
+/// let mut command = Command::new(Program::with_spawner("git", &replayer)?);
+/// command.argument("describe").spawner(replayer.clone());
+/// assert!(Process::spawn(command)?.wait()?.success());
+/// # Ok::<(), Error>(())
+/// ```
+///
+/// [`Spawner`]: ./trait.Spawner.html
+/// [`Recording`]: ./struct.Recording.html
+#[derive(Clone)]
+pub struct Replayer {
+    processes: Arc<Mutex<Vec<RecordedProcess>>>,
+    timing: bool,
+}
+
+struct ReplayChild {
+    id: u32,
+    status: ExitStatus,
+    exit: Option<(Instant, Option<Delay>)>,
+    expected_stdin: Vec<u8>,
+    stdin: Arc<Mutex<FakeStdinState>>,
+    killed: bool,
+}
+
+struct ReplayOutput {
+    chunks: VecDeque<(Option<Instant>, Vec<u8>)>,
+    delay: Option<Delay>,
+}
+
+impl Recording {
+    /// load a recording saved with [`Recording::save`]
+    ///
+    /// [`Recording::save`]: ./struct.Recording.html#method.save
+    pub fn load<P: AsRef<Path>>(path: P) -> io::Result<Self> {
+        let file = fs::File::open(path)?;
+        serde_json::from_reader(io::BufReader::new(file)).map_err(io::Error::from)
+    }
+
+    /// save the recording as JSON
+    pub fn save<P: AsRef<Path>>(&self, path: P) -> io::Result<()> {
+        let file = fs::File::create(path)?;
+        serde_json::to_writer_pretty(io::BufWriter::new(file), self).map_err(io::Error::from)
+    }
+}
+
+impl RecordedProcess {
+    fn new(command: &Command) -> Self {
+        RecordedProcess {
+            program: command.program().to_string(),
+            arguments: command.displayed_arguments().map(str::to_owned).collect(),
+            working_directory: command.working_directory().cloned(),
+            environment: command
+                .displayed_environment()
+                .map(|(key, value)| (key.to_owned(), value.to_owned()))
+                .collect(),
+            ..RecordedProcess::default()
+        }
+    }
+
+    /// tell if the command is the one recorded, the secrets are not
+    /// compared as they are not recorded
+    fn is(&self, command: &Command) -> bool {
+        self.program == command.program().as_ref() as &str
+            && self
+                .arguments
+                .iter()
+                .map(String::as_str)
+                .eq(command.displayed_arguments())
+            && self.working_directory.as_ref() == command.working_directory()
+            && self
+                .environment
+                .iter()
+                .map(|(key, value)| (key.as_str(), value.as_str()))
+                .eq(command.displayed_environment())
+    }
+
+    fn status(&self) -> ExitStatus {
+        #[cfg(unix)]
+        {
+            if let Some(signal) = self.signal {
+                return fake::signal_status(signal);
+            }
+        }
+        fake::exit_status(self.exit_code.unwrap_or(1))
+    }
+}
+
+impl Recorder {
+    /// record the processes spawned by the [`OsSpawner`]
+    ///
+    /// [`OsSpawner`]: ./struct.OsSpawner.html
+    pub fn new() -> Self {
+        Self::with_spawner(OsSpawner)
+    }
+
+    /// record the processes spawned by the given spawner
+    pub fn with_spawner<S: Spawner + 'static>(spawner: S) -> Self {
+        Recorder {
+            spawner: Arc::new(spawner),
+            processes: Arc::new(Mutex::new(Vec::new())),
+        }
+    }
+
+    /// the processes recorded so far, in the order they were spawned
+    pub fn recording(&self) -> Recording {
+        let processes = self
+            .processes
+            .lock()
+            .unwrap()
+            .iter()
+            .map(|record| record.lock().unwrap().clone())
+            .collect();
+        Recording { processes }
+    }
+
+    /// save the processes recorded so far, see [`Recording::save`]
+    ///
+    /// [`Recording::save`]: ./struct.Recording.html#method.save
+    pub fn save<P: AsRef<Path>>(&self, path: P) -> io::Result<()> {
+        self.recording().save(path)
+    }
+}
+
+impl Default for Recorder {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Spawner for Recorder {
+    fn spawn(&self, command: &Command) -> io::Result<Spawned> {
+        let spawned = self.spawner.spawn(command)?;
+        let record = Arc::new(Mutex::new(RecordedProcess::new(command)));
+        self.processes.lock().unwrap().push(record.clone());
+        let started = Instant::now();
+
+        Ok(Spawned {
+            child: Box::new(RecordingChild {
+                child: spawned.child,
+                record: record.clone(),
+                started,
+            }),
+            stdin: spawned.stdin.map(|stdin| {
+                ChildStdin::new(RecordingStdin {
+                    stdin,
+                    record: record.clone(),
+                })
+            }),
+            stdout: spawned.stdout.map(|pipe| {
+                ChildStdout::new(RecordingOutput {
+                    pipe,
+                    name: RecordedPipe::Stdout,
+                    record: record.clone(),
+                    started,
+                })
+            }),
+            stderr: spawned.stderr.map(|pipe| {
+                ChildStderr::new(RecordingOutput {
+                    pipe,
+                    name: RecordedPipe::Stderr,
+                    record,
+                    started,
+                })
+            }),
+        })
+    }
+
+    #[inline]
+    fn check_program(&self, program: &Program) -> io::Result<()> {
+        self.spawner.check_program(program)
+    }
+}
+
+impl Child for RecordingChild {
+    #[inline]
+    fn id(&self) -> u32 {
+        self.child.id()
+    }
+
+    fn poll_exit(&mut self) -> Poll<ExitStatus, io::Error> {
+        let status = futures::try_ready!(self.child.poll_exit());
+
+        let mut record = self.record.lock().unwrap();
+        record.exit_code = status.code();
+        #[cfg(unix)]
+        {
+            use std::os::unix::process::ExitStatusExt as _;
+            record.signal = status.signal();
+        }
+        record.duration = self.started.elapsed();
+
+        Ok(Async::Ready(status))
+    }
+
+    #[inline]
+    fn kill(&mut self) -> io::Result<()> {
+        self.child.kill()
+    }
+
+    #[inline]
+    fn terminate(&mut self) -> io::Result<()> {
+        self.child.terminate()
+    }
+
+    #[inline]
+    fn resource_usage(&mut self) -> Option<ResourceUsage> {
+        self.child.resource_usage()
+    }
+}
+
+impl Write for RecordingStdin {
+    fn write(&mut self, buffer: &[u8]) -> io::Result<usize> {
+        let written = self.stdin.write(buffer)?;
+        self.record
+            .lock()
+            .unwrap()
+            .stdin
+            .extend_from_slice(&buffer[..written]);
+        Ok(written)
+    }
+
+    #[inline]
+    fn flush(&mut self) -> io::Result<()> {
+        self.stdin.flush()
+    }
+}
+
+impl AsyncWrite for RecordingStdin {
+    #[inline]
+    fn shutdown(&mut self) -> Poll<(), io::Error> {
+        self.stdin.shutdown()
+    }
+}
+
+impl<R: OutputPipe> RecordingOutput<R> {
+    fn recorded(&self, read: io::Result<usize>, buffer: &[u8]) -> io::Result<usize> {
+        let read = read?;
+        if read > 0 {
+            self.record.lock().unwrap().output.push(RecordedChunk {
+                pipe: self.name,
+                at: self.started.elapsed(),
+                data: buffer[..read].to_vec(),
+            });
+        }
+        Ok(read)
+    }
+}
+
+impl<R: OutputPipe> Read for RecordingOutput<R> {
+    fn read(&mut self, buffer: &mut [u8]) -> io::Result<usize> {
+        let read = self.pipe.read(buffer);
+        self.recorded(read, buffer)
+    }
+}
+
+impl<R: OutputPipe> AsyncRead for RecordingOutput<R> {}
+
+impl<R: OutputPipe> OutputPipe for RecordingOutput<R> {
+    fn read_remaining(&mut self, buffer: &mut [u8]) -> io::Result<usize> {
+        let read = self.pipe.read_remaining(buffer);
+        self.recorded(read, buffer)
+    }
+}
+
+impl Replayer {
+    /// replay the given recording, without waiting between the outputs
+    pub fn new(recording: Recording) -> Self {
+        Replayer {
+            processes: Arc::new(Mutex::new(recording.processes)),
+            timing: false,
+        }
+    }
+
+    /// replay the recording saved in the given file
+    pub fn load<P: AsRef<Path>>(path: P) -> io::Result<Self> {
+        Recording::load(path).map(Self::new)
+    }
+
+    /// replay the outputs and the exits at the time they were recorded
+    ///
+    /// this requires a `tokio` runtime with a timer.
+    pub fn timing(mut self, timing: bool) -> Self {
+        self.timing = timing;
+        self
+    }
+
+    /// the number of recorded processes not replayed yet
+    pub fn remaining(&self) -> usize {
+        self.processes.lock().unwrap().len()
+    }
+}
+
+impl Spawner for Replayer {
+    fn spawn(&self, command: &Command) -> io::Result<Spawned> {
+        let process = {
+            let mut processes = self.processes.lock().unwrap();
+            let index = processes
+                .iter()
+                .position(|process| process.is(command))
+                .ok_or_else(|| {
+                    io::Error::new(
+                        io::ErrorKind::InvalidInput,
+                        format!("command '{}' is not in the recording", command),
+                    )
+                })?;
+            processes.remove(index)
+        };
+
+        let started = Instant::now();
+        let at = |at: Duration| {
+            if self.timing {
+                Some(started + at)
+            } else {
+                None
+            }
+        };
+        let mut stdout = VecDeque::new();
+        let mut stderr = VecDeque::new();
+        for chunk in &process.output {
+            let chunk_at = at(chunk.at);
+            let data = chunk.data.clone();
+            match chunk.pipe {
+                RecordedPipe::Stdout => stdout.push_back((chunk_at, data)),
+                RecordedPipe::Stderr => stderr.push_back((chunk_at, data)),
+            }
+        }
+
+        let stdin = Arc::new(Mutex::new(FakeStdinState::default()));
+        Ok(Spawned {
+            child: Box::new(ReplayChild {
+                id: fake::fake_id(),
+                status: process.status(),
+                exit: at(process.duration).map(|exit| (exit, None)),
+                expected_stdin: process.stdin,
+                stdin: stdin.clone(),
+                killed: false,
+            }),
+            stdin: Some(ChildStdin::new(FakeStdin(stdin))),
+            stdout: Some(ChildStdout::new(ReplayOutput::new(stdout))),
+            stderr: Some(ChildStderr::new(ReplayOutput::new(stderr))),
+        })
+    }
+
+    /// the program exists if it has been recorded
+    fn check_program(&self, program: &Program) -> io::Result<()> {
+        let program = program.to_string();
+        if self
+            .processes
+            .lock()
+            .unwrap()
+            .iter()
+            .any(|process| process.program == program)
+        {
+            Ok(())
+        } else {
+            Err(io::Error::new(
+                io::ErrorKind::NotFound,
+                format!("program '{}' is not in the recording", program),
+            ))
+        }
+    }
+}
+
+impl Child for ReplayChild {
+    #[inline]
+    fn id(&self) -> u32 {
+        self.id
+    }
+
+    fn poll_exit(&mut self) -> Poll<ExitStatus, io::Error> {
+        if self.killed {
+            return Ok(Async::Ready(self.status));
+        }
+
+        if let Some((exit, delay)) = &mut self.exit {
+            let exit = *exit;
+            let delay = delay.get_or_insert_with(|| Delay::new(exit));
+            futures::try_ready!(delay.poll().map_err(io::Error::other));
+        }
+
+        let mut stdin = self.stdin.lock().unwrap();
+        let diverged = !self.expected_stdin.starts_with(&stdin.written)
+            || (stdin.closed && stdin.written.len() < self.expected_stdin.len());
+        if diverged {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidData,
+                format!(
+                    "standard input diverges from the recording: expected \"{}\", received \"{}\"",
+                    self.expected_stdin.escape_ascii(),
+                    stdin.written.escape_ascii(),
+                ),
+            ));
+        }
+        if stdin.written.len() < self.expected_stdin.len() {
+            stdin.task = Some(task::current());
+            return Ok(Async::NotReady);
+        }
+
+        Ok(Async::Ready(self.status))
+    }
+
+    fn kill(&mut self) -> io::Result<()> {
+        self.killed = true;
+        Ok(())
+    }
+}
+
+impl ReplayOutput {
+    fn new(chunks: VecDeque<(Option<Instant>, Vec<u8>)>) -> Self {
+        ReplayOutput {
+            chunks,
+            delay: None,
+        }
+    }
+}
+
+impl Read for ReplayOutput {
+    fn read(&mut self, buffer: &mut [u8]) -> io::Result<usize> {
+        let (at, chunk) = match self.chunks.front_mut() {
+            None => return Ok(0),
+            Some(front) => front,
+        };
+
+        if let Some(at) = *at {
+            if Instant::now() < at {
+                let delay = self.delay.get_or_insert_with(|| Delay::new(at));
+                delay.reset(at);
+                if delay.poll().map_err(io::Error::other)?.is_not_ready() {
+                    return Err(io::ErrorKind::WouldBlock.into());
+                }
+            }
+        }
+
+        let read = buffer.len().min(chunk.len());
+        buffer[..read].copy_from_slice(&chunk[..read]);
+        chunk.drain(..read);
+        if chunk.is_empty() {
+            self.chunks.pop_front();
+        }
+        Ok(read)
+    }
+}
+
+impl AsyncRead for ReplayOutput {}
+
+impl OutputPipe for ReplayOutput {}
+
+#[cfg(all(test, unix))]
+mod test {
+    use super::*;
+    use crate::{temp::TempPath, Error, Output, Process, Result, StandardInput as _};
+
+    fn command(spawner: impl Spawner + 'static, program: Program) -> Command {
+        let mut command = Command::new(program);
+        command
+            .arguments(["-c", "read line; echo \"got $line\"; echo oops >&2; exit 3"])
+            .environment_variable("LANG", "C")
+            .spawner(spawner);
+        command
+    }
+
+    fn run(command: Command) -> Result<Output> {
+        let mut runtime = tokio::runtime::Runtime::new().unwrap();
+        let mut process = Process::spawn(command)?;
+        let stdin = process.stdin().take().expect("the standard input");
+        let output = tokio_io::io::write_all(stdin, b"hello\n")
+            .map_err(Error::from)
+            // the standard input is closed once written
+            .and_then(move |_stdin| process.output());
+        runtime.block_on(output)
+    }
+
+    #[test]
+    fn record_and_replay() -> Result<()> {
+        let recorder = Recorder::new();
+        let program = Program::new("sh")?;
+
+        let recorded = run(command(recorder.clone(), program.clone()))?;
+        assert_eq!(recorded.status.code(), Some(3));
+
+        let recording = recorder.recording();
+        let process = &recording.processes[0];
+        assert_eq!(process.program, "sh");
+        assert_eq!(process.environment["LANG"], "C");
+        assert_eq!(process.stdin, b"hello\n");
+        assert_eq!(process.exit_code, Some(3));
+        let stdout: Vec<u8> = process
+            .output
+            .iter()
+            .filter(|chunk| chunk.pipe == RecordedPipe::Stdout)
+            .flat_map(|chunk| chunk.data.iter().copied())
+            .collect();
+        assert_eq!(stdout, b"got hello\n");
+
+        let path = TempPath::new("record.json");
+        recording.save(&path)?;
+        let replayer = Replayer::load(&path)?;
+
+        let replayed = run(command(replayer.clone(), program))?;
+        assert_eq!(replayed, recorded);
+        assert_eq!(replayer.remaining(), 0);
+
+        Ok(())
+    }
+
+    #[test]
+    fn record_bytes_and_redact_secrets() -> Result<()> {
+        fn command(spawner: impl Spawner + 'static) -> Result<Command> {
+            let mut command = Command::new(Program::new("sh")?);
+            command
+                .arguments(["-c", "printf '\\377\\n'", "sh"])
+                .secret_argument("hunter2")
+                .secret_environment_variable("TOKEN", "t0k3n")
+                .spawner(spawner);
+            Ok(command)
+        }
+        let recorder = Recorder::new();
+        let mut runtime = tokio::runtime::Runtime::new().unwrap();
+        let recorded = runtime.block_on(command(recorder.clone())?.spawn()?.output())?;
+        assert_eq!(recorded.stdout, b"\xff\n");
+
+        let recording = recorder.recording();
+        let process = &recording.processes[0];
+        assert_eq!(process.arguments[3], "***");
+        assert_eq!(process.environment["TOKEN"], "***");
+        assert_eq!(process.output[0].data, b"\xff\n");
+        let json = serde_json::to_string(&recording).unwrap();
+        assert!(!json.contains("hunter2") && !json.contains("t0k3n"));
+
+        let replayer = Replayer::new(serde_json::from_str(&json).unwrap());
+        let replayed = runtime.block_on(command(replayer)?.spawn()?.output())?;
+        assert_eq!(replayed, recorded);
+
+        Ok(())
+    }
+
+    #[test]
+    fn diverging_replay() -> Result<()> {
+        let replayer = Replayer::new(Recording {
+            processes: vec![RecordedProcess {
+                program: "cat".to_owned(),
+                stdin: b"yes\n".to_vec(),
+                exit_code: Some(0),
+                ..RecordedProcess::default()
+            }],
+        });
+        let program = Program::with_spawner("cat", &replayer)?;
+
+        let mut command = Command::new(program.clone());
+        command.argument("--unexpected").spawner(replayer.clone());
+        match Process::spawn(command).err().expect("not recorded") {
+            Error::CannotSpawnCommand { .. } => {}
+            error => panic!("unexpected error: {}", error),
+        }
+
+        let mut command = Command::new(program);
+        command.spawner(replayer);
+        let mut process = Process::spawn(command)?;
+        process.standard_input().write_all(b"no\n")?;
+        assert!(process.wait().is_err());
+
+        Ok(())
+    }
+}
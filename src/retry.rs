@@ -0,0 +1,403 @@
+use crate::{
+    output::TailOutput, supervisor::backoff, Command, Error, OutputTail, Process, Result,
+    TailOptions,
+};
+use futures::{try_ready, Async, Future, Poll};
+use std::{
+    collections::hash_map::RandomState,
+    fmt,
+    hash::{BuildHasher, Hasher},
+    process::ExitStatus,
+    sync::Arc,
+    time::{Duration, Instant},
+};
+use tokio_timer::Delay;
+
+/// how many times, and when, to run again a failing [`Command`]
+///
+/// the delay between two attempts grows exponentially from the initial
+/// delay up to the maximum delay of the backoff, it is then shortened by
+/// a random fraction of at most `jitter` so concurrent retries spread out.
+///
+/// ```
+/// # use bawawa::RetryPolicy;
+/// # use std::time::Duration;
+/// let policy = RetryPolicy::new(5)
+///     .backoff(Duration::from_millis(100), Duration::from_secs(5))
+///     .jitter(0.5)
+///     .retry_on_output("connection reset");
+/// ```
+///
+/// [`Command`]: ./struct.Command.html
+#[derive(Clone)]
+pub struct RetryPolicy {
+    attempts: usize,
+    initial_delay: Duration,
+    max_delay: Duration,
+    jitter: f64,
+    retry_if: Option<Arc<RetryIf>>,
+}
+
+/// future returned by [`Retry::new`], yields the exit status of the first
+/// successful attempt
+///
+/// only the [`OutputTail`] of the attempts is kept (the last 10 lines of
+/// the standard error output unless [`Command::output_tail`] is set), the
+/// rest of their output is discarded.
+///
+/// if all the attempts failed, or if an attempt cannot be spawned, the
+/// error is `Error::Retry` caused by the failure of the last attempt,
+/// itself caused by the failure of the previous one and so on.
+///
+/// [`Retry::new`]: ./struct.Retry.html#method.new
+/// [`OutputTail`]: ./struct.OutputTail.html
+/// [`Command::output_tail`]: ./struct.Command.html#method.output_tail
+pub struct Retry {
+    command: Command,
+    policy: RetryPolicy,
+    attempt: usize,
+    state: State,
+    failures: Option<Error>,
+}
+
+type RetryIf = dyn Fn(ExitStatus, &OutputTail) -> bool + Send + Sync;
+
+enum State {
+    Running(Box<TailOutput>),
+    Waiting(Delay),
+}
+
+impl RetryPolicy {
+    const DEFAULT_INITIAL_DELAY: Duration = Duration::from_millis(100);
+    const DEFAULT_MAX_DELAY: Duration = Duration::from_secs(10);
+
+    /// run the command at most `attempts` times (at least once), as long
+    /// as it fails
+    pub fn new(attempts: usize) -> Self {
+        RetryPolicy {
+            attempts: attempts.max(1),
+            initial_delay: Self::DEFAULT_INITIAL_DELAY,
+            max_delay: Self::DEFAULT_MAX_DELAY,
+            jitter: 0.0,
+            retry_if: None,
+        }
+    }
+
+    /// set the exponential backoff between attempts
+    pub fn backoff(mut self, initial_delay: Duration, max_delay: Duration) -> Self {
+        self.initial_delay = initial_delay;
+        self.max_delay = max_delay;
+        self
+    }
+
+    /// shorten the delays by a random fraction of at most `jitter`
+    /// (between `0.0`, the default, and `1.0`)
+    pub fn jitter(mut self, jitter: f64) -> Self {
+        self.jitter = jitter.clamp(0.0, 1.0);
+        self
+    }
+
+    /// only retry the failed attempts for which `retry_if` returns `true`,
+    /// given their exit status and their [`OutputTail`]
+    ///
+    /// by default every failed attempt is retried.
+    ///
+    /// [`OutputTail`]: ./struct.OutputTail.html
+    pub fn retry_if<F>(mut self, retry_if: F) -> Self
+    where
+        F: Fn(ExitStatus, &OutputTail) -> bool + Send + Sync + 'static,
+    {
+        self.retry_if = Some(Arc::new(retry_if));
+        self
+    }
+
+    /// only retry the attempts exiting with one of the given codes
+    pub fn retry_on_exit_codes(self, codes: &[i32]) -> Self {
+        let codes = codes.to_vec();
+        self.retry_if(move |status, _| status.code().is_some_and(|code| codes.contains(&code)))
+    }
+
+    /// only retry the attempts with a line containing `pattern` in their
+    /// [`OutputTail`]
+    ///
+    /// the tail holds the standard error output, and the standard output
+    /// too if the [`TailOptions`] of the command include it.
+    ///
+    /// [`OutputTail`]: ./struct.OutputTail.html
+    /// [`TailOptions`]: ./struct.TailOptions.html
+    pub fn retry_on_output<S: Into<String>>(self, pattern: S) -> Self {
+        let pattern = pattern.into();
+        self.retry_if(move |_, tail| tail.lines().any(|line| line.contains(&pattern)))
+    }
+
+    /// tell if the failed attempt should be retried
+    pub fn should_retry(&self, status: ExitStatus, tail: &OutputTail) -> bool {
+        self.retry_if
+            .as_ref()
+            .is_none_or(|retry_if| retry_if(status, tail))
+    }
+
+    /// the delay before the next attempt, given the number of retries so
+    /// far, without the jitter
+    pub fn delay(&self, retries: usize) -> Duration {
+        backoff(self.initial_delay, self.max_delay, retries)
+    }
+
+    fn jittered_delay(&self, retries: usize) -> Duration {
+        let delay = self.delay(retries);
+        if self.jitter == 0.0 {
+            return delay;
+        }
+        delay.mul_f64(1.0 - self.jitter * random())
+    }
+}
+
+impl fmt::Debug for RetryPolicy {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("RetryPolicy")
+            .field("attempts", &self.attempts)
+            .field("initial_delay", &self.initial_delay)
+            .field("max_delay", &self.max_delay)
+            .field("jitter", &self.jitter)
+            .field("retry_if", &self.retry_if.as_ref().map(|_| "Fn"))
+            .finish()
+    }
+}
+
+impl Retry {
+    /// number of lines of the standard error output kept by default
+    const DEFAULT_TAIL_LINES: usize = 10;
+
+    /// spawn the first attempt of the given [`Command`]
+    ///
+    /// the delays between attempts require a `tokio` runtime with a timer.
+    ///
+    /// # Error
+    ///
+    /// fails if the command cannot be spawned, the spawn errors are not
+    /// retried.
+    ///
+    /// [`Command`]: ./struct.Command.html
+    pub fn new(mut command: Command, policy: RetryPolicy) -> Result<Self> {
+        if command.output_tail_options().is_none() {
+            command.output_tail(TailOptions::new(Self::DEFAULT_TAIL_LINES));
+        }
+        let output = TailOutput::new(Process::spawn(command.clone())?);
+        Ok(Retry {
+            command,
+            policy,
+            attempt: 1,
+            state: State::Running(Box::new(output)),
+            failures: None,
+        })
+    }
+
+    /// the current attempt, starting from 1
+    #[inline]
+    pub fn attempt(&self) -> usize {
+        self.attempt
+    }
+
+    /// add the failure of the current attempt to the chain of failures
+    fn failed(&mut self, status: ExitStatus, tail: OutputTail) {
+        self.failures = Some(Error::Attempt {
+            command: Box::new(self.command.clone()),
+            attempt: self.attempt,
+            status,
+            tail,
+            previous: self.failures.take().map(Box::new),
+        });
+    }
+}
+
+impl Future for Retry {
+    type Item = ExitStatus;
+    type Error = Error;
+
+    fn poll(&mut self) -> Poll<Self::Item, Self::Error> {
+        loop {
+            self.state = match &mut self.state {
+                State::Running(output) => {
+                    let (status, tail) = try_ready!(output.poll());
+                    if status.success() {
+                        return Ok(Async::Ready(status));
+                    }
+
+                    let retry = self.policy.should_retry(status, &tail);
+                    self.failed(status, tail);
+                    if self.attempt >= self.policy.attempts || !retry {
+                        let failures = self.failures.take().expect("at least one failure");
+                        return Err(Error::Retry {
+                            command: Box::new(self.command.clone()),
+                            attempts: self.attempt,
+                            last: Box::new(failures),
+                        });
+                    }
+
+                    let delay = self.policy.jittered_delay(self.attempt - 1);
+                    State::Waiting(Delay::new(Instant::now() + delay))
+                }
+                State::Waiting(delay) => {
+                    try_ready!(delay.poll().map_err(Error::Timer));
+                    self.attempt += 1;
+                    match Process::spawn(self.command.clone()) {
+                        Ok(process) => State::Running(Box::new(TailOutput::new(process))),
+                        Err(error) => {
+                            return Err(Error::Retry {
+                                command: Box::new(self.command.clone()),
+                                attempts: self.attempt,
+                                last: Box::new(Error::CannotSpawnAttempt {
+                                    attempt: self.attempt,
+                                    error: Box::new(error),
+                                    previous: self.failures.take().map(Box::new),
+                                }),
+                            })
+                        }
+                    }
+                }
+            };
+        }
+    }
+}
+
+/// a random number in `[0, 1)`, good enough for the jitter
+fn random() -> f64 {
+    let mut hasher = RandomState::new().build_hasher();
+    hasher.write_u128(
+        std::time::SystemTime::now()
+            .duration_since(std::time::UNIX_EPOCH)
+            .unwrap_or_default()
+            .as_nanos(),
+    );
+    (hasher.finish() >> 11) as f64 / (1u64 << 53) as f64
+}
+
+#[cfg(all(test, unix))]
+mod test {
+    use super::*;
+    use crate::{
+        temp::{block_on, TempPath},
+        FakeProcess, FakeSpawner, Program,
+    };
+
+    fn run(command: Command, policy: RetryPolicy) -> Result<ExitStatus> {
+        block_on(Retry::new(command, policy)?)
+    }
+
+    fn fast(attempts: usize) -> RetryPolicy {
+        RetryPolicy::new(attempts).backoff(Duration::from_millis(1), Duration::from_millis(5))
+    }
+
+    #[test]
+    fn succeed_after_retrying() -> Result<()> {
+        let file = TempPath::new("retry");
+        // fails the first time only
+        let script = format!("test -f {0} || {{ touch {0}; exit 1; }}", file.display());
+
+        assert!(run(Command::shell(script)?, fast(3))?.success());
+        Ok(())
+    }
+
+    #[test]
+    fn chain_every_attempt() -> Result<()> {
+        let error = run(
+            Command::shell("echo 'try again' >&2; exit 3")?,
+            fast(3).jitter(1.0),
+        )
+        .expect_err("all the attempts should fail");
+
+        match &error {
+            Error::Retry { attempts: 3, .. } => {}
+            error => panic!("unexpected error: {}", error),
+        }
+        let causes: Vec<_> =
+            std::iter::successors(std::error::Error::source(&error), |cause| cause.source())
+                .map(|cause| cause.to_string())
+                .collect();
+        assert_eq!(causes.len(), 3);
+        for (cause, attempt) in causes.iter().zip([3, 2, 1]) {
+            assert!(
+                cause.starts_with(&format!("attempt {} of ", attempt)),
+                "{}",
+                cause
+            );
+            assert!(
+                cause.ends_with("exit status: 3\n  | try again"),
+                "{}",
+                cause
+            );
+        }
+
+        Ok(())
+    }
+
+    #[test]
+    fn do_not_retry_unexpected_failures() -> Result<()> {
+        let error = run(
+            Command::shell("exit 2")?,
+            fast(3).retry_on_exit_codes(&[75]),
+        )
+        .expect_err("the attempt should fail");
+
+        match error {
+            Error::Retry { attempts: 1, .. } => Ok(()),
+            error => panic!("unexpected error: {}", error),
+        }
+    }
+
+    #[test]
+    fn retry_on_output() -> Result<()> {
+        let command = Command::shell("echo 'connection reset' >&2; exit 1")?;
+
+        match run(command.clone(), fast(2).retry_on_output("reset")) {
+            Err(Error::Retry { attempts: 2, .. }) => {}
+            result => panic!("unexpected result: {:?}", result),
+        }
+        match run(command, fast(2).retry_on_output("timed out")) {
+            Err(Error::Retry { attempts: 1, .. }) => Ok(()),
+            result => panic!("unexpected result: {:?}", result),
+        }
+    }
+
+    #[test]
+    fn later_attempt_cannot_spawn() -> Result<()> {
+        let spawner = FakeSpawner::new();
+        // only the first attempt is expected, the second cannot be spawned
+        spawner.expect(FakeProcess::new().exit_code(1));
+        let mut command = Command::new(Program::with_spawner("flaky", &spawner)?);
+        command.spawner(spawner.clone());
+
+        let error = run(command, fast(3)).expect_err("the second attempt should fail");
+        match &error {
+            Error::Retry {
+                attempts: 2, last, ..
+            } => match last.as_ref() {
+                Error::CannotSpawnAttempt {
+                    attempt: 2,
+                    previous: Some(previous),
+                    ..
+                } => {
+                    assert_eq!(
+                        last.to_string(),
+                        "attempt 2: cannot spawn command: 'flaky': unexpected command 'flaky'"
+                    );
+                    match previous.as_ref() {
+                        Error::Attempt { attempt: 1, .. } => Ok(()),
+                        previous => panic!("unexpected previous failure: {}", previous),
+                    }
+                }
+                last => panic!("unexpected last failure: {}", last),
+            },
+            error => panic!("unexpected error: {}", error),
+        }
+    }
+
+    #[test]
+    fn backoff_is_exponential() {
+        let policy = RetryPolicy::new(10).backoff(Duration::from_secs(1), Duration::from_secs(5));
+        assert_eq!(policy.delay(0), Duration::from_secs(1));
+        assert_eq!(policy.delay(1), Duration::from_secs(2));
+        assert_eq!(policy.delay(2), Duration::from_secs(4));
+        assert_eq!(policy.delay(3), Duration::from_secs(5));
+    }
+}
@@ -0,0 +1,88 @@
+use std::{fmt, time::Duration};
+
+/// resources used by a finished [`Process`]
+///
+/// this includes the resources of the children of the process that
+/// it has waited for.
+///
+/// [`Process`]: ./struct.Process.html
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Default)]
+#[non_exhaustive]
+pub struct ResourceUsage {
+    /// time spent executing in user mode
+    pub user_time: Duration,
+    /// time spent executing in kernel mode
+    pub system_time: Duration,
+    /// maximum resident set size, in bytes
+    pub max_resident_set_size: u64,
+    /// page faults serviced without any I/O activity
+    pub minor_page_faults: u64,
+    /// page faults serviced that required I/O activity
+    pub major_page_faults: u64,
+}
+
+impl ResourceUsage {
+    /// collect the resource usage of the given child process, if it has
+    /// exited, without reaping it.
+    ///
+    /// The child is left as a zombie so its exit status can still be
+    /// collected by `tokio_process`.
+    #[cfg(target_os = "linux")]
+    pub(crate) fn of_exited_child(pid: u32) -> std::io::Result<Option<Self>> {
+        let mut info: libc::siginfo_t = unsafe { std::mem::zeroed() };
+        let mut usage: libc::rusage = unsafe { std::mem::zeroed() };
+
+        // the libc's `waitid` does not expose the `rusage` argument of
+        // the system call
+        let result = unsafe {
+            libc::syscall(
+                libc::SYS_waitid,
+                libc::P_PID,
+                pid as libc::id_t,
+                &mut info as *mut libc::siginfo_t,
+                libc::WEXITED | libc::WNOHANG | libc::WNOWAIT,
+                &mut usage as *mut libc::rusage,
+            )
+        };
+        if result == -1 {
+            Err(std::io::Error::last_os_error())
+        } else if unsafe { info.si_pid() } == 0 {
+            // still running
+            Ok(None)
+        } else {
+            Ok(Some(ResourceUsage::from(usage)))
+        }
+    }
+}
+
+#[cfg(target_os = "linux")]
+impl From<libc::rusage> for ResourceUsage {
+    fn from(usage: libc::rusage) -> Self {
+        fn duration(time: libc::timeval) -> Duration {
+            Duration::from_secs(time.tv_sec as u64) + Duration::from_micros(time.tv_usec as u64)
+        }
+
+        ResourceUsage {
+            user_time: duration(usage.ru_utime),
+            system_time: duration(usage.ru_stime),
+            // in kilobytes on linux
+            max_resident_set_size: usage.ru_maxrss as u64 * 1024,
+            minor_page_faults: usage.ru_minflt as u64,
+            major_page_faults: usage.ru_majflt as u64,
+        }
+    }
+}
+
+impl fmt::Display for ResourceUsage {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(
+            f,
+            "user: {:?}, system: {:?}, max RSS: {} KiB, page faults: {} minor / {} major",
+            self.user_time,
+            self.system_time,
+            self.max_resident_set_size / 1024,
+            self.minor_page_faults,
+            self.major_page_faults
+        )
+    }
+}
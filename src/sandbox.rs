@@ -0,0 +1,553 @@
+use crate::{
+    pre_exec::{Reporter, Step},
+    Command,
+};
+use std::{
+    ffi::CString,
+    io,
+    os::unix::{ffi::OsStrExt as _, process::CommandExt as _},
+    path::{Path, PathBuf},
+    ptr,
+    sync::atomic::{AtomicI32, Ordering},
+};
+
+/// the signals forwarded to the sandboxed command
+const FORWARDED_SIGNALS: [libc::c_int; 6] = [
+    libc::SIGTERM,
+    libc::SIGINT,
+    libc::SIGHUP,
+    libc::SIGQUIT,
+    libc::SIGUSR1,
+    libc::SIGUSR2,
+];
+
+/// the process the signals are forwarded to, in the supervising process
+static FORWARD_TO: AtomicI32 = AtomicI32::new(0);
+
+/// `MOUNT_ATTR_RDONLY` of `mount_setattr`
+const MOUNT_ATTR_RDONLY: u64 = 0x1;
+
+/// the `struct mount_attr` of `mount_setattr`
+#[repr(C)]
+struct MountAttr {
+    attr_set: u64,
+    attr_clr: u64,
+    propagation: u64,
+    userns_fd: u64,
+}
+
+/// isolation of a [`Command`] in new Linux namespaces, without any
+/// container runtime
+///
+/// the command runs in its own user (as `root`, mapped to the current
+/// user), mount, PID, network (with the loopback interface only) and IPC
+/// namespaces. All the processes it starts are killed when it exits or
+/// when it is killed. The usual signals are forwarded to the command,
+/// which exits with the code `128 + signal` when terminated by one.
+///
+/// a read-only root directory and a writable scratch directory can be
+/// mounted in the mount namespace, nothing else is mounted but `/proc`.
+///
+/// the standard input and outputs are the ones of any other [`Process`]:
+/// [`Capture`] and [`SendStdin`] work as usual. However the process that
+/// is spawned is the one supervising the sandbox, outside of its PID
+/// namespace: [`Process::id`], the [`ProcessStats`] and the
+/// [`ResourceUsage`] describe this supervisor, not the command.
+///
+/// ```no_run
+/// # use bawawa::{Command, Program, Sandbox};
+/// let mut command = Command::new(Program::new("cargo")?);
+/// command
+///     .argument("test")
+///     .sandbox(Sandbox::new().read_only_root("/").scratch("target", "/tmp"));
+/// # Ok::<(), bawawa::Error>(())
+/// ```
+///
+/// [`Command`]: ./struct.Command.html
+/// [`Process`]: ./struct.Process.html
+/// [`Capture`]: ./struct.Capture.html
+/// [`SendStdin`]: ./struct.SendStdin.html
+/// [`Process::id`]: ./struct.Process.html#method.id
+/// [`ProcessStats`]: ./struct.ProcessStats.html
+/// [`ResourceUsage`]: ./struct.ResourceUsage.html
+#[derive(Debug, Clone, Default, PartialEq, Eq, Hash)]
+pub struct Sandbox {
+    root: Option<PathBuf>,
+    scratch: Option<(PathBuf, PathBuf)>,
+}
+
+/// what the sandbox needs in the child, prepared before the fork
+struct Prepared {
+    root: Option<CString>,
+    /// the scratch directory and where it is mounted, relative to the
+    /// root if any
+    scratch: Option<(CString, CString)>,
+    /// the working directory within the root
+    working_directory: CString,
+}
+
+impl Sandbox {
+    /// a sandbox sharing the file system of the parent process
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// change the root directory of the command to a read-only view of
+    /// `root`
+    ///
+    /// the mount points below `root` are read-only too, which requires
+    /// `mount_setattr` (Linux 5.12). The working directory of the command
+    /// is taken within the new root.
+    pub fn read_only_root<P: Into<PathBuf>>(mut self, root: P) -> Self {
+        self.root = Some(root.into());
+        self
+    }
+
+    /// mount the `directory` at `mount_point` (within the root), writable
+    ///
+    /// the mount point must exist. With a [`read_only_root`], the mounts
+    /// below `directory` are read-only.
+    ///
+    /// [`read_only_root`]: #method.read_only_root
+    pub fn scratch<P, Q>(mut self, directory: P, mount_point: Q) -> Self
+    where
+        P: Into<PathBuf>,
+        Q: Into<PathBuf>,
+    {
+        self.scratch = Some((directory.into(), mount_point.into()));
+        self
+    }
+
+    fn prepare(&self, command: &Command) -> Prepared {
+        let current_directory = std::env::current_dir().unwrap_or_else(|_| PathBuf::from("/"));
+        let absolute = |path: &Path| current_directory.join(path);
+        // relative to the root once it is the working directory
+        let within_root = |path: &Path| match &self.root {
+            Some(_) => Path::new(".").join(path.strip_prefix("/").unwrap_or(path)),
+            None => absolute(path),
+        };
+
+        Prepared {
+            root: self.root.as_deref().map(|root| c_path(&absolute(root))),
+            scratch: self.scratch.as_ref().map(|(directory, mount_point)| {
+                (
+                    c_path(&absolute(directory)),
+                    c_path(&within_root(mount_point)),
+                )
+            }),
+            working_directory: c_path(&match command.working_directory() {
+                Some(directory) => current_directory.join(directory),
+                None => current_directory.clone(),
+            }),
+        }
+    }
+
+    /// enter the namespaces in the child, before `exec`
+    ///
+    /// a new PID namespace only applies to the children of the process
+    /// that enters it: the child supervises the first process of the
+    /// namespace, which supervises the command (the first process of a
+    /// PID namespace ignores the signals it has no handler for).
+    pub(crate) fn apply(
+        &self,
+        command: &Command,
+        process: &mut std::process::Command,
+        reporter: Reporter,
+    ) {
+        let prepared = self.prepare(command);
+
+        // only async-signal-safe functions are called in the child
+        unsafe {
+            process.pre_exec(move || {
+                let fail = || Err(reporter.fail(Step::Sandbox));
+
+                let uid = libc::geteuid();
+                let gid = libc::getegid();
+                let namespaces = libc::CLONE_NEWUSER
+                    | libc::CLONE_NEWNS
+                    | libc::CLONE_NEWPID
+                    | libc::CLONE_NEWNET
+                    | libc::CLONE_NEWIPC;
+                if libc::unshare(namespaces) != 0
+                    || !write_file(b"/proc/self/setgroups\0", b"deny")
+                    || !write_id_map(b"/proc/self/uid_map\0", uid)
+                    || !write_id_map(b"/proc/self/gid_map\0", gid)
+                {
+                    return fail();
+                }
+
+                // `getppid` is 0 in the first process of a PID namespace,
+                // its parent is watched through a pidfd instead
+                let parent = libc::syscall(libc::SYS_pidfd_open, libc::getpid(), 0) as libc::c_int;
+                if !fork_and_supervise() {
+                    return fail();
+                }
+                // the first process of the PID namespace, killed if the
+                // supervising process is, unless it already exited
+                libc::prctl(libc::PR_SET_PDEATHSIG, libc::SIGKILL);
+                if parent >= 0 {
+                    let mut exited = libc::pollfd {
+                        fd: parent,
+                        events: libc::POLLIN,
+                        revents: 0,
+                    };
+                    if libc::poll(&mut exited, 1, 0) > 0 {
+                        libc::_exit(1);
+                    }
+                    libc::close(parent);
+                }
+                if !prepared.mount() || !loopback_up() {
+                    return fail();
+                }
+
+                if !fork_and_supervise() {
+                    return fail();
+                }
+                Ok(())
+            });
+        }
+    }
+}
+
+impl Prepared {
+    /// mount the file systems of the sandbox, in its mount namespace
+    unsafe fn mount(&self) -> bool {
+        let root = b"/\0".as_ptr() as *const libc::c_char;
+        if !mount(
+            ptr::null(),
+            root,
+            ptr::null(),
+            libc::MS_REC | libc::MS_PRIVATE,
+        ) {
+            return false;
+        }
+
+        // a mount on top of `/` is not seen from `/`, the new root is
+        // reached from the working directory
+        if let Some(new_root) = &self.root {
+            if !mount(
+                new_root.as_ptr(),
+                new_root.as_ptr(),
+                ptr::null(),
+                libc::MS_BIND | libc::MS_REC,
+            ) || libc::chdir(new_root.as_ptr()) != 0
+            {
+                return false;
+            }
+        }
+
+        if let Some((directory, mount_point)) = &self.scratch {
+            if !mount(
+                directory.as_ptr(),
+                mount_point.as_ptr(),
+                ptr::null(),
+                libc::MS_BIND | libc::MS_REC,
+            ) {
+                return false;
+            }
+        }
+
+        if self.root.is_some() {
+            // the root and all its submounts are read-only, the scratch
+            // directory is made writable again
+            let current = b".\0".as_ptr() as *const libc::c_char;
+            let scratch_writable = || match &self.scratch {
+                Some((_, mount_point)) => set_read_only(mount_point.as_ptr(), false, false),
+                None => true,
+            };
+            if !set_read_only(current, true, true)
+                || !scratch_writable()
+                || libc::chroot(current) != 0
+                || libc::chdir(self.working_directory.as_ptr()) != 0
+            {
+                return false;
+            }
+        }
+
+        let proc = b"proc\0".as_ptr() as *const libc::c_char;
+        mount(
+            proc,
+            b"/proc\0".as_ptr() as *const _,
+            proc,
+            libc::MS_NOSUID | libc::MS_NODEV | libc::MS_NOEXEC,
+        )
+    }
+}
+
+fn c_path(path: &Path) -> CString {
+    CString::new(path.as_os_str().as_bytes()).unwrap_or_default()
+}
+
+unsafe fn mount(
+    source: *const libc::c_char,
+    target: *const libc::c_char,
+    file_system: *const libc::c_char,
+    flags: libc::c_ulong,
+) -> bool {
+    libc::mount(source, target, file_system, flags, ptr::null()) == 0
+}
+
+/// set or clear the read-only flag of the mount at `path`, and of its
+/// submounts if `recursive`
+unsafe fn set_read_only(path: *const libc::c_char, read_only: bool, recursive: bool) -> bool {
+    let (attr_set, attr_clr) = if read_only {
+        (MOUNT_ATTR_RDONLY, 0)
+    } else {
+        (0, MOUNT_ATTR_RDONLY)
+    };
+    let attributes = MountAttr {
+        attr_set,
+        attr_clr,
+        propagation: 0,
+        userns_fd: 0,
+    };
+    let flags = if recursive { libc::AT_RECURSIVE } else { 0 };
+    libc::syscall(
+        libc::SYS_mount_setattr,
+        libc::AT_FDCWD,
+        path,
+        flags,
+        &attributes as *const MountAttr,
+        std::mem::size_of::<MountAttr>(),
+    ) == 0
+}
+
+unsafe fn write_file(path: &[u8], content: &[u8]) -> bool {
+    let fd = libc::open(path.as_ptr() as *const _, libc::O_WRONLY | libc::O_CLOEXEC);
+    if fd < 0 {
+        return false;
+    }
+    let written = libc::write(fd, content.as_ptr() as *const _, content.len());
+    libc::close(fd);
+    written == content.len() as isize
+}
+
+/// map `0` in the namespace to `id`, without allocating
+unsafe fn write_id_map(path: &[u8], id: u32) -> bool {
+    let mut line = *b"0           1";
+    let mut digits = [0u8; 10];
+    let mut count = 0;
+    let mut id = id;
+    loop {
+        digits[count] = b'0' + (id % 10) as u8;
+        count += 1;
+        id /= 10;
+        if id == 0 {
+            break;
+        }
+    }
+    for (index, digit) in digits[..count].iter().rev().enumerate() {
+        line[2 + index] = *digit;
+    }
+    write_file(path, &line)
+}
+
+/// bring the loopback interface of the network namespace up
+unsafe fn loopback_up() -> bool {
+    let socket = libc::socket(libc::AF_INET, libc::SOCK_DGRAM | libc::SOCK_CLOEXEC, 0);
+    if socket < 0 {
+        return false;
+    }
+    let mut request: libc::ifreq = std::mem::zeroed();
+    request.ifr_name[0] = b'l' as libc::c_char;
+    request.ifr_name[1] = b'o' as libc::c_char;
+    let up = libc::ioctl(socket, libc::SIOCGIFFLAGS as _, &mut request) == 0 && {
+        request.ifr_ifru.ifru_flags |= libc::IFF_UP as libc::c_short;
+        libc::ioctl(socket, libc::SIOCSIFFLAGS as _, &request) == 0
+    };
+    libc::close(socket);
+    up
+}
+
+/// fork, the parent supervising the child until it exits: `true` is
+/// returned in the child
+unsafe fn fork_and_supervise() -> bool {
+    match libc::fork() {
+        -1 => false,
+        0 => true,
+        child => supervise(child),
+    }
+}
+
+extern "C" fn forward(signal: libc::c_int) {
+    let child = FORWARD_TO.load(Ordering::SeqCst);
+    if child > 0 {
+        unsafe { libc::kill(child, signal) };
+    }
+}
+
+/// forward the signals to `child` and exit like it once it exited, the
+/// other children (orphans of the PID namespace) are reaped
+unsafe fn supervise(child: libc::pid_t) -> ! {
+    FORWARD_TO.store(child, Ordering::SeqCst);
+    for signal in FORWARDED_SIGNALS.iter() {
+        let mut action: libc::sigaction = std::mem::zeroed();
+        action.sa_sigaction = forward as extern "C" fn(libc::c_int) as libc::sighandler_t;
+        action.sa_flags = libc::SA_RESTART;
+        libc::sigemptyset(&mut action.sa_mask);
+        libc::sigaction(*signal, &action, ptr::null_mut());
+    }
+
+    // the pipes stay open as long as a process holds them, only the
+    // command does
+    if libc::syscall(libc::SYS_close_range, 0, libc::c_uint::MAX, 0) != 0 {
+        for fd in 0..1024 {
+            libc::close(fd);
+        }
+    }
+
+    let status = loop {
+        let mut status = 0;
+        let pid = libc::waitpid(-1, &mut status, 0);
+        if pid == child {
+            break status;
+        }
+        if pid < 0 && io::Error::last_os_error().raw_os_error() != Some(libc::EINTR) {
+            libc::_exit(1);
+        }
+    };
+
+    if libc::WIFSIGNALED(status) {
+        let signal = libc::WTERMSIG(status);
+        libc::signal(signal, libc::SIG_DFL);
+        libc::kill(libc::getpid(), signal);
+        // the first process of a PID namespace cannot be killed this way
+        libc::_exit(128 + signal);
+    }
+    libc::_exit(libc::WEXITSTATUS(status))
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::{
+        temp::TempPath, Error, Process, Program, Result, StandardInput as _, StandardOutput as _,
+    };
+    use futures::{Future as _, Sink as _};
+    use tokio_codec::LinesCodec;
+
+    /// whether the sandbox can be set up: unprivileged user namespaces
+    /// may be disabled (`user.max_user_namespaces`,
+    /// `kernel.unprivileged_userns_clone` or AppArmor), and so may be
+    /// mounting in them
+    fn user_namespaces() -> bool {
+        unsafe {
+            match libc::fork() {
+                -1 => false,
+                0 => {
+                    let uid = libc::geteuid();
+                    let gid = libc::getegid();
+                    let root = b"/\0".as_ptr() as *const libc::c_char;
+                    let usable = libc::unshare(libc::CLONE_NEWUSER | libc::CLONE_NEWNS) == 0
+                        && write_file(b"/proc/self/setgroups\0", b"deny")
+                        && write_id_map(b"/proc/self/uid_map\0", uid)
+                        && write_id_map(b"/proc/self/gid_map\0", gid)
+                        && mount(
+                            ptr::null(),
+                            root,
+                            ptr::null(),
+                            libc::MS_REC | libc::MS_PRIVATE,
+                        );
+                    libc::_exit(if usable { 0 } else { 1 })
+                }
+                child => {
+                    let mut status = 0;
+                    libc::waitpid(child, &mut status, 0) == child
+                        && libc::WIFEXITED(status)
+                        && libc::WEXITSTATUS(status) == 0
+                }
+            }
+        }
+    }
+
+    macro_rules! require_user_namespaces {
+        () => {
+            if !user_namespaces() {
+                eprintln!("skipped: unprivileged user namespaces are disabled");
+                return Ok(());
+            }
+        };
+    }
+
+    #[test]
+    fn isolated_process() -> Result<()> {
+        require_user_namespaces!();
+        let mut command = Command::shell("echo $$ $(id -u); grep -c : /proc/net/dev; exit 3")?;
+        command.sandbox(Sandbox::new());
+
+        let output = command.spawn()?.output().wait()?;
+        assert_eq!(String::from_utf8_lossy(&output.stdout), "2 0\n1\n");
+        assert_eq!(output.status.code(), Some(3));
+        Ok(())
+    }
+
+    #[test]
+    fn forward_signals() -> Result<()> {
+        use crate::Control as _;
+        require_user_namespaces!();
+
+        let mut command = Command::new(Program::new("sleep")?);
+        command.argument("10").sandbox(Sandbox::new());
+
+        let started = std::time::Instant::now();
+        let mut process = command.spawn()?;
+        std::thread::sleep(std::time::Duration::from_millis(100));
+        process.terminate()?;
+        let status = process.wait()?;
+        assert_eq!(status.code(), Some(128 + libc::SIGTERM));
+        assert!(started.elapsed() < std::time::Duration::from_secs(5));
+        Ok(())
+    }
+
+    #[test]
+    fn read_only_root_and_scratch() -> Result<()> {
+        require_user_namespaces!();
+        let scratch = TempPath::dir("sandbox");
+
+        // `/dev/shm` is usually a mount of its own
+        let mut command = Command::shell(
+            "touch /bawawa-sandbox || touch /dev/shm/bawawa-sandbox || echo 'hello' > /mnt/file",
+        )?;
+        command.sandbox(
+            Sandbox::new()
+                .read_only_root("/")
+                .scratch(scratch.to_path_buf(), "/mnt"),
+        );
+        let output = command.spawn()?.output().wait()?;
+        assert!(
+            output.status.success(),
+            "{}",
+            String::from_utf8_lossy(&output.stderr)
+        );
+
+        assert_eq!(std::fs::read_to_string(scratch.join("file"))?, "hello\n");
+        assert!(!Path::new("/bawawa-sandbox").exists());
+        assert!(!Path::new("/dev/shm/bawawa-sandbox").exists());
+        Ok(())
+    }
+
+    #[test]
+    fn stdio_and_errors() -> Result<()> {
+        require_user_namespaces!();
+        let mut command = Command::new(Program::new("cat")?);
+        command.sandbox(Sandbox::new());
+
+        let process = Process::spawn(command.clone())?
+            .send_stdin(LinesCodec::new())
+            .capture_stdout(LinesCodec::new())
+            .send("hello".to_owned())
+            .wait()?;
+        let mut lines = futures::Stream::wait(process);
+        assert_eq!(lines.next().transpose()?, Some("hello".to_owned()));
+        drop(lines);
+
+        command.sandbox(Sandbox::new().read_only_root("/bawawa-missing-root"));
+        match command.spawn().err().expect("the root does not exist") {
+            error @ Error::CannotSandbox { .. } => {
+                assert!(error.to_string().starts_with("cannot sandbox command"))
+            }
+            error => panic!("unexpected error: {}", error),
+        }
+        Ok(())
+    }
+}
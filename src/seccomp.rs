@@ -0,0 +1,376 @@
+use crate::{
+    pre_exec::{Reporter, Step},
+    Error, Result,
+};
+use std::{collections::BTreeSet, os::unix::process::CommandExt as _};
+
+/// the architecture of the system calls, as seen by seccomp
+#[cfg(target_arch = "x86_64")]
+const AUDIT_ARCH: u32 = 0xc000_003e;
+#[cfg(target_arch = "aarch64")]
+const AUDIT_ARCH: u32 = 0xc000_00b7;
+
+/// the system calls of the x32 ABI, on x86_64, have this bit set
+#[cfg(target_arch = "x86_64")]
+const X32_SYSCALL_BIT: u32 = 0x4000_0000;
+
+/// a seccomp filter of the system calls of a [`Command`], installed
+/// right before `exec`
+///
+/// either only the listed system calls are allowed (the program must be
+/// allowed to call `execve` then), or all but the listed ones. The
+/// system calls of another architecture are always denied.
+///
+/// ```
+/// # use bawawa::{Denied, SyscallFilter};
+/// let filter = SyscallFilter::deny(["ptrace", "mount", "umount2"])?
+///     .denied(Denied::Errno(libc::EPERM))?;
+/// # Ok::<(), bawawa::Error>(())
+/// ```
+///
+/// [`Command`]: ./struct.Command.html
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub struct SyscallFilter {
+    syscalls: BTreeSet<&'static str>,
+    allow_listed: bool,
+    denied: Denied,
+}
+
+/// what happens when a [`SyscallFilter`] denies a system call
+///
+/// [`SyscallFilter`]: ./struct.SyscallFilter.html
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum Denied {
+    /// the process is killed by a `SIGSYS` signal
+    Kill,
+    /// the system call fails with the given error number, within
+    /// `1..=4095`
+    Errno(i32),
+}
+
+impl SyscallFilter {
+    /// deny all the system calls but the given ones, killing the process
+    /// by default
+    ///
+    /// # Error
+    ///
+    /// fails with [`Error::UnknownSyscall`] if a system call does not
+    /// exist on this architecture.
+    ///
+    /// [`Error::UnknownSyscall`]: ./enum.Error.html#variant.UnknownSyscall
+    pub fn allow_only<I, S>(syscalls: I) -> Result<Self>
+    where
+        I: IntoIterator<Item = S>,
+        S: AsRef<str>,
+    {
+        Self::new(syscalls, true)
+    }
+
+    /// deny the given system calls, killing the process by default
+    ///
+    /// # Error
+    ///
+    /// fails with [`Error::UnknownSyscall`] if a system call does not
+    /// exist on this architecture.
+    ///
+    /// [`Error::UnknownSyscall`]: ./enum.Error.html#variant.UnknownSyscall
+    pub fn deny<I, S>(syscalls: I) -> Result<Self>
+    where
+        I: IntoIterator<Item = S>,
+        S: AsRef<str>,
+    {
+        Self::new(syscalls, false)
+    }
+
+    fn new<I, S>(syscalls: I, allow_listed: bool) -> Result<Self>
+    where
+        I: IntoIterator<Item = S>,
+        S: AsRef<str>,
+    {
+        let syscalls = syscalls
+            .into_iter()
+            .map(|name| {
+                let name = name.as_ref();
+                syscall(name)
+                    .map(|(known, _)| known)
+                    .ok_or_else(|| Error::UnknownSyscall {
+                        name: name.to_owned(),
+                    })
+            })
+            .collect::<Result<_>>()?;
+        Ok(SyscallFilter {
+            syscalls,
+            allow_listed,
+            denied: Denied::Kill,
+        })
+    }
+
+    /// set what happens when a system call is denied
+    ///
+    /// # Error
+    ///
+    /// fails with [`Error::InvalidErrno`] if the error number is not
+    /// within `1..=4095`, it would not be seen as an error.
+    ///
+    /// [`Error::InvalidErrno`]: ./enum.Error.html#variant.InvalidErrno
+    pub fn denied(mut self, denied: Denied) -> Result<Self> {
+        if let Denied::Errno(errno) = denied {
+            if !(1..=4095).contains(&errno) {
+                return Err(Error::InvalidErrno { errno });
+            }
+        }
+        self.denied = denied;
+        Ok(self)
+    }
+
+    /// the BPF program of the filter
+    fn program(&self) -> Vec<libc::sock_filter> {
+        let allowed = libc::SECCOMP_RET_ALLOW;
+        let denied = match self.denied {
+            Denied::Kill => libc::SECCOMP_RET_KILL_PROCESS,
+            Denied::Errno(errno) => {
+                libc::SECCOMP_RET_ERRNO | (errno as u32 & libc::SECCOMP_RET_DATA)
+            }
+        };
+        let (listed, others) = if self.allow_listed {
+            (allowed, denied)
+        } else {
+            (denied, allowed)
+        };
+
+        // the offsets of the fields of `struct seccomp_data`
+        let nr = 0;
+        let arch = 4;
+
+        let mut program = vec![
+            statement(libc::BPF_LD | libc::BPF_W | libc::BPF_ABS, arch),
+            jump(
+                libc::BPF_JMP | libc::BPF_JEQ | libc::BPF_K,
+                AUDIT_ARCH,
+                1,
+                0,
+            ),
+            statement(libc::BPF_RET | libc::BPF_K, libc::SECCOMP_RET_KILL_PROCESS),
+            statement(libc::BPF_LD | libc::BPF_W | libc::BPF_ABS, nr),
+        ];
+        #[cfg(target_arch = "x86_64")]
+        program.extend([
+            jump(
+                libc::BPF_JMP | libc::BPF_JGE | libc::BPF_K,
+                X32_SYSCALL_BIT,
+                0,
+                1,
+            ),
+            statement(libc::BPF_RET | libc::BPF_K, libc::SECCOMP_RET_KILL_PROCESS),
+        ]);
+        for name in self.syscalls.iter() {
+            let (_, number) = syscall(name).expect("checked on creation");
+            program.extend([
+                jump(
+                    libc::BPF_JMP | libc::BPF_JEQ | libc::BPF_K,
+                    number as u32,
+                    0,
+                    1,
+                ),
+                statement(libc::BPF_RET | libc::BPF_K, listed),
+            ]);
+        }
+        program.push(statement(libc::BPF_RET | libc::BPF_K, others));
+        program
+    }
+
+    /// install the filter in the child, before `exec`
+    pub(crate) fn apply(&self, process: &mut std::process::Command, reporter: Reporter) {
+        let program = self.program();
+
+        // only async-signal-safe functions are called in the child
+        unsafe {
+            process.pre_exec(move || {
+                let filter = libc::sock_fprog {
+                    len: program.len() as libc::c_ushort,
+                    filter: program.as_ptr() as *mut _,
+                };
+                if libc::prctl(libc::PR_SET_NO_NEW_PRIVS, 1, 0, 0, 0) != 0
+                    || libc::prctl(
+                        libc::PR_SET_SECCOMP,
+                        libc::SECCOMP_MODE_FILTER,
+                        &filter as *const libc::sock_fprog,
+                    ) != 0
+                {
+                    return Err(reporter.fail(Step::Seccomp));
+                }
+                Ok(())
+            });
+        }
+    }
+}
+
+/// the name (without the `SYS_` prefix) and the number of a system call
+fn syscall(name: &str) -> Option<(&'static str, libc::c_long)> {
+    COMMON_SYSCALLS
+        .iter()
+        .chain(ARCH_SYSCALLS)
+        .map(|(known, number)| (&known["SYS_".len()..], *number))
+        .find(|(known, _)| *known == name)
+}
+
+fn statement(code: u32, k: u32) -> libc::sock_filter {
+    jump(code, k, 0, 0)
+}
+
+fn jump(code: u32, k: u32, jt: u8, jf: u8) -> libc::sock_filter {
+    libc::sock_filter {
+        code: code as u16,
+        jt,
+        jf,
+        k,
+    }
+}
+
+macro_rules! syscalls {
+    ($($syscall:ident)*) => {
+        &[$((stringify!($syscall), libc::$syscall)),*]
+    };
+}
+
+/// the system calls known on all the architectures, by name
+const COMMON_SYSCALLS: &[(&str, libc::c_long)] = syscalls! {
+    SYS_accept SYS_accept4 SYS_acct SYS_add_key SYS_adjtimex SYS_bind SYS_bpf SYS_brk
+    SYS_capget SYS_capset SYS_chdir SYS_chroot SYS_clock_adjtime SYS_clock_getres
+    SYS_clock_gettime SYS_clock_nanosleep SYS_clock_settime SYS_clone SYS_clone3
+    SYS_close SYS_close_range SYS_connect SYS_copy_file_range SYS_delete_module SYS_dup
+    SYS_dup3 SYS_epoll_create1 SYS_epoll_ctl SYS_epoll_pwait SYS_epoll_pwait2
+    SYS_eventfd2 SYS_execve SYS_execveat SYS_exit SYS_exit_group SYS_faccessat
+    SYS_faccessat2 SYS_fadvise64 SYS_fallocate SYS_fanotify_init SYS_fanotify_mark
+    SYS_fchdir SYS_fchmod SYS_fchmodat SYS_fchown SYS_fchownat SYS_fcntl SYS_fdatasync
+    SYS_fgetxattr SYS_finit_module SYS_flistxattr SYS_flock SYS_fremovexattr
+    SYS_fsconfig SYS_fsetxattr SYS_fsmount SYS_fsopen SYS_fspick SYS_fstat SYS_fstatfs
+    SYS_fsync SYS_ftruncate SYS_futex SYS_futex_waitv SYS_get_mempolicy
+    SYS_get_robust_list SYS_getcpu SYS_getcwd SYS_getdents64 SYS_getegid SYS_geteuid
+    SYS_getgid SYS_getgroups SYS_getitimer SYS_getpeername SYS_getpgid SYS_getpid
+    SYS_getppid SYS_getpriority SYS_getrandom SYS_getresgid SYS_getresuid SYS_getrlimit
+    SYS_getrusage SYS_getsid SYS_getsockname SYS_getsockopt SYS_gettid SYS_gettimeofday
+    SYS_getuid SYS_getxattr SYS_init_module SYS_inotify_add_watch SYS_inotify_init1
+    SYS_inotify_rm_watch SYS_io_cancel SYS_io_destroy SYS_io_getevents SYS_io_setup
+    SYS_io_submit SYS_io_uring_enter SYS_io_uring_register SYS_io_uring_setup SYS_ioctl
+    SYS_ioprio_get SYS_ioprio_set SYS_kcmp SYS_kexec_file_load SYS_kexec_load SYS_keyctl
+    SYS_kill SYS_landlock_add_rule SYS_landlock_create_ruleset
+    SYS_landlock_restrict_self SYS_lgetxattr SYS_linkat SYS_listen SYS_listxattr
+    SYS_llistxattr SYS_lookup_dcookie SYS_lremovexattr SYS_lseek SYS_lsetxattr
+    SYS_madvise SYS_mbind SYS_membarrier SYS_memfd_create SYS_memfd_secret
+    SYS_migrate_pages SYS_mincore SYS_mkdirat SYS_mknodat SYS_mlock SYS_mlock2
+    SYS_mlockall SYS_mmap SYS_mount SYS_mount_setattr SYS_move_mount SYS_move_pages
+    SYS_mprotect SYS_mq_getsetattr SYS_mq_notify SYS_mq_open SYS_mq_timedreceive
+    SYS_mq_timedsend SYS_mq_unlink SYS_mremap SYS_mseal SYS_msgctl SYS_msgget SYS_msgrcv
+    SYS_msgsnd SYS_msync SYS_munlock SYS_munlockall SYS_munmap SYS_name_to_handle_at
+    SYS_nanosleep SYS_newfstatat SYS_nfsservctl SYS_open_by_handle_at SYS_open_tree
+    SYS_openat SYS_openat2 SYS_perf_event_open SYS_personality SYS_pidfd_getfd
+    SYS_pidfd_open SYS_pidfd_send_signal SYS_pipe2 SYS_pivot_root SYS_pkey_alloc
+    SYS_pkey_free SYS_pkey_mprotect SYS_ppoll SYS_prctl SYS_pread64 SYS_preadv
+    SYS_preadv2 SYS_prlimit64 SYS_process_madvise SYS_process_mrelease
+    SYS_process_vm_readv SYS_process_vm_writev SYS_pselect6 SYS_ptrace SYS_pwrite64
+    SYS_pwritev SYS_pwritev2 SYS_quotactl SYS_quotactl_fd SYS_read SYS_readahead
+    SYS_readlinkat SYS_readv SYS_reboot SYS_recvfrom SYS_recvmmsg SYS_recvmsg
+    SYS_remap_file_pages SYS_removexattr SYS_renameat SYS_renameat2 SYS_request_key
+    SYS_restart_syscall SYS_rseq SYS_rt_sigaction SYS_rt_sigpending SYS_rt_sigprocmask
+    SYS_rt_sigqueueinfo SYS_rt_sigreturn SYS_rt_sigsuspend SYS_rt_sigtimedwait
+    SYS_rt_tgsigqueueinfo SYS_sched_get_priority_max SYS_sched_get_priority_min
+    SYS_sched_getaffinity SYS_sched_getattr SYS_sched_getparam SYS_sched_getscheduler
+    SYS_sched_rr_get_interval SYS_sched_setaffinity SYS_sched_setattr SYS_sched_setparam
+    SYS_sched_setscheduler SYS_sched_yield SYS_seccomp SYS_semctl SYS_semget SYS_semop
+    SYS_semtimedop SYS_sendfile SYS_sendmmsg SYS_sendmsg SYS_sendto SYS_set_mempolicy
+    SYS_set_mempolicy_home_node SYS_set_robust_list SYS_set_tid_address
+    SYS_setdomainname SYS_setfsgid SYS_setfsuid SYS_setgid SYS_setgroups SYS_sethostname
+    SYS_setitimer SYS_setns SYS_setpgid SYS_setpriority SYS_setregid SYS_setresgid
+    SYS_setresuid SYS_setreuid SYS_setrlimit SYS_setsid SYS_setsockopt SYS_settimeofday
+    SYS_setuid SYS_setxattr SYS_shmat SYS_shmctl SYS_shmdt SYS_shmget SYS_shutdown
+    SYS_sigaltstack SYS_signalfd4 SYS_socket SYS_socketpair SYS_splice SYS_statfs
+    SYS_statx SYS_swapoff SYS_swapon SYS_symlinkat SYS_sync SYS_sync_file_range
+    SYS_syncfs SYS_sysinfo SYS_syslog SYS_tee SYS_tgkill SYS_timer_create
+    SYS_timer_delete SYS_timer_getoverrun SYS_timer_gettime SYS_timer_settime
+    SYS_timerfd_create SYS_timerfd_gettime SYS_timerfd_settime SYS_times SYS_tkill
+    SYS_truncate SYS_umask SYS_umount2 SYS_uname SYS_unlinkat SYS_unshare
+    SYS_userfaultfd SYS_utimensat SYS_vhangup SYS_vmsplice SYS_wait4 SYS_waitid
+    SYS_write SYS_writev
+};
+
+/// the system calls known on x86_64 only, replaced by more generic ones
+/// elsewhere
+#[cfg(target_arch = "x86_64")]
+const ARCH_SYSCALLS: &[(&str, libc::c_long)] = syscalls! {
+    SYS__sysctl SYS_access SYS_afs_syscall SYS_alarm SYS_arch_prctl SYS_chmod SYS_chown
+    SYS_creat SYS_dup2 SYS_epoll_create SYS_epoll_ctl_old SYS_epoll_wait
+    SYS_epoll_wait_old SYS_eventfd SYS_fchmodat2 SYS_fork SYS_futimesat
+    SYS_get_thread_area SYS_getdents SYS_getpgrp SYS_getpmsg SYS_inotify_init SYS_ioperm
+    SYS_iopl SYS_lchown SYS_link SYS_lstat SYS_mkdir SYS_mknod SYS_modify_ldt SYS_open
+    SYS_pause SYS_pipe SYS_poll SYS_putpmsg SYS_readlink SYS_rename SYS_rmdir
+    SYS_security SYS_select SYS_set_thread_area SYS_signalfd SYS_stat SYS_symlink
+    SYS_sysfs SYS_time SYS_tuxcall SYS_unlink SYS_uselib SYS_ustat SYS_utime SYS_utimes
+    SYS_vfork SYS_vserver
+};
+#[cfg(not(target_arch = "x86_64"))]
+const ARCH_SYSCALLS: &[(&str, libc::c_long)] = &[];
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::{temp::TempPath, Command, Program};
+    use futures::Future as _;
+    use std::os::unix::process::ExitStatusExt as _;
+
+    fn mkdir(name: &str, filter: SyscallFilter) -> Result<(std::process::ExitStatus, bool)> {
+        let directory = TempPath::new(name);
+        let mut command = Command::new(Program::new("mkdir")?);
+        command.argument(directory.display().to_string());
+        command.syscall_filter(filter);
+
+        let status = command.spawn()?.wait()?;
+        let created = directory.is_dir();
+        Ok((status, created))
+    }
+
+    #[test]
+    fn denied_syscall_kills() -> Result<()> {
+        let (status, created) = mkdir("seccomp-kill", SyscallFilter::deny(["mkdir", "mkdirat"])?)?;
+        assert_eq!(status.signal(), Some(libc::SIGSYS));
+        assert!(!created);
+
+        let (status, created) = mkdir("seccomp-other", SyscallFilter::deny(["rmdir"])?)?;
+        assert!(status.success());
+        assert!(created);
+        Ok(())
+    }
+
+    #[test]
+    fn denied_syscall_fails() -> Result<()> {
+        let filter =
+            SyscallFilter::deny(["mkdir", "mkdirat"])?.denied(Denied::Errno(libc::EPERM))?;
+        let (status, created) = mkdir("seccomp-errno", filter)?;
+        assert_eq!(status.code(), Some(1));
+        assert!(!created);
+
+        for errno in [0, -1, 4096] {
+            match SyscallFilter::deny(["mkdir"])?.denied(Denied::Errno(errno)) {
+                Err(Error::InvalidErrno { errno: invalid }) => assert_eq!(invalid, errno),
+                _ => panic!("{} is not a valid error number", errno),
+            }
+        }
+        Ok(())
+    }
+
+    #[test]
+    fn allow_list() -> Result<()> {
+        // not even `execve` is allowed
+        let (status, _) = mkdir(
+            "seccomp-allow",
+            SyscallFilter::allow_only(["read", "write"])?,
+        )?;
+        assert_eq!(status.signal(), Some(libc::SIGSYS));
+
+        match SyscallFilter::deny(["mkdir", "make_coffee"]) {
+            Err(Error::UnknownSyscall { name }) => assert_eq!(name, "make_coffee"),
+            _ => panic!("make_coffee is not a system call"),
+        }
+        Ok(())
+    }
+}
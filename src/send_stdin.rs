@@ -1,10 +1,10 @@
 use crate::{
-    Command, Control, Error, ErrorKind, ResultExt, StandardError, StandardInput, StandardOutput,
+    ChildStderr, ChildStdin, ChildStdout, Command, Control, Error, StandardError, StandardInput,
+    StandardOutput,
 };
 use futures::prelude::*;
 use std::{marker::PhantomData, mem::ManuallyDrop};
 use tokio_codec::{Encoder, FramedWrite};
-use tokio_process::{ChildStderr, ChildStdin, ChildStdout};
 
 /// provide API to control the sending part to the standard input.
 /// created from [`StandardInput::send_stdin`].
@@ -59,6 +59,11 @@ where
     fn kill(&mut self) -> Result<(), Error> {
         unsafe { (*self.command).kill() }
     }
+
+    #[inline]
+    fn terminate(&mut self) -> Result<(), Error> {
+        unsafe { (*self.command).terminate() }
+    }
 }
 
 impl<'a, C, E, Item> Drop for SendStdin<'a, C, E, Item> {
@@ -85,19 +90,19 @@ impl<'a, C, E, Item> Drop for SendStdin<'a, C, E, Item> {
 impl<'a, C, E, Err, Item> Sink for SendStdin<'a, C, E, Item>
 where
     E: Encoder<Item = Item, Error = Err>,
-    Err: std::error::Error + Send + From<std::io::Error> + 'static,
+    Err: std::error::Error + Send + Sync + From<std::io::Error> + 'static,
 {
     type SinkItem = Item;
     type SinkError = Error;
     fn start_send(&mut self, item: Self::SinkItem) -> StartSend<Self::SinkItem, Self::SinkError> {
         self.framed_write
             .start_send(item)
-            .chain_err(|| ErrorKind::SendStdin)
+            .map_err(|error| Error::SendStdin(Box::new(error)))
     }
     fn poll_complete(&mut self) -> Poll<(), Self::SinkError> {
         self.framed_write
             .poll_complete()
-            .chain_err(|| ErrorKind::SendStdin)
+            .map_err(|error| Error::SendStdin(Box::new(error)))
     }
 }
 
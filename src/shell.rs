@@ -0,0 +1,114 @@
+use std::fmt;
+
+/// the characters that do not need to be quoted in a shell script
+fn is_safe(c: char) -> bool {
+    c.is_ascii_alphanumeric() || "_@%+=:,./-".contains(c)
+}
+
+/// quote `parameter` so it is passed as a single word to a POSIX shell,
+/// whatever it contains
+///
+/// ```
+/// # use bawawa::shell_quote;
+/// assert_eq!(shell_quote("main.rs"), "main.rs");
+/// assert_eq!(shell_quote("it's $HOME"), r#"'it'\''s $HOME'"#);
+/// ```
+pub fn shell_quote<T: fmt::Display + ?Sized>(parameter: &T) -> String {
+    let parameter = parameter.to_string();
+    if !parameter.is_empty() && parameter.chars().all(is_safe) {
+        parameter
+    } else {
+        format!("'{}'", parameter.replace('\'', r#"'\''"#))
+    }
+}
+
+/// format a shell script for [`Command::shell`], every parameter being
+/// quoted with [`shell_quote`]
+///
+/// ```
+/// # use bawawa::shell;
+/// let pattern = "a b; rm -rf /";
+/// assert_eq!(shell!("grep {} {}", pattern, "file.txt"), "grep 'a b; rm -rf /' file.txt");
+/// ```
+///
+/// only the bare `{}` placeholders are accepted: the named, numbered or
+/// formatted ones (`{pattern}`, `{0}`, `{:?}`...) would not be quoted
+/// (or quoted twice) and fail to compile.
+///
+/// ```compile_fail
+/// # use bawawa::shell;
+/// let pattern = "a b; rm -rf /";
+/// shell!("grep {pattern} file.txt");
+/// ```
+///
+/// [`Command::shell`]: ./struct.Command.html#method.shell
+/// [`shell_quote`]: ./fn.shell_quote.html
+#[macro_export]
+macro_rules! shell {
+    ($script:literal $(, $parameter:expr)* $(,)?) => {{
+        const _: () = $crate::__check_shell_script($script);
+        format!($script $(, $crate::shell_quote(&$parameter))*)
+    }};
+}
+
+/// fail to compile if the script of [`shell!`] has other placeholders
+/// than `{}`
+///
+/// [`shell!`]: ./macro.shell.html
+#[doc(hidden)]
+pub const fn check_script(script: &str) {
+    let script = script.as_bytes();
+    let mut index = 0;
+    while index < script.len() {
+        match script[index] {
+            b'{' if index + 1 < script.len() && script[index + 1] == b'{' => index += 1,
+            b'{' if index + 1 < script.len() && script[index + 1] == b'}' => index += 1,
+            b'{' => panic!("shell! only accepts bare placeholders, the others are not quoted"),
+            _ => {}
+        }
+        index += 1;
+    }
+}
+
+#[cfg(all(test, unix))]
+mod test {
+    use crate::{temp::TempPath, Command, Result};
+    use futures::Future as _;
+
+    #[test]
+    fn no_injection() -> Result<()> {
+        let file = TempPath::file("shell", "needle\n$(touch injected)\n");
+
+        for pattern in ["needle", "$(touch injected)", "'; touch injected; '"] {
+            let script = shell!("grep -F -- {} {}", pattern, file.display());
+            let output = Command::shell(&script)?.spawn()?.output().wait()?;
+            let expected = match pattern {
+                "needle" => "needle\n",
+                "$(touch injected)" => "$(touch injected)\n",
+                _ => "",
+            };
+            assert_eq!(String::from_utf8_lossy(&output.stdout), expected);
+        }
+
+        assert!(!std::path::Path::new("injected").exists());
+        Ok(())
+    }
+
+    #[test]
+    fn check_placeholders() {
+        super::check_script("grep {} {{not a placeholder}} {}");
+        for script in ["{pattern}", "{0}", "{:?}", "{ }", "{"] {
+            assert!(std::panic::catch_unwind(|| super::check_script(script)).is_err());
+        }
+    }
+
+    #[test]
+    fn display_the_script() -> Result<()> {
+        let mut command = Command::shell("ls *.rs && echo done")?;
+        command.current_working_directory("src".into());
+
+        assert_eq!(command.to_string(), "CWD=src ls *.rs && echo done");
+        assert!(command.spawn()?.wait()?.success());
+        Ok(())
+    }
+}
@@ -0,0 +1,326 @@
+use crate::{ChildStderr, ChildStdin, ChildStdout, Command, Program, ResourceUsage};
+#[cfg(target_os = "linux")]
+use futures::{future::FlattenStream, task, Stream as _};
+use futures::{Async, Future as _, Poll};
+#[cfg(target_os = "linux")]
+use std::sync::Mutex;
+use std::{fmt, hash, io, process::ExitStatus, sync::Arc};
+use tokio_process::CommandExt as _;
+
+/// spawn the processes of the [`Command`]s
+///
+/// the [`OsSpawner`] is used by default, another spawner can be set with
+/// [`Command::spawner`] (for example the [`FakeSpawner`] to test code
+/// without running actual programs).
+///
+/// [`Command`]: ./struct.Command.html
+/// [`OsSpawner`]: ./struct.OsSpawner.html
+/// [`Command::spawner`]: ./struct.Command.html#method.spawner
+/// [`FakeSpawner`]: ./struct.FakeSpawner.html
+pub trait Spawner: Send + Sync {
+    /// start the given command
+    fn spawn(&self, command: &Command) -> io::Result<Spawned>;
+
+    /// check the program exists and can be executed, see [`Program::with_spawner`]
+    ///
+    /// by default the program is spawned with `--help` then killed.
+    ///
+    /// [`Program::with_spawner`]: ./struct.Program.html#method.with_spawner
+    fn check_program(&self, program: &Program) -> io::Result<()> {
+        let mut command = Command::new(program.clone());
+        command.argument("--help");
+        // dropping the child kills it
+        self.spawn(&command).map(|_| ())
+    }
+}
+
+/// a child process started by a [`Spawner`]
+///
+/// [`Spawner`]: ./trait.Spawner.html
+pub trait Child: Send {
+    /// the Process ID of the child
+    fn id(&self) -> u32;
+
+    /// poll the exit status of the child
+    fn poll_exit(&mut self) -> Poll<ExitStatus, io::Error>;
+
+    /// force the child to finish
+    fn kill(&mut self) -> io::Result<()>;
+
+    /// ask the child to finish, by default it is killed
+    fn terminate(&mut self) -> io::Result<()> {
+        self.kill()
+    }
+
+    /// the resources used by the child, once it has exited
+    fn resource_usage(&mut self) -> Option<ResourceUsage> {
+        None
+    }
+}
+
+/// what a [`Spawner`] returns: the child and its standard inputs and outputs
+///
+/// [`Spawner`]: ./trait.Spawner.html
+pub struct Spawned {
+    pub child: Box<dyn Child>,
+    pub stdin: Option<ChildStdin>,
+    pub stdout: Option<ChildStdout>,
+    pub stderr: Option<ChildStderr>,
+}
+
+/// the default [`Spawner`], starting actual processes of the operating system
+///
+/// [`Spawner`]: ./trait.Spawner.html
+#[derive(Debug, Clone, Copy, Default)]
+pub struct OsSpawner;
+
+struct OsChild {
+    child: tokio_process::Child,
+    /// the number of `SIGCHLD` seen, to collect the resource usage of the
+    /// child before `tokio_process` reaps it
+    #[cfg(target_os = "linux")]
+    exits_seen: u64,
+    usage: Option<ResourceUsage>,
+    /// set once the child has been reaped, its PID may be reused then
+    status: Option<ExitStatus>,
+}
+
+/// a [`Spawner`] set on a [`Command`], two handles are equal if they
+/// share the same spawner
+///
+/// [`Spawner`]: ./trait.Spawner.html
+/// [`Command`]: ./struct.Command.html
+#[derive(Clone)]
+pub(crate) struct SpawnerHandle(Arc<dyn Spawner>);
+
+impl Spawner for OsSpawner {
+    fn spawn(&self, command: &Command) -> io::Result<Spawned> {
+        let mut process = command.process_command();
+        #[cfg(unix)]
+        let report = crate::pre_exec::Report::prepare(command, &mut process)?;
+        let mut child = match process.spawn_async() {
+            Ok(child) => child,
+            #[cfg(unix)]
+            Err(error) => {
+                return Err(match report {
+                    Some(report) => report.error(error),
+                    None => error,
+                })
+            }
+            #[cfg(not(unix))]
+            Err(error) => return Err(error),
+        };
+
+        Ok(Spawned {
+            stdin: child.stdin().take().map(ChildStdin::new),
+            stdout: child.stdout().take().map(ChildStdout::new),
+            stderr: child.stderr().take().map(ChildStderr::new),
+            child: Box::new(OsChild {
+                child,
+                #[cfg(target_os = "linux")]
+                exits_seen: 0,
+                usage: None,
+                status: None,
+            }),
+        })
+    }
+}
+
+impl Child for OsChild {
+    #[inline]
+    fn id(&self) -> u32 {
+        self.child.id()
+    }
+
+    fn poll_exit(&mut self) -> Poll<ExitStatus, io::Error> {
+        if let Some(status) = self.status {
+            return Ok(Async::Ready(status));
+        }
+        let status = futures::try_ready!(self.poll_reap());
+        self.status = Some(status);
+        Ok(Async::Ready(status))
+    }
+
+    fn kill(&mut self) -> io::Result<()> {
+        if self.status.is_some() {
+            // the PID may belong to another process now
+            return Ok(());
+        }
+        self.child.kill()
+    }
+
+    #[cfg(unix)]
+    fn terminate(&mut self) -> io::Result<()> {
+        if self.status.is_some() {
+            return Ok(());
+        }
+        if unsafe { libc::kill(self.id() as libc::pid_t, libc::SIGTERM) } == 0 {
+            Ok(())
+        } else {
+            Err(io::Error::last_os_error())
+        }
+    }
+
+    fn resource_usage(&mut self) -> Option<ResourceUsage> {
+        self.usage
+    }
+}
+
+impl OsChild {
+    #[cfg(target_os = "linux")]
+    fn poll_reap(&mut self) -> Poll<ExitStatus, io::Error> {
+        // the child is only reaped once it is known to have exited, so
+        // its resource usage is always collected
+        loop {
+            let registered = !ChildExits::poll(&mut self.exits_seen)?;
+            match ResourceUsage::of_exited_child(self.id()) {
+                Ok(None) if registered => return Ok(Async::NotReady),
+                Ok(None) => continue,
+                Ok(Some(usage)) => {
+                    self.usage = Some(usage);
+                    return self.child.poll();
+                }
+                // let `tokio_process` report the error
+                Err(_) => return self.child.poll(),
+            }
+        }
+    }
+
+    #[cfg(not(target_os = "linux"))]
+    #[inline]
+    fn poll_reap(&mut self) -> Poll<ExitStatus, io::Error> {
+        self.child.poll()
+    }
+}
+
+/// the `SIGCHLD` notifications, shared by all the children
+///
+/// the signal is registered with a reactor of its own, the children may
+/// be polled from any runtime.
+#[cfg(target_os = "linux")]
+struct ChildExits {
+    signal: Option<(
+        tokio_reactor::Background,
+        FlattenStream<tokio_signal::IoFuture<tokio_signal::unix::Signal>>,
+    )>,
+    /// the number of `SIGCHLD` received
+    count: u64,
+    /// the tasks of the children waiting for the next `SIGCHLD`
+    waiting: Vec<task::Task>,
+}
+
+#[cfg(target_os = "linux")]
+static CHILD_EXITS: Mutex<ChildExits> = Mutex::new(ChildExits {
+    signal: None,
+    count: 0,
+    waiting: Vec::new(),
+});
+
+#[cfg(target_os = "linux")]
+impl ChildExits {
+    /// whether a child exited since `seen` was updated, otherwise the
+    /// current task is notified of the next `SIGCHLD`
+    fn poll(seen: &mut u64) -> io::Result<bool> {
+        let mut exits = CHILD_EXITS
+            .lock()
+            .unwrap_or_else(|error| error.into_inner());
+        if exits.signal.is_none() {
+            let reactor = tokio_reactor::Reactor::new()?.background()?;
+            let signal = tokio_signal::unix::Signal::with_handle(libc::SIGCHLD, reactor.handle());
+            exits.signal = Some((reactor, signal.flatten_stream()));
+        }
+        while let Some((_, signal)) = &mut exits.signal {
+            match signal.poll()? {
+                Async::NotReady => break,
+                Async::Ready(_) => exits.signal_received(),
+            }
+        }
+
+        if *seen != exits.count {
+            *seen = exits.count;
+            return Ok(true);
+        }
+        if !exits.waiting.iter().any(task::Task::will_notify_current) {
+            exits.waiting.push(task::current());
+        }
+        Ok(false)
+    }
+
+    fn signal_received(&mut self) {
+        self.count += 1;
+        for waiting in self.waiting.drain(..) {
+            waiting.notify();
+        }
+    }
+
+    /// the signal stream only notifies the task that polled it last,
+    /// which may be gone: the other children poll it again
+    fn release() {
+        let mut exits = CHILD_EXITS
+            .lock()
+            .unwrap_or_else(|error| error.into_inner());
+        for waiting in exits.waiting.drain(..) {
+            waiting.notify();
+        }
+    }
+}
+
+#[cfg(target_os = "linux")]
+impl Drop for OsChild {
+    fn drop(&mut self) {
+        if self.status.is_none() {
+            ChildExits::release();
+        }
+    }
+}
+
+impl SpawnerHandle {
+    pub(crate) fn new<S: Spawner + 'static>(spawner: S) -> Self {
+        SpawnerHandle(Arc::new(spawner))
+    }
+
+    #[inline]
+    pub(crate) fn spawner(&self) -> &dyn Spawner {
+        self.0.as_ref()
+    }
+}
+
+impl PartialEq for SpawnerHandle {
+    fn eq(&self, other: &Self) -> bool {
+        Arc::ptr_eq(&self.0, &other.0)
+    }
+}
+
+impl Eq for SpawnerHandle {}
+
+impl hash::Hash for SpawnerHandle {
+    fn hash<H: hash::Hasher>(&self, state: &mut H) {
+        (Arc::as_ptr(&self.0) as *const u8).hash(state)
+    }
+}
+
+impl fmt::Debug for SpawnerHandle {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "Spawner({:p})", Arc::as_ptr(&self.0))
+    }
+}
+
+#[cfg(all(test, unix))]
+mod test {
+    use super::*;
+
+    #[test]
+    fn signal_once_exited() -> io::Result<()> {
+        let spawned = OsSpawner.spawn(&Command::new(Program::new("true").unwrap()))?;
+        let mut runtime = tokio::runtime::current_thread::Runtime::new().unwrap();
+        let mut child = spawned.child;
+        let status = runtime.block_on(futures::future::poll_fn(|| child.poll_exit()))?;
+        assert!(status.success());
+
+        // no signal is sent to a PID that may have been reused
+        child.terminate()?;
+        child.kill()?;
+        assert_eq!(child.poll_exit()?, Async::Ready(status));
+        Ok(())
+    }
+}
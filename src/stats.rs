@@ -0,0 +1,257 @@
+use crate::{Command, Control, Error};
+use futures::{try_ready, Async, Poll, Stream};
+use std::{
+    fs, io,
+    path::PathBuf,
+    time::{Duration, Instant},
+};
+use tokio_timer::Interval;
+
+/// a sample of the resources used by a running [`Process`]
+///
+/// read from `/proc/<id>/stat`, `/proc/<id>/status` and `/proc/<id>/fd`.
+///
+/// [`Process`]: ./struct.Process.html
+#[derive(Debug, Clone, Copy, PartialEq)]
+#[non_exhaustive]
+pub struct ProcessStats {
+    /// the Process ID the stats are from
+    pub id: u32,
+    /// time spent executing in user mode so far
+    pub user_time: Duration,
+    /// time spent executing in kernel mode so far
+    pub system_time: Duration,
+    /// CPU usage in percent (of one CPU) since the previous sample
+    ///
+    /// only available from [`SampleStats`].
+    ///
+    /// [`SampleStats`]: ./struct.SampleStats.html
+    pub cpu_usage: Option<f64>,
+    /// resident set size, in bytes
+    pub resident_set_size: u64,
+    /// number of threads
+    pub threads: u64,
+    /// number of opened file descriptors
+    ///
+    /// `None` if they cannot be listed, as for a process running as
+    /// another user.
+    pub open_fds: Option<u64>,
+}
+
+/// stream of periodic [`ProcessStats`] of a running process
+///
+/// created from [`Control::sample_stats`]. The stream ends once the
+/// process has exited.
+///
+/// [`ProcessStats`]: ./struct.ProcessStats.html
+/// [`Control::sample_stats`]: ./trait.Control.html#method.sample_stats
+pub struct SampleStats {
+    command: Command,
+    id: u32,
+    interval: Interval,
+    previous: Option<(Instant, ProcessStats)>,
+}
+
+impl ProcessStats {
+    /// read the current stats of the given process
+    ///
+    /// returns `None` if the process has exited (or is a zombie), even
+    /// while it is read
+    pub(crate) fn read(id: u32) -> io::Result<Option<Self>> {
+        let proc = PathBuf::from(format!("/proc/{}", id));
+
+        let stat = match readable(fs::read_to_string(proc.join("stat")))? {
+            Some(stat) => stat,
+            None => return Ok(None),
+        };
+        // the program name (2nd field) is between parenthesis and may
+        // contain spaces
+        let fields: Vec<&str> = stat
+            .rsplit_once(')')
+            .map(|(_, fields)| fields.split_whitespace().collect())
+            .unwrap_or_default();
+        if fields.first() == Some(&"Z") || fields.first() == Some(&"X") {
+            return Ok(None);
+        }
+        let field = |index: usize| -> io::Result<u64> {
+            fields
+                .get(index)
+                .and_then(|field| field.parse().ok())
+                .ok_or_else(|| invalid_data("stat", &stat))
+        };
+
+        let ticks = clock_ticks();
+        let user_time = Duration::from_secs_f64(field(11)? as f64 / ticks);
+        let system_time = Duration::from_secs_f64(field(12)? as f64 / ticks);
+        let threads = field(17)?;
+
+        let status = match readable(fs::read_to_string(proc.join("status")))? {
+            Some(status) => status,
+            None => return Ok(None),
+        };
+        let resident_set_size = status
+            .lines()
+            .find(|line| line.starts_with("VmRSS:"))
+            .and_then(|line| line.split_whitespace().nth(1))
+            .and_then(|kilobytes| kilobytes.parse::<u64>().ok())
+            .map(|kilobytes| kilobytes * 1024)
+            .unwrap_or(0);
+
+        let open_fds = match fs::read_dir(proc.join("fd")) {
+            Err(error) if error.kind() == io::ErrorKind::PermissionDenied => None,
+            fds => match readable(fds)? {
+                Some(fds) => Some(fds.count() as u64),
+                None => return Ok(None),
+            },
+        };
+
+        Ok(Some(ProcessStats {
+            id,
+            user_time,
+            system_time,
+            cpu_usage: None,
+            resident_set_size,
+            threads,
+            open_fds,
+        }))
+    }
+
+    /// total CPU time spent so far
+    #[inline]
+    pub fn cpu_time(&self) -> Duration {
+        self.user_time + self.system_time
+    }
+}
+
+impl SampleStats {
+    pub(crate) fn new<C: Control>(control: &C, period: Duration) -> Self {
+        SampleStats {
+            command: control.command().clone(),
+            id: control.id(),
+            interval: Interval::new(Instant::now(), period),
+            previous: None,
+        }
+    }
+}
+
+impl Stream for SampleStats {
+    type Item = ProcessStats;
+    type Error = Error;
+
+    fn poll(&mut self) -> Poll<Option<Self::Item>, Self::Error> {
+        let now = match try_ready!(self.interval.poll().map_err(Error::Timer)) {
+            None => return Ok(Async::Ready(None)),
+            Some(now) => now,
+        };
+
+        let mut stats = match ProcessStats::read(self.id).map_err(|source| Error::Stats {
+            command: Box::new(self.command.clone()),
+            id: self.id,
+            source,
+        })? {
+            None => return Ok(Async::Ready(None)),
+            Some(stats) => stats,
+        };
+
+        if let Some((then, previous)) = &self.previous {
+            let elapsed = now.duration_since(*then).as_secs_f64();
+            if elapsed > 0.0 {
+                let cpu = stats.cpu_time().saturating_sub(previous.cpu_time());
+                stats.cpu_usage = Some(cpu.as_secs_f64() / elapsed * 100.0);
+            }
+        }
+        self.previous = Some((now, stats));
+
+        Ok(Async::Ready(Some(stats)))
+    }
+}
+
+/// `None` if the file of `/proc` cannot be read because the process is
+/// gone
+fn readable<T>(result: io::Result<T>) -> io::Result<Option<T>> {
+    match result {
+        Ok(content) => Ok(Some(content)),
+        Err(error)
+            if error.kind() == io::ErrorKind::NotFound
+                || error.raw_os_error() == Some(libc::ESRCH) =>
+        {
+            Ok(None)
+        }
+        Err(error) => Err(error),
+    }
+}
+
+fn clock_ticks() -> f64 {
+    match unsafe { libc::sysconf(libc::_SC_CLK_TCK) } {
+        ticks if ticks > 0 => ticks as f64,
+        _ => 100.0,
+    }
+}
+
+fn invalid_data(file: &str, content: &str) -> io::Error {
+    io::Error::new(
+        io::ErrorKind::InvalidData,
+        format!("unexpected content in {}: '{}'", file, content.trim()),
+    )
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::{Process, Program, Result};
+    use futures::Future as _;
+
+    #[test]
+    fn stats_of_running_process() -> Result<()> {
+        let process = Process::spawn(Command::new(Program::new("cat")?))?;
+
+        let stats = process.stats()?.expect("the process is still running");
+
+        assert_eq!(stats.id, process.id());
+        assert_eq!(stats.threads, 1);
+        assert!(stats.resident_set_size > 0);
+        // the 3 pipes of the standard inputs and outputs
+        assert!(stats.open_fds >= Some(3));
+
+        Ok(())
+    }
+
+    #[test]
+    fn read_while_exiting() -> Result<()> {
+        let program = Program::new("true")?;
+
+        for _ in 0..20 {
+            let mut process = Process::spawn(Command::new(program.clone()))?;
+            let id = process.id();
+            // until the process is reaped, whichever file it exits on
+            while ProcessStats::read(id)?.is_some() {}
+            (&mut process).wait()?;
+            assert_eq!(ProcessStats::read(id)?, None);
+        }
+
+        for error in [libc::ENOENT, libc::ESRCH] {
+            let result: io::Result<()> = Err(io::Error::from_raw_os_error(error));
+            assert_eq!(readable(result)?, None);
+        }
+        let denied: io::Result<()> = Err(io::Error::from_raw_os_error(libc::EACCES));
+        assert!(readable(denied).is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn sample_until_exit() -> Result<()> {
+        let mut cmd = Command::new(Program::new("sleep")?);
+        cmd.argument("0.3");
+        let process = Process::spawn(cmd)?;
+
+        let samples = process.sample_stats(Duration::from_millis(50)).collect();
+        let mut runtime = tokio::runtime::Runtime::new().unwrap();
+        let samples = runtime.block_on(process.join(samples))?.1;
+
+        assert!(samples.len() > 1);
+        assert!(samples[0].cpu_usage.is_none());
+        assert!(samples[1].cpu_usage.is_some());
+
+        Ok(())
+    }
+}
@@ -0,0 +1,138 @@
+use futures::Poll;
+#[cfg(unix)]
+use std::os::unix::io::AsRawFd;
+use std::{
+    fmt,
+    io::{self, Read, Write},
+};
+use tokio_io::{AsyncRead, AsyncWrite};
+
+/// a pipe the output of a child is read from
+///
+/// on top of `AsyncRead`, the pipe is read once more when the child has
+/// exited to collect the last lines printed. See [`OutputPipe::read_remaining`].
+///
+/// [`OutputPipe::read_remaining`]: ./trait.OutputPipe.html#method.read_remaining
+pub trait OutputPipe: AsyncRead + Send {
+    /// read what is left in the pipe without waiting for it to be
+    /// readable, `WouldBlock` if nothing is left for now
+    ///
+    /// by default this is a plain read.
+    fn read_remaining(&mut self, buffer: &mut [u8]) -> io::Result<usize> {
+        self.read(buffer)
+    }
+}
+
+/// the standard input of a spawned [`Process`]
+///
+/// [`Process`]: ./struct.Process.html
+pub struct ChildStdin(Box<dyn AsyncWrite + Send>);
+
+/// the standard output of a spawned [`Process`]
+///
+/// [`Process`]: ./struct.Process.html
+pub struct ChildStdout(Box<dyn OutputPipe>);
+
+/// the standard error output of a spawned [`Process`]
+///
+/// [`Process`]: ./struct.Process.html
+pub struct ChildStderr(Box<dyn OutputPipe>);
+
+impl ChildStdin {
+    pub fn new<W: AsyncWrite + Send + 'static>(stdin: W) -> Self {
+        ChildStdin(Box::new(stdin))
+    }
+}
+
+impl Write for ChildStdin {
+    #[inline]
+    fn write(&mut self, buffer: &[u8]) -> io::Result<usize> {
+        self.0.write(buffer)
+    }
+
+    #[inline]
+    fn flush(&mut self) -> io::Result<()> {
+        self.0.flush()
+    }
+}
+
+impl AsyncWrite for ChildStdin {
+    #[inline]
+    fn shutdown(&mut self) -> Poll<(), io::Error> {
+        self.0.shutdown()
+    }
+}
+
+impl fmt::Debug for ChildStdin {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str("ChildStdin")
+    }
+}
+
+macro_rules! output_pipe {
+    ($pipe:ident) => {
+        impl $pipe {
+            pub fn new<R: OutputPipe + 'static>(pipe: R) -> Self {
+                $pipe(Box::new(pipe))
+            }
+        }
+
+        impl Read for $pipe {
+            #[inline]
+            fn read(&mut self, buffer: &mut [u8]) -> io::Result<usize> {
+                self.0.read(buffer)
+            }
+        }
+
+        impl AsyncRead for $pipe {}
+
+        impl OutputPipe for $pipe {
+            #[inline]
+            fn read_remaining(&mut self, buffer: &mut [u8]) -> io::Result<usize> {
+                self.0.read_remaining(buffer)
+            }
+        }
+
+        impl fmt::Debug for $pipe {
+            fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+                f.write_str(stringify!($pipe))
+            }
+        }
+    };
+}
+
+output_pipe!(ChildStdout);
+output_pipe!(ChildStderr);
+
+/// the reactor may not have notified yet that the pipe is readable:
+/// read from the (non blocking) file descriptor directly so the last
+/// lines printed before exiting are not lost.
+#[cfg(unix)]
+fn read_fd<R: AsRawFd>(pipe: &R, buffer: &mut [u8]) -> io::Result<usize> {
+    let read = unsafe {
+        libc::read(
+            pipe.as_raw_fd(),
+            buffer.as_mut_ptr() as *mut libc::c_void,
+            buffer.len(),
+        )
+    };
+    if read < 0 {
+        Err(io::Error::last_os_error())
+    } else {
+        Ok(read as usize)
+    }
+}
+
+impl OutputPipe for tokio_process::ChildStdout {
+    #[cfg(unix)]
+    fn read_remaining(&mut self, buffer: &mut [u8]) -> io::Result<usize> {
+        read_fd(self, buffer)
+    }
+}
+
+impl OutputPipe for tokio_process::ChildStderr {
+    #[cfg(unix)]
+    fn read_remaining(&mut self, buffer: &mut [u8]) -> io::Result<usize> {
+        read_fd(self, buffer)
+    }
+}
@@ -0,0 +1,509 @@
+use crate::{health::HealthChecker, Command, Control, Error, Health, HealthCheck, Process, Result};
+use futures::{try_ready, Async, Future as _, Poll, Stream};
+use std::{
+    collections::VecDeque,
+    io,
+    process::ExitStatus,
+    time::{Duration, Instant},
+};
+use tokio_timer::Delay;
+
+/// when to restart the supervised process
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum Restart {
+    /// restart the process every time it exits
+    Always,
+    /// restart the process only if it did not exit successfully
+    OnFailure,
+    /// never restart the process
+    Never,
+}
+
+/// restart policy of a [`Supervisor`]
+///
+/// the delay before restarting grows exponentially with the number of
+/// restarts within the window (see [`RestartPolicy::max_restarts`]),
+/// from the initial delay up to the maximum delay of the backoff.
+///
+/// ```
+/// # use bawawa::RestartPolicy;
+/// # use std::time::Duration;
+/// let policy = RestartPolicy::on_failure()
+///     .max_restarts(5, Duration::from_secs(60))
+///     .backoff(Duration::from_millis(100), Duration::from_secs(10));
+/// ```
+///
+/// [`Supervisor`]: ./struct.Supervisor.html
+/// [`RestartPolicy::max_restarts`]: ./struct.RestartPolicy.html#method.max_restarts
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct RestartPolicy {
+    restart: Restart,
+    max_restarts: Option<usize>,
+    window: Duration,
+    initial_delay: Duration,
+    max_delay: Duration,
+}
+
+/// life cycle events of the process managed by a [`Supervisor`]
+///
+/// [`Supervisor`]: ./struct.Supervisor.html
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SupervisorEvent {
+    /// a new incarnation of the process has been started
+    Started { id: u32 },
+    /// the current incarnation exited
+    Exited { id: u32, status: ExitStatus },
+    /// a new incarnation could not be started, this counts as a restart
+    SpawnFailed { kind: io::ErrorKind },
+    /// the process will be restarted after the given delay
+    Restarting { restarts: usize, delay: Duration },
+    /// the process exited too many times, it won't be restarted
+    GaveUp { restarts: usize },
+    /// the health check of the current incarnation failed too many times
+    /// (see [`Supervisor::health_check`])
+    ///
+    /// [`Supervisor::health_check`]: ./struct.Supervisor.html#method.health_check
+    Unhealthy { id: u32, failures: usize },
+}
+
+/// keep a [`Command`] running, restarting it according to a [`RestartPolicy`]
+///
+/// the `Supervisor` is a stream of [`SupervisorEvent`]: it ends once the
+/// process has exited and won't be restarted anymore. Like the [`Process`],
+/// dropping the `Supervisor` terminates the current incarnation.
+///
+/// [`Control`] applies to the current incarnation of the process, calling
+/// [`Control::kill`] also stops the supervision.
+///
+/// The restart delays require a `tokio` runtime with a timer.
+///
+/// [`Command`]: ./struct.Command.html
+/// [`RestartPolicy`]: ./struct.RestartPolicy.html
+/// [`SupervisorEvent`]: ./enum.SupervisorEvent.html
+/// [`Process`]: ./struct.Process.html
+/// [`Control`]: ./trait.Control.html
+/// [`Control::kill`]: ./trait.Control.html#tymethod.kill
+pub struct Supervisor {
+    command: Command,
+    policy: RestartPolicy,
+    state: State,
+    id: u32,
+    restarts: VecDeque<Instant>,
+    events: VecDeque<SupervisorEvent>,
+    stopping: bool,
+    health: Option<HealthChecker>,
+}
+
+enum State {
+    Running(Box<Process>),
+    Waiting(Delay),
+    Stopped,
+}
+
+impl RestartPolicy {
+    pub(crate) const DEFAULT_WINDOW: Duration = Duration::from_secs(60);
+    pub(crate) const DEFAULT_INITIAL_DELAY: Duration = Duration::from_millis(100);
+    pub(crate) const DEFAULT_MAX_DELAY: Duration = Duration::from_secs(30);
+
+    pub fn new(restart: Restart) -> Self {
+        RestartPolicy {
+            restart,
+            max_restarts: None,
+            window: Self::DEFAULT_WINDOW,
+            initial_delay: Self::DEFAULT_INITIAL_DELAY,
+            max_delay: Self::DEFAULT_MAX_DELAY,
+        }
+    }
+
+    #[inline]
+    pub fn always() -> Self {
+        Self::new(Restart::Always)
+    }
+
+    #[inline]
+    pub fn on_failure() -> Self {
+        Self::new(Restart::OnFailure)
+    }
+
+    #[inline]
+    pub fn never() -> Self {
+        Self::new(Restart::Never)
+    }
+
+    /// give up after `max_restarts` restarts within the given `window`
+    pub fn max_restarts(mut self, max_restarts: usize, window: Duration) -> Self {
+        self.max_restarts = Some(max_restarts);
+        self.window = window;
+        self
+    }
+
+    /// set the exponential backoff between restarts
+    pub fn backoff(mut self, initial_delay: Duration, max_delay: Duration) -> Self {
+        self.initial_delay = initial_delay;
+        self.max_delay = max_delay;
+        self
+    }
+
+    /// tell if the process should be restarted after exiting with `status`
+    pub fn should_restart(&self, status: ExitStatus) -> bool {
+        match self.restart {
+            Restart::Always => true,
+            Restart::OnFailure => !status.success(),
+            Restart::Never => false,
+        }
+    }
+
+    /// the delay before the next restart, given the number of recent restarts
+    pub fn delay(&self, restarts: usize) -> Duration {
+        backoff(self.initial_delay, self.max_delay, restarts)
+    }
+}
+
+/// the exponential backoff after `retries` retries: `initial_delay` for
+/// the first one, doubled for every following one up to `max_delay`
+pub(crate) fn backoff(initial_delay: Duration, max_delay: Duration, retries: usize) -> Duration {
+    let factor = 1u32.checked_shl(retries as u32).unwrap_or(u32::MAX);
+    initial_delay
+        .checked_mul(factor)
+        .map_or(max_delay, |delay| delay.min(max_delay))
+}
+
+impl Supervisor {
+    /// spawn the first incarnation of the given [`Command`]
+    ///
+    /// # Error
+    ///
+    /// fails if the first incarnation cannot be spawned.
+    ///
+    /// [`Command`]: ./struct.Command.html
+    pub fn new(command: Command, policy: RestartPolicy) -> Result<Self> {
+        let process = Process::spawn(command.clone())?;
+        let id = process.id();
+
+        Ok(Supervisor {
+            command,
+            policy,
+            state: State::Running(Box::new(process)),
+            id,
+            restarts: VecDeque::new(),
+            events: vec![SupervisorEvent::Started { id }].into(),
+            stopping: false,
+            health: None,
+        })
+    }
+
+    /// check the health of every incarnation of the process
+    ///
+    /// set [`HealthCheck::kill_when_unhealthy`] so an unhealthy incarnation
+    /// is killed and then restarted according to the [`RestartPolicy`].
+    ///
+    /// [`HealthCheck::kill_when_unhealthy`]: ./struct.HealthCheck.html#method.kill_when_unhealthy
+    /// [`RestartPolicy`]: ./struct.RestartPolicy.html
+    pub fn health_check(mut self, check: HealthCheck) -> Self {
+        self.health = Some(HealthChecker::new(check));
+        self
+    }
+
+    /// the current incarnation of the process, if running
+    pub fn process(&self) -> Option<&Process> {
+        match &self.state {
+            State::Running(process) => Some(process),
+            _ => None,
+        }
+    }
+
+    /// the current incarnation of the process, if running, to take its
+    /// standard input and outputs once it has [`Started`]
+    ///
+    /// [`Started`]: ./enum.SupervisorEvent.html#variant.Started
+    pub fn process_mut(&mut self) -> Option<&mut Process> {
+        match &mut self.state {
+            State::Running(process) => Some(process),
+            _ => None,
+        }
+    }
+
+    /// number of restarts within the policy's window
+    fn recent_restarts(&mut self, now: Instant) -> usize {
+        let window = self.policy.window;
+        while let Some(restart) = self.restarts.front() {
+            if now.duration_since(*restart) > window {
+                self.restarts.pop_front();
+            } else {
+                break;
+            }
+        }
+        self.restarts.len()
+    }
+
+    fn exited(&mut self, status: ExitStatus) -> State {
+        self.events.push_back(SupervisorEvent::Exited {
+            id: self.id,
+            status,
+        });
+
+        if self.stopping || !self.policy.should_restart(status) {
+            return State::Stopped;
+        }
+        self.restart()
+    }
+
+    fn spawn_failed(&mut self, error: &Error) -> State {
+        let kind = std::error::Error::source(error)
+            .and_then(|source| source.downcast_ref::<io::Error>())
+            .map_or(io::ErrorKind::Other, |source| source.kind());
+        self.events.push_back(SupervisorEvent::SpawnFailed { kind });
+        self.restart()
+    }
+
+    /// wait before the next restart, unless the policy gives up
+    fn restart(&mut self) -> State {
+        let now = Instant::now();
+        let restarts = self.recent_restarts(now);
+        if self.policy.max_restarts.is_some_and(|max| restarts >= max) {
+            self.events.push_back(SupervisorEvent::GaveUp { restarts });
+            return State::Stopped;
+        }
+
+        let delay = self.policy.delay(restarts);
+        self.restarts.push_back(now);
+        self.events.push_back(SupervisorEvent::Restarting {
+            restarts: restarts + 1,
+            delay,
+        });
+        State::Waiting(Delay::new(now + delay))
+    }
+}
+
+impl Control for Supervisor {
+    #[inline]
+    fn command(&self) -> &Command {
+        &self.command
+    }
+
+    /// the Process ID of the current (or last) incarnation
+    #[inline]
+    fn id(&self) -> u32 {
+        self.id
+    }
+
+    /// kill the current incarnation and stop the supervision
+    fn kill(&mut self) -> Result<()> {
+        self.stopping = true;
+        match &mut self.state {
+            // the stream will still report the exit status
+            State::Running(process) => process.kill(),
+            State::Waiting(_) => {
+                self.state = State::Stopped;
+                Ok(())
+            }
+            State::Stopped => Ok(()),
+        }
+    }
+
+    /// terminate the current incarnation and stop the supervision
+    fn terminate(&mut self) -> Result<()> {
+        self.stopping = true;
+        match &mut self.state {
+            State::Running(process) => process.terminate(),
+            State::Waiting(_) => {
+                self.state = State::Stopped;
+                Ok(())
+            }
+            State::Stopped => Ok(()),
+        }
+    }
+}
+
+impl Stream for Supervisor {
+    type Item = SupervisorEvent;
+    type Error = Error;
+
+    fn poll(&mut self) -> Poll<Option<Self::Item>, Self::Error> {
+        loop {
+            if let Some(event) = self.events.pop_front() {
+                return Ok(Async::Ready(Some(event)));
+            }
+
+            self.state = match &mut self.state {
+                State::Running(process) => {
+                    if let Some(checker) = &mut self.health {
+                        while let Async::Ready(health) = checker.poll_check(process.as_mut())? {
+                            if let Health::Unhealthy { failures, .. } = health {
+                                self.events.push_back(SupervisorEvent::Unhealthy {
+                                    id: self.id,
+                                    failures,
+                                });
+                            }
+                        }
+                    }
+
+                    match process.poll()? {
+                        Async::Ready(status) => self.exited(status),
+                        Async::NotReady if self.events.is_empty() => return Ok(Async::NotReady),
+                        Async::NotReady => continue,
+                    }
+                }
+                State::Waiting(delay) => {
+                    try_ready!(delay.poll().map_err(Error::Timer));
+                    match Process::spawn(self.command.clone()) {
+                        Ok(process) => {
+                            self.id = process.id();
+                            self.events
+                                .push_back(SupervisorEvent::Started { id: self.id });
+                            if let Some(checker) = &mut self.health {
+                                checker.reset();
+                            }
+                            State::Running(Box::new(process))
+                        }
+                        Err(error) => self.spawn_failed(&error),
+                    }
+                }
+                State::Stopped => return Ok(Async::Ready(None)),
+            };
+        }
+    }
+}
+
+#[cfg(all(test, unix))]
+mod test {
+    use super::*;
+    use crate::Program;
+
+    fn events(command: Command, policy: RestartPolicy) -> Result<Vec<SupervisorEvent>> {
+        let supervisor = Supervisor::new(command, policy)?;
+        let mut runtime = tokio::runtime::Runtime::new().unwrap();
+        runtime.block_on(supervisor.collect())
+    }
+
+    #[test]
+    fn give_up_after_max_restarts() -> Result<()> {
+        let policy = RestartPolicy::on_failure()
+            .max_restarts(2, Duration::from_secs(60))
+            .backoff(Duration::from_millis(10), Duration::from_millis(15));
+
+        let events = events(Command::new(Program::new("false")?), policy)?;
+
+        let kinds: Vec<_> = events
+            .iter()
+            .map(|event| match event {
+                SupervisorEvent::Started { .. } => "started",
+                SupervisorEvent::Exited { .. } => "exited",
+                SupervisorEvent::SpawnFailed { .. } => "spawn failed",
+                SupervisorEvent::Restarting { .. } => "restarting",
+                SupervisorEvent::GaveUp { .. } => "gave up",
+                SupervisorEvent::Unhealthy { .. } => "unhealthy",
+            })
+            .collect();
+        assert_eq!(
+            kinds,
+            vec![
+                "started",
+                "exited",
+                "restarting",
+                "started",
+                "exited",
+                "restarting",
+                "started",
+                "exited",
+                "gave up"
+            ]
+        );
+        assert_eq!(
+            events[5],
+            SupervisorEvent::Restarting {
+                restarts: 2,
+                delay: Duration::from_millis(15)
+            }
+        );
+
+        Ok(())
+    }
+
+    #[test]
+    fn give_up_after_failed_spawns() -> Result<()> {
+        use crate::{FakeProcess, FakeSpawner};
+
+        // only the first incarnation can be spawned
+        let spawner = FakeSpawner::new();
+        spawner.expect(FakeProcess::new().exit_code(1));
+        let mut command = Command::new(Program::new_unchecked("server".to_owned()));
+        command.spawner(spawner);
+        let policy = RestartPolicy::on_failure()
+            .max_restarts(2, Duration::from_secs(60))
+            .backoff(Duration::from_millis(10), Duration::from_millis(10));
+
+        let events = events(command, policy)?;
+
+        assert_eq!(events.len(), 7);
+        assert_eq!(
+            events[3],
+            SupervisorEvent::SpawnFailed {
+                kind: io::ErrorKind::NotFound
+            }
+        );
+        assert_eq!(events[5], events[3]);
+        assert_eq!(events[6], SupervisorEvent::GaveUp { restarts: 2 });
+
+        Ok(())
+    }
+
+    #[test]
+    fn no_restart_on_success() -> Result<()> {
+        let events = events(
+            Command::new(Program::new("true")?),
+            RestartPolicy::on_failure(),
+        )?;
+
+        assert_eq!(events.len(), 2);
+        match events[1] {
+            SupervisorEvent::Exited { status, .. } => assert!(status.success()),
+            event => panic!("unexpected event: {:?}", event),
+        }
+
+        Ok(())
+    }
+
+    #[test]
+    fn restart_when_unhealthy() -> Result<()> {
+        let mut cmd = Command::new(Program::new("sleep")?);
+        cmd.argument("10");
+        let check = HealthCheck::probe(|| false)
+            .interval(Duration::from_millis(10))
+            .failure_threshold(1)
+            .kill_when_unhealthy(true);
+        let policy = RestartPolicy::always()
+            .max_restarts(1, Duration::from_secs(60))
+            .backoff(Duration::from_millis(10), Duration::from_millis(10));
+
+        let supervisor = Supervisor::new(cmd, policy)?.health_check(check);
+        let mut runtime = tokio::runtime::Runtime::new().unwrap();
+        let events = runtime.block_on(supervisor.collect())?;
+
+        let unhealthy = events
+            .iter()
+            .filter(|event| match event {
+                SupervisorEvent::Unhealthy { failures, .. } => *failures == 1,
+                _ => false,
+            })
+            .count();
+        assert_eq!(unhealthy, 2);
+        assert_eq!(
+            events.last(),
+            Some(&SupervisorEvent::GaveUp { restarts: 1 })
+        );
+
+        Ok(())
+    }
+
+    #[test]
+    fn backoff_is_exponential() {
+        let policy =
+            RestartPolicy::always().backoff(Duration::from_millis(100), Duration::from_millis(500));
+
+        assert_eq!(policy.delay(0), Duration::from_millis(100));
+        assert_eq!(policy.delay(2), Duration::from_millis(400));
+        assert_eq!(policy.delay(3), Duration::from_millis(500));
+        assert_eq!(policy.delay(64), Duration::from_millis(500));
+    }
+}
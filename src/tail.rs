@@ -0,0 +1,222 @@
+use std::{collections::VecDeque, fmt};
+
+/// settings of the [`OutputTail`] kept by a [`Process`]
+///
+/// by default only the standard error output is drained into the
+/// tail, see [`TailOptions::include_stdout`] to also keep the lines
+/// of the standard output.
+///
+/// [`OutputTail`]: ./struct.OutputTail.html
+/// [`Process`]: ./struct.Process.html
+/// [`TailOptions::include_stdout`]: ./struct.TailOptions.html#method.include_stdout
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct TailOptions {
+    max_lines: usize,
+    max_bytes: usize,
+    stdout: bool,
+}
+
+/// bounded ring buffer of the last lines printed by a [`Process`]
+///
+/// once full, the oldest lines are dropped to make room for the new
+/// ones. The buffer is bounded both in number of lines and in number
+/// of bytes (see [`TailOptions`]).
+///
+/// [`Process`]: ./struct.Process.html
+/// [`TailOptions`]: ./struct.TailOptions.html
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct OutputTail {
+    options: TailOptions,
+    lines: VecDeque<String>,
+    bytes: usize,
+    dropped: usize,
+}
+
+/// display the tail indented below an error message
+pub(crate) struct Report<'a>(&'a OutputTail);
+
+impl TailOptions {
+    /// default maximum number of bytes kept in the tail
+    pub const DEFAULT_MAX_BYTES: usize = 8 * 1024;
+
+    /// keep the last `max_lines` lines of the standard error output
+    pub fn new(max_lines: usize) -> Self {
+        TailOptions {
+            max_lines,
+            max_bytes: Self::DEFAULT_MAX_BYTES,
+            stdout: false,
+        }
+    }
+
+    /// set the maximum number of bytes kept in the tail
+    pub fn max_bytes(mut self, max_bytes: usize) -> Self {
+        self.max_bytes = max_bytes;
+        self
+    }
+
+    /// also drain the standard output into the tail
+    ///
+    /// the lines of both outputs are then interleaved in the order
+    /// they have been read.
+    pub fn include_stdout(mut self, stdout: bool) -> Self {
+        self.stdout = stdout;
+        self
+    }
+
+    /// tell if the standard output is drained into the tail too
+    #[inline]
+    pub fn stdout(&self) -> bool {
+        self.stdout
+    }
+}
+
+impl OutputTail {
+    /// create a new empty tail
+    pub fn new(options: TailOptions) -> Self {
+        OutputTail {
+            options,
+            lines: VecDeque::new(),
+            bytes: 0,
+            dropped: 0,
+        }
+    }
+
+    /// push a new line in the tail, dropping the oldest lines
+    /// if the tail is full
+    pub fn push<S: Into<String>>(&mut self, line: S) {
+        let mut line = line.into();
+        if self.options.max_lines == 0 {
+            self.dropped += 1;
+            return;
+        }
+
+        if line.len() > self.options.max_bytes {
+            let mut end = self.options.max_bytes;
+            while !line.is_char_boundary(end) {
+                end -= 1;
+            }
+            line.truncate(end);
+        }
+
+        self.bytes += line.len();
+        self.lines.push_back(line);
+
+        while self.lines.len() > self.options.max_lines || self.bytes > self.options.max_bytes {
+            if let Some(line) = self.lines.pop_front() {
+                self.bytes -= line.len();
+                self.dropped += 1;
+            }
+        }
+    }
+
+    /// the settings of the tail
+    #[inline]
+    pub fn options(&self) -> &TailOptions {
+        &self.options
+    }
+
+    /// the lines currently kept in the tail, from the oldest to the newest
+    pub fn lines(&self) -> impl Iterator<Item = &str> {
+        self.lines.iter().map(|line| line.as_str())
+    }
+
+    /// number of lines that did not fit in the tail and have been dropped
+    #[inline]
+    pub fn dropped(&self) -> usize {
+        self.dropped
+    }
+
+    #[inline]
+    pub fn is_empty(&self) -> bool {
+        self.lines.is_empty()
+    }
+
+    #[inline]
+    pub(crate) fn report(&self) -> Report<'_> {
+        Report(self)
+    }
+}
+
+impl Default for OutputTail {
+    /// an empty tail not keeping any line
+    fn default() -> Self {
+        OutputTail::new(TailOptions::new(0))
+    }
+}
+
+impl fmt::Display for OutputTail {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        for (index, line) in self.lines().enumerate() {
+            if index != 0 {
+                writeln!(f)?;
+            }
+            f.write_str(line)?;
+        }
+        Ok(())
+    }
+}
+
+impl<'a> fmt::Display for Report<'a> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        if self.0.dropped() != 0 && !self.0.is_empty() {
+            write!(f, "\n  | ... ({} lines omitted)", self.0.dropped())?;
+        }
+        for line in self.0.lines() {
+            write!(f, "\n  | {}", line)?;
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn keep_last_lines() {
+        let mut tail = OutputTail::new(TailOptions::new(2));
+
+        tail.push("one");
+        tail.push("two");
+        tail.push("three");
+
+        assert_eq!(tail.lines().collect::<Vec<_>>(), vec!["two", "three"]);
+        assert_eq!(tail.dropped(), 1);
+        assert_eq!(tail.to_string(), "two\nthree");
+    }
+
+    #[test]
+    fn large_limit() {
+        let mut tail = OutputTail::new(TailOptions::new(usize::MAX));
+
+        tail.push("one");
+
+        assert_eq!(tail.lines().collect::<Vec<_>>(), vec!["one"]);
+    }
+
+    #[test]
+    fn bounded_in_bytes() {
+        let mut tail = OutputTail::new(TailOptions::new(10).max_bytes(8));
+
+        tail.push("1234");
+        tail.push("5678");
+        tail.push("9");
+        tail.push("a very long line");
+
+        assert_eq!(tail.lines().collect::<Vec<_>>(), vec!["a very l"]);
+        assert_eq!(tail.dropped(), 3);
+    }
+
+    #[test]
+    fn report_is_indented() {
+        let mut tail = OutputTail::new(TailOptions::new(1));
+
+        tail.push("warning: unused");
+        tail.push("error: aborting");
+
+        assert_eq!(
+            tail.report().to_string(),
+            "\n  | ... (1 lines omitted)\n  | error: aborting"
+        );
+    }
+}
@@ -0,0 +1,69 @@
+use futures::Future;
+use std::{
+    ops::Deref,
+    path::{Path, PathBuf},
+};
+
+/// a path in the temporary directory, unique to the test process and to
+/// `name`, removed (with its content) when dropped
+pub(crate) struct TempPath(PathBuf);
+
+impl TempPath {
+    /// the path, nothing is created and what was left there is removed
+    pub(crate) fn new(name: &str) -> Self {
+        let path = std::env::temp_dir().join(format!("bawawa-{}-{}", std::process::id(), name));
+        let path = TempPath(path);
+        path.remove();
+        path
+    }
+
+    /// a file with the given content
+    pub(crate) fn file(name: &str, content: &str) -> Self {
+        let path = Self::new(name);
+        std::fs::write(&path, content).unwrap();
+        path
+    }
+
+    /// an empty directory
+    pub(crate) fn dir(name: &str) -> Self {
+        let path = Self::new(name);
+        std::fs::create_dir_all(&path).unwrap();
+        path
+    }
+
+    fn remove(&self) {
+        if self.0.is_dir() {
+            let _ = std::fs::remove_dir_all(&self.0);
+        } else {
+            let _ = std::fs::remove_file(&self.0);
+        }
+    }
+}
+
+impl Deref for TempPath {
+    type Target = Path;
+
+    fn deref(&self) -> &Path {
+        &self.0
+    }
+}
+
+impl AsRef<Path> for TempPath {
+    fn as_ref(&self) -> &Path {
+        &self.0
+    }
+}
+
+impl Drop for TempPath {
+    fn drop(&mut self) {
+        self.remove();
+    }
+}
+
+/// run the future to completion on a runtime of its own, with a timer
+///
+/// the future may borrow from the test, it runs on the current thread.
+pub(crate) fn block_on<F: Future>(future: F) -> Result<F::Item, F::Error> {
+    let mut runtime = tokio::runtime::current_thread::Runtime::new().unwrap();
+    runtime.block_on(future)
+}
@@ -0,0 +1,326 @@
+use crate::{Command, Error, Program, Result};
+use std::{
+    collections::{BTreeMap, BTreeSet},
+    fmt,
+    str::FromStr,
+};
+
+/// a command line with `{name}` placeholders, rendered into a [`Command`]
+/// once the value of every placeholder is known
+///
+/// placeholders can be anywhere in the program or the arguments, `{{`
+/// and `}}` are a literal `{` and `}`. Every value is rendered within
+/// its argument, it is never split into several arguments.
+///
+/// ```
+/// # use bawawa::{CommandTemplate, Error};
+/// let template: CommandTemplate = "rustc {input} -o {output}".parse()?;
+///
+/// let command = template.render(&[("input", "main.rs"), ("output", "my app")])?;
+/// assert_eq!(command.argument_list(), ["main.rs", "-o", "my app"]);
+///
+/// assert!(template.render(&[("input", "main.rs")]).is_err());
+/// # Ok::<(), Error>(())
+/// ```
+///
+/// [`Command`]: ./struct.Command.html
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub struct CommandTemplate {
+    program: Word,
+    arguments: Vec<Word>,
+}
+
+/// a program or an argument of the template
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+struct Word {
+    source: String,
+    parts: Vec<Part>,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+enum Part {
+    Text(String),
+    Variable(String),
+}
+
+impl CommandTemplate {
+    /// create a template from the program and the arguments
+    ///
+    /// unlike [`parse`] the arguments may contain whitespaces.
+    ///
+    /// [`parse`]: #method.parse
+    pub fn new<P, I, S>(program: P, arguments: I) -> Result<Self>
+    where
+        P: AsRef<str>,
+        I: IntoIterator<Item = S>,
+        S: AsRef<str>,
+    {
+        let program = program.as_ref();
+        let arguments: Vec<String> = arguments
+            .into_iter()
+            .map(|argument| argument.as_ref().to_owned())
+            .collect();
+        let parse = |word: &str| {
+            Word::parse(word).map_err(|reason| Error::InvalidTemplate {
+                template: std::iter::once(program)
+                    .chain(arguments.iter().map(String::as_str))
+                    .collect::<Vec<_>>()
+                    .join(" "),
+                reason,
+            })
+        };
+
+        Ok(CommandTemplate {
+            program: parse(program)?,
+            arguments: arguments
+                .iter()
+                .map(|argument| parse(argument))
+                .collect::<Result<_>>()?,
+        })
+    }
+
+    /// parse a command line, the program and the arguments being
+    /// separated by whitespaces
+    pub fn parse(template: &str) -> Result<Self> {
+        let mut words = template.split_whitespace();
+        let program = words.next().ok_or_else(|| Error::InvalidTemplate {
+            template: template.to_owned(),
+            reason: "no program".to_owned(),
+        })?;
+        Self::new(program, words)
+    }
+
+    /// the names of the placeholders of the template
+    pub fn variables(&self) -> BTreeSet<&str> {
+        std::iter::once(&self.program)
+            .chain(self.arguments.iter())
+            .flat_map(|word| word.parts.iter())
+            .filter_map(|part| match part {
+                Part::Variable(name) => Some(name.as_str()),
+                Part::Text(_) => None,
+            })
+            .collect()
+    }
+
+    /// render the [`Command`] with the value of every placeholder
+    ///
+    /// # Error
+    ///
+    /// fails if a placeholder has no value, if a value is given for an
+    /// unknown placeholder or if the rendered program is empty.
+    ///
+    /// the rendered program is not run to check it exists, the
+    /// [`Spawner`] of the command is not known yet: spawning the command
+    /// fails if it does not.
+    ///
+    /// [`Command`]: ./struct.Command.html
+    /// [`Spawner`]: ./trait.Spawner.html
+    pub fn render<I, K, V>(&self, variables: I) -> Result<Command>
+    where
+        I: IntoIterator,
+        I::Item: std::borrow::Borrow<(K, V)>,
+        K: AsRef<str>,
+        V: AsRef<str>,
+    {
+        use std::borrow::Borrow as _;
+
+        let placeholders = self.variables();
+        let mut values = BTreeMap::new();
+        for variable in variables {
+            let (name, value) = variable.borrow();
+            let name = name.as_ref();
+            if !placeholders.contains(name) {
+                return Err(Error::UnknownVariable {
+                    template: self.to_string(),
+                    name: name.to_owned(),
+                });
+            }
+            values.insert(name.to_owned(), value.as_ref().to_owned());
+        }
+        if let Some(name) = placeholders
+            .iter()
+            .find(|name| !values.contains_key(**name))
+        {
+            return Err(Error::MissingVariable {
+                template: self.to_string(),
+                name: (*name).to_owned(),
+            });
+        }
+
+        let program = self.program.render(&values);
+        if program.is_empty() {
+            return Err(Error::InvalidTemplate {
+                template: self.to_string(),
+                reason: "empty program".to_owned(),
+            });
+        }
+        let mut command = Command::new(Program::new_unchecked(program));
+        command.arguments(self.arguments.iter().map(|word| word.render(&values)));
+        Ok(command)
+    }
+}
+
+impl Word {
+    /// parse the placeholders of the word, or tell why it is invalid
+    fn parse(source: &str) -> std::result::Result<Self, String> {
+        let mut parts = Vec::new();
+        let mut text = String::new();
+        let mut chars = source.chars().peekable();
+        while let Some(c) = chars.next() {
+            match c {
+                '{' if chars.peek() == Some(&'{') => {
+                    chars.next();
+                    text.push('{');
+                }
+                '}' if chars.peek() == Some(&'}') => {
+                    chars.next();
+                    text.push('}');
+                }
+                '{' => {
+                    let mut name = String::new();
+                    let mut closed = false;
+                    for c in chars.by_ref() {
+                        if c == '}' {
+                            closed = true;
+                            break;
+                        }
+                        name.push(c);
+                    }
+                    if !closed {
+                        return Err("unclosed '{'".to_owned());
+                    }
+                    if name.is_empty()
+                        || !name
+                            .chars()
+                            .all(|c| c.is_ascii_alphanumeric() || c == '_' || c == '-')
+                    {
+                        return Err(format!("invalid placeholder '{{{}}}'", name));
+                    }
+                    if !text.is_empty() {
+                        parts.push(Part::Text(std::mem::take(&mut text)));
+                    }
+                    parts.push(Part::Variable(name));
+                }
+                '}' => return Err("unmatched '}'".to_owned()),
+                c => text.push(c),
+            }
+        }
+        if !text.is_empty() {
+            parts.push(Part::Text(text));
+        }
+        Ok(Word {
+            source: source.to_owned(),
+            parts,
+        })
+    }
+
+    fn render(&self, values: &BTreeMap<String, String>) -> String {
+        self.parts
+            .iter()
+            .map(|part| match part {
+                Part::Text(text) => text.as_str(),
+                Part::Variable(name) => values[name].as_str(),
+            })
+            .collect()
+    }
+}
+
+impl FromStr for CommandTemplate {
+    type Err = Error;
+
+    fn from_str(template: &str) -> Result<Self> {
+        Self::parse(template)
+    }
+}
+
+impl fmt::Display for CommandTemplate {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str(&self.program.source)?;
+        for argument in self.arguments.iter() {
+            write!(f, " {}", argument.source)?;
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::{FakeProcess, FakeSpawner};
+    use futures::Future as _;
+
+    #[test]
+    fn render_placeholders() -> Result<()> {
+        let template = CommandTemplate::new("rustc", ["--out-dir={dir}/{{bin}}", "{input}"])?;
+        assert_eq!(
+            template.variables().into_iter().collect::<Vec<_>>(),
+            ["dir", "input"]
+        );
+
+        let command = template.render(vec![("input", "main.rs"), ("dir", "target dir")])?;
+        assert_eq!(
+            command.argument_list(),
+            ["--out-dir=target dir/{bin}", "main.rs"]
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn missing_and_unknown_variables() -> Result<()> {
+        let template = CommandTemplate::parse("rustc {input}")?;
+
+        match template.render(&[("input", "main.rs"), ("output", "app")]) {
+            Err(Error::UnknownVariable { name, .. }) => assert_eq!(name, "output"),
+            _ => panic!("the output variable is unknown"),
+        }
+        match template.render(&[] as &[(&str, &str)]) {
+            Err(error @ Error::MissingVariable { .. }) => assert_eq!(
+                error.to_string(),
+                "missing variable 'input' in command template 'rustc {input}'"
+            ),
+            _ => panic!("the input variable is missing"),
+        }
+        Ok(())
+    }
+
+    #[test]
+    fn render_without_running_the_program() -> Result<()> {
+        let spawner = FakeSpawner::new();
+        spawner.expect_program("deploy", FakeProcess::new().stdout("done\n"));
+        let template = CommandTemplate::parse("{tool} --to {target}")?;
+
+        let mut command = template.render(&[("tool", "deploy"), ("target", "staging")])?;
+        assert_eq!(spawner.remaining(), 1);
+        let output = command.spawner(spawner.clone()).spawn()?.output().wait()?;
+        assert_eq!(output.stdout, b"done\n");
+
+        match template.render(&[("tool", ""), ("target", "staging")]) {
+            Err(Error::InvalidTemplate { reason, .. }) => assert_eq!(reason, "empty program"),
+            _ => panic!("the program should not be empty"),
+        }
+        Ok(())
+    }
+
+    #[test]
+    fn invalid_templates() {
+        for template in [
+            "",
+            "rustc {input",
+            "rustc input}",
+            "rustc {}",
+            "rustc {a.b}",
+        ] {
+            match CommandTemplate::parse(template) {
+                Err(Error::InvalidTemplate { .. }) => {}
+                _ => panic!("'{}' should be invalid", template),
+            }
+        }
+
+        match CommandTemplate::parse("rustc --out={a.b}") {
+            Err(Error::InvalidTemplate { reason, .. }) => {
+                assert_eq!(reason, "invalid placeholder '{a.b}'")
+            }
+            _ => panic!("the placeholder should be invalid"),
+        }
+    }
+}
@@ -0,0 +1,199 @@
+use crate::{Command, ResourceUsage};
+use std::process::ExitStatus;
+
+/// trace the life cycle of a [`Process`]: spawned, terminated, killed and
+/// exited (once, however many times the process is polled)
+///
+/// every event is emitted within a `process` span carrying the command
+/// line and the PID. Does nothing unless the `tracing` feature is enabled.
+///
+/// [`Process`]: ./struct.Process.html
+pub(crate) struct Lifecycle {
+    #[cfg(feature = "tracing")]
+    span: tracing::Span,
+    #[cfg(feature = "tracing")]
+    started: std::time::Instant,
+    #[cfg(feature = "tracing")]
+    exited: bool,
+}
+
+#[cfg(feature = "tracing")]
+impl Lifecycle {
+    pub(crate) fn spawning(command: &Command) -> Self {
+        Lifecycle {
+            span: tracing::info_span!(
+                target: "bawawa",
+                "process",
+                command = %command,
+                pid = tracing::field::Empty,
+            ),
+            started: std::time::Instant::now(),
+            exited: false,
+        }
+    }
+
+    pub(crate) fn spawned(&mut self, pid: u32) {
+        self.span.record("pid", pid);
+        self.started = std::time::Instant::now();
+        tracing::debug!(target: "bawawa", parent: &self.span, "spawned");
+    }
+
+    pub(crate) fn cannot_spawn(&self, error: &std::io::Error) {
+        tracing::error!(target: "bawawa", parent: &self.span, %error, "cannot spawn");
+    }
+
+    pub(crate) fn exited(&mut self, status: ExitStatus, usage: Option<ResourceUsage>) {
+        if std::mem::replace(&mut self.exited, true) {
+            return;
+        }
+        let duration = self.started.elapsed();
+        let usage = usage.map(tracing::field::display);
+        if status.success() {
+            tracing::debug!(target: "bawawa", parent: &self.span, ?duration, %status, usage, "exited");
+        } else {
+            tracing::warn!(target: "bawawa", parent: &self.span, ?duration, %status, usage, "exited");
+        }
+    }
+
+    pub(crate) fn wait_failed(&self, error: &std::io::Error) {
+        let duration = self.started.elapsed();
+        tracing::error!(target: "bawawa", parent: &self.span, ?duration, %error, "wait failed");
+    }
+
+    pub(crate) fn killed(&self, result: &std::io::Result<()>) {
+        let duration = self.started.elapsed();
+        match result {
+            Ok(()) => tracing::debug!(target: "bawawa", parent: &self.span, ?duration, "killed"),
+            Err(error) => {
+                tracing::error!(target: "bawawa", parent: &self.span, ?duration, %error, "cannot kill")
+            }
+        }
+    }
+
+    pub(crate) fn terminated(&self, result: &std::io::Result<()>) {
+        let duration = self.started.elapsed();
+        match result {
+            Ok(()) => {
+                tracing::debug!(target: "bawawa", parent: &self.span, ?duration, "terminated")
+            }
+            Err(error) => {
+                tracing::error!(target: "bawawa", parent: &self.span, ?duration, %error, "cannot terminate")
+            }
+        }
+    }
+}
+
+#[cfg(not(feature = "tracing"))]
+impl Lifecycle {
+    #[inline]
+    pub(crate) fn spawning(_: &Command) -> Self {
+        Lifecycle {}
+    }
+
+    #[inline]
+    pub(crate) fn spawned(&mut self, _: u32) {}
+
+    #[inline]
+    pub(crate) fn cannot_spawn(&self, _: &std::io::Error) {}
+
+    #[inline]
+    pub(crate) fn exited(&mut self, _: ExitStatus, _: Option<ResourceUsage>) {}
+
+    #[inline]
+    pub(crate) fn wait_failed(&self, _: &std::io::Error) {}
+
+    #[inline]
+    pub(crate) fn killed(&self, _: &std::io::Result<()>) {}
+
+    #[inline]
+    pub(crate) fn terminated(&self, _: &std::io::Result<()>) {}
+}
+
+#[cfg(all(test, feature = "tracing"))]
+mod test {
+    use crate::{Command, Control as _, Process, Program, Result};
+    use futures::Future as _;
+    use std::{
+        fmt,
+        sync::{Arc, Mutex},
+    };
+    use tracing::{
+        field::{Field, Visit},
+        span, Event, Metadata, Subscriber,
+    };
+
+    /// collect the messages and the fields of the events
+    #[derive(Clone, Default)]
+    struct Events(Arc<Mutex<Vec<String>>>);
+
+    struct Message<'a>(&'a mut String);
+
+    impl<'a> Visit for Message<'a> {
+        fn record_debug(&mut self, field: &Field, value: &dyn fmt::Debug) {
+            self.0.push_str(&format!("{}={:?} ", field.name(), value));
+        }
+    }
+
+    impl Subscriber for Events {
+        fn enabled(&self, _: &Metadata<'_>) -> bool {
+            true
+        }
+        fn new_span(&self, _: &span::Attributes<'_>) -> span::Id {
+            span::Id::from_u64(1)
+        }
+        fn record(&self, _: &span::Id, _: &span::Record<'_>) {}
+        fn record_follows_from(&self, _: &span::Id, _: &span::Id) {}
+        fn event(&self, event: &Event<'_>) {
+            let mut message = String::new();
+            event.record(&mut Message(&mut message));
+            self.0.lock().unwrap().push(message);
+        }
+        fn enter(&self, _: &span::Id) {}
+        fn exit(&self, _: &span::Id) {}
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn spawned_and_killed() -> Result<()> {
+        let events = Events::default();
+        let cat = Program::new("cat")?;
+
+        tracing::subscriber::with_default(events.clone(), || -> Result<()> {
+            let mut process = Process::spawn(Command::new(cat))?;
+            process.kill()?;
+            let _status = process.wait()?;
+            Ok(())
+        })?;
+
+        let events = events.0.lock().unwrap();
+        assert!(events[0].starts_with("message=spawned"));
+        assert!(events[1].starts_with("message=killed"));
+        assert!(events[2].starts_with("message=exited"));
+        assert!(events[2].contains("status=signal: 9"));
+
+        Ok(())
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn terminated_and_exited_once() -> Result<()> {
+        let events = Events::default();
+        let cat = Program::new("cat")?;
+
+        tracing::subscriber::with_default(events.clone(), || -> Result<()> {
+            let mut process = Process::spawn(Command::new(cat))?;
+            process.terminate()?;
+            (&mut process).wait()?;
+            // polled again once it has exited
+            (&mut process).wait()?;
+            Ok(())
+        })?;
+
+        let events = events.0.lock().unwrap();
+        assert_eq!(events.len(), 3);
+        assert!(events[1].starts_with("message=terminated"));
+        assert!(events[2].starts_with("message=exited"));
+
+        Ok(())
+    }
+}
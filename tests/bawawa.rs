@@ -0,0 +1,79 @@
+#![cfg(all(unix, feature = "cli"))]
+
+use std::{
+    path::PathBuf,
+    process::Output,
+    time::{Duration, Instant},
+};
+
+/// run the binary on a Procfile with the given content
+fn bawawa(name: &str, procfile: &str) -> Output {
+    let dir = std::env::temp_dir().join(format!("bawawa-cli-{}-{}", std::process::id(), name));
+    std::fs::create_dir_all(&dir).unwrap();
+    let path: PathBuf = dir.join("Procfile");
+    std::fs::write(&path, procfile).unwrap();
+
+    let output = std::process::Command::new(env!("CARGO_BIN_EXE_bawawa"))
+        .arg(&path)
+        .output()
+        .unwrap();
+    let _ = std::fs::remove_dir_all(&dir);
+    output
+}
+
+fn lines(output: &Output) -> Vec<String> {
+    String::from_utf8_lossy(&output.stdout)
+        .lines()
+        .map(str::to_owned)
+        .collect()
+}
+
+#[test]
+fn prefix_the_lines() {
+    let output = bawawa(
+        "prefix",
+        "one: echo a; echo b >&2\ntwo: sleep 0.1; echo c\n",
+    );
+    assert!(output.status.success());
+
+    let lines = lines(&output);
+    let one: Vec<_> = lines
+        .iter()
+        .filter(|line| line.starts_with("one |"))
+        .collect();
+    assert_eq!(one.len(), 3, "{:?}", lines);
+    assert!(one.contains(&&"one | a".to_owned()));
+    assert!(one.contains(&&"one | b".to_owned()));
+    // the exit is printed after the rest of the output
+    assert_eq!(one[2], "one | exited: exit status: 0");
+    let two: Vec<_> = lines
+        .iter()
+        .filter(|line| line.starts_with("two |"))
+        .collect();
+    assert_eq!(two, ["two | c", "two | exited: exit status: 0"]);
+}
+
+#[test]
+fn stop_on_first_failure() {
+    let start = Instant::now();
+    let output = bawawa("failure", "failing: echo oops; exit 3\nslow: sleep 10\n");
+
+    assert_eq!(output.status.code(), Some(3));
+    assert!(start.elapsed() < Duration::from_secs(5));
+    let lines = lines(&output);
+    assert!(lines.contains(&"failing | oops".to_owned()), "{:?}", lines);
+    assert!(lines.contains(&"failing | exited: exit status: 3".to_owned()));
+}
+
+#[test]
+fn do_not_wait_for_the_background() {
+    let start = Instant::now();
+    let output = bawawa("background", "main: echo done; sleep 3 & sleep 0.2\n");
+
+    assert!(output.status.success());
+    assert!(start.elapsed() < Duration::from_secs(2));
+    assert_eq!(
+        lines(&output),
+        ["main | done", "main | exited: exit status: 0"]
+    );
+}